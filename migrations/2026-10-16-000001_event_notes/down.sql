@@ -0,0 +1,11 @@
+-- SQLite cannot drop columns, so rebuild the table without the note column.
+CREATE TABLE events_without_notes (
+    id INTEGER NOT NULL PRIMARY KEY,
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    event_type TEXT CHECK(event_type IN ('in', 'out', 'note')) NOT NULL,
+    clock DATETIME NOT NULL
+);
+INSERT INTO events_without_notes (id, project_id, event_type, clock)
+    SELECT id, project_id, event_type, clock FROM events;
+DROP TABLE events;
+ALTER TABLE events_without_notes RENAME TO events;
@@ -0,0 +1 @@
+ALTER TABLE projects ADD COLUMN max_session_hours INTEGER NOT NULL DEFAULT 12;
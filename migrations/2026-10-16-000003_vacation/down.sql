@@ -0,0 +1,14 @@
+ALTER TABLE projects DROP COLUMN vacation_accrual_ratio;
+
+-- Rebuild the events table without vacation events or their minutes column.
+CREATE TABLE events_without_vacation (
+    id INTEGER NOT NULL PRIMARY KEY,
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    event_type TEXT CHECK(event_type IN ('in', 'out', 'note')) NOT NULL,
+    clock DATETIME NOT NULL,
+    note TEXT
+);
+INSERT INTO events_without_vacation (id, project_id, event_type, clock, note)
+    SELECT id, project_id, event_type, clock, note FROM events WHERE event_type != 'vacation';
+DROP TABLE events;
+ALTER TABLE events_without_vacation RENAME TO events;
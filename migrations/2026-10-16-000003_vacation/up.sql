@@ -0,0 +1,16 @@
+-- SQLite cannot alter a CHECK constraint, so rebuild the events table to allow vacation events,
+-- along with a column recording how much vacation time was used.
+CREATE TABLE events_with_vacation (
+    id INTEGER NOT NULL PRIMARY KEY,
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    event_type TEXT CHECK(event_type IN ('in', 'out', 'note', 'vacation')) NOT NULL,
+    clock DATETIME NOT NULL,
+    note TEXT,
+    minutes INTEGER
+);
+INSERT INTO events_with_vacation (id, project_id, event_type, clock, note)
+    SELECT id, project_id, event_type, clock, note FROM events;
+DROP TABLE events;
+ALTER TABLE events_with_vacation RENAME TO events;
+
+ALTER TABLE projects ADD COLUMN vacation_accrual_ratio DOUBLE NOT NULL DEFAULT 0;
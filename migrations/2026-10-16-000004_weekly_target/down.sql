@@ -0,0 +1 @@
+ALTER TABLE projects DROP COLUMN weekly_target_minutes;
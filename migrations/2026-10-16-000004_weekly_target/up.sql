@@ -0,0 +1 @@
+ALTER TABLE projects ADD COLUMN weekly_target_minutes INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE projects DROP COLUMN daily_overtime_threshold_minutes;
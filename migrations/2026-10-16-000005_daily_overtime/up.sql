@@ -0,0 +1 @@
+ALTER TABLE projects ADD COLUMN daily_overtime_threshold_minutes INTEGER NOT NULL DEFAULT 0;
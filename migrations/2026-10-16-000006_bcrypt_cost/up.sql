@@ -0,0 +1 @@
+ALTER TABLE config ADD COLUMN bcrypt_cost INTEGER NOT NULL DEFAULT 12;
@@ -0,0 +1 @@
+ALTER TABLE projects ADD COLUMN skip_weekends BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE projects ADD COLUMN overhead_mode TEXT
+    CHECK(overhead_mode IN ('per_session', 'per_day')) NOT NULL DEFAULT 'per_session';
@@ -0,0 +1 @@
+ALTER TABLE projects ADD COLUMN auto_split_hours INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE projects ADD COLUMN week_start TEXT
+    CHECK(week_start IN ('mon', 'tue', 'wed', 'thu', 'fri', 'sat', 'sun')) NOT NULL DEFAULT 'mon';
@@ -0,0 +1 @@
+ALTER TABLE projects ADD COLUMN idle_timeout_minutes INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN admin BOOLEAN NOT NULL DEFAULT 0;
+UPDATE users SET admin = 1 WHERE role = 'admin';
+ALTER TABLE users DROP COLUMN role;
@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN role TEXT
+    CHECK(role IN ('admin', 'worker', 'viewer')) NOT NULL DEFAULT 'worker';
+UPDATE users SET role = 'admin' WHERE admin;
+ALTER TABLE users DROP COLUMN admin;
@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN time_display TEXT
+    CHECK(time_display IN ('both', 'net', 'gross')) NOT NULL DEFAULT 'both';
@@ -0,0 +1 @@
+ALTER TABLE projects ADD COLUMN min_session_seconds INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+CREATE INDEX idx_events_project_clock ON events(project_id, clock);
@@ -0,0 +1 @@
+ALTER TABLE config ADD COLUMN default_overhead INTEGER NOT NULL DEFAULT 15;
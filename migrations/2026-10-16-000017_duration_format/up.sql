@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN duration_format TEXT
+    CHECK(duration_format IN ('hours_minutes', 'decimal_hours')) NOT NULL DEFAULT 'hours_minutes';
@@ -0,0 +1 @@
+ALTER TABLE projects DROP COLUMN day_start_hour;
@@ -0,0 +1,2 @@
+ALTER TABLE projects ADD COLUMN day_start_hour INTEGER
+    CHECK(day_start_hour BETWEEN 0 AND 23) NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE config DROP COLUMN flash_max_age;
@@ -0,0 +1 @@
+ALTER TABLE config ADD COLUMN flash_max_age INTEGER NOT NULL DEFAULT 60;
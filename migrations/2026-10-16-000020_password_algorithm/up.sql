@@ -0,0 +1,2 @@
+ALTER TABLE config ADD COLUMN password_algorithm TEXT
+    CHECK(password_algorithm IN ('bcrypt', 'argon2')) NOT NULL DEFAULT 'bcrypt';
@@ -0,0 +1 @@
+CREATE UNIQUE INDEX idx_users_name_nocase ON users(name COLLATE NOCASE);
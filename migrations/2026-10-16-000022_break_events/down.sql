@@ -0,0 +1,15 @@
+-- Rebuild the events table without break events.
+CREATE TABLE events_without_breaks (
+    id INTEGER NOT NULL PRIMARY KEY,
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    event_type TEXT CHECK(event_type IN ('in', 'out', 'note', 'vacation')) NOT NULL,
+    clock DATETIME NOT NULL,
+    note TEXT,
+    minutes INTEGER
+);
+INSERT INTO events_without_breaks (id, project_id, event_type, clock, note, minutes)
+    SELECT id, project_id, event_type, clock, note, minutes FROM events
+    WHERE event_type != 'break';
+DROP TABLE events;
+ALTER TABLE events_without_breaks RENAME TO events;
+CREATE INDEX idx_events_project_clock ON events(project_id, clock);
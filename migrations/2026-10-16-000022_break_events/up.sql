@@ -0,0 +1,15 @@
+-- SQLite cannot alter a CHECK constraint, so rebuild the events table to allow break events.  Like
+-- vacation events, they give their length in the minutes column.
+CREATE TABLE events_with_breaks (
+    id INTEGER NOT NULL PRIMARY KEY,
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    event_type TEXT CHECK(event_type IN ('in', 'out', 'note', 'vacation', 'break')) NOT NULL,
+    clock DATETIME NOT NULL,
+    note TEXT,
+    minutes INTEGER
+);
+INSERT INTO events_with_breaks (id, project_id, event_type, clock, note, minutes)
+    SELECT id, project_id, event_type, clock, note, minutes FROM events;
+DROP TABLE events;
+ALTER TABLE events_with_breaks RENAME TO events;
+CREATE INDEX idx_events_project_clock ON events(project_id, clock);
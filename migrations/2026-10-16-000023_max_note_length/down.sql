@@ -0,0 +1 @@
+ALTER TABLE config DROP COLUMN max_note_length;
@@ -0,0 +1 @@
+ALTER TABLE config ADD COLUMN max_note_length INTEGER NOT NULL DEFAULT 1000;
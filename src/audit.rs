@@ -0,0 +1,66 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use serde_json;
+
+use time::UtcDateTime;
+
+/// An append-only trail of the changes made to events through the server, kept as one JSON object
+/// per line.  Unlike the events table, entries are never changed or removed, so the trail still
+/// shows what happened after the events themselves have been corrected or deleted.
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+/// The kinds of change recorded in the audit log.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub enum AuditAction {
+    PunchIn,
+    PunchOut,
+    Edit,
+    Delete,
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    time: UtcDateTime,
+    username: &'a str,
+    action: AuditAction,
+    event_id: i64,
+}
+
+impl AuditLog {
+    /// Open the audit log at the given path for appending, creating it if it doesn't exist.
+    pub fn open(path: &str) -> io::Result<AuditLog> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(AuditLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append an entry for a change which has been committed to the database.  The entry is synced
+    /// to disk before returning, so that a crash can't lose it.  The change has already been made,
+    /// so a failure to write is logged rather than returned.
+    pub fn record(&self, username: &str, action: AuditAction, event_id: i64) {
+        let entry = AuditEntry {
+            time: UtcDateTime::now(),
+            username,
+            action,
+            event_id,
+        };
+        let result = serde_json::to_string(&entry)
+            .map_err(io::Error::from)
+            .and_then(|line| {
+                let mut file = self.file.lock().unwrap();
+                writeln!(file, "{}", line)?;
+                file.sync_data()
+            });
+        if let Err(e) = result {
+            error!(
+                "Unable to write to the audit log: {} (lost entry: {:?} of event {} by {})",
+                e, action, event_id, username
+            );
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use actix;
+use actix_web::client::{ClientRequest, ClientResponse, SendRequestError};
+use actix_web::error::JsonPayloadError;
+use actix_web::http::{Cookie, StatusCode};
+use actix_web::{self, HttpMessage};
+use futures::future::{self, Either};
+use futures::Future;
+use serde_json;
+use uuid::Uuid;
+
+use csrf::CSRF_COOKIE_NAME;
+
+use models::PunchDirection;
+use server::{ApiError, ApiPunch, API_PUNCH_PATH, API_REPORT_PATH, LOGIN_PATH};
+
+/// The name of the cookie which holds the session identity after logging in.
+const AUTH_COOKIE_NAME: &str = "auth";
+
+/// The operations which the command-line client can ask the server to perform.
+pub enum ClientCommand {
+    Punch(PunchDirection, Option<String>),
+    Report,
+}
+
+#[derive(Fail, Debug)]
+pub enum ClientError {
+    #[fail(display = "Unable to reach the server: {}", _0)]
+    Send(String),
+    #[fail(display = "Unable to build the request: {}", _0)]
+    Request(String),
+    #[fail(display = "Invalid username and/or password.")]
+    Login,
+    #[fail(display = "{}", _0)]
+    BadState(String),
+    #[fail(display = "Unexpected response from the server ({}): {}", _0, _1)]
+    Server(StatusCode, String),
+}
+impl From<SendRequestError> for ClientError {
+    fn from(e: SendRequestError) -> ClientError {
+        ClientError::Send(format!("{}", e))
+    }
+}
+impl From<actix_web::Error> for ClientError {
+    fn from(e: actix_web::Error) -> ClientError {
+        ClientError::Request(format!("{}", e))
+    }
+}
+impl From<JsonPayloadError> for ClientError {
+    fn from(e: JsonPayloadError) -> ClientError {
+        ClientError::Server(StatusCode::OK, format!("{}", e))
+    }
+}
+
+#[derive(Serialize)]
+struct LoginForm<'a> {
+    username: &'a str,
+    password: &'a str,
+    csrf_token: &'a str,
+}
+
+/// Log in to a running Punch server at the given base URL, and then perform the command.
+pub fn do_client(
+    url: &str,
+    username: &str,
+    password: &str,
+    command: ClientCommand,
+) -> Result<(), ClientError> {
+    let url = url.trim_end_matches('/').to_string();
+    let mut sys = actix::System::new("punch-client");
+    // The login form must carry the same CSRF token as the cookie, so supply both.
+    let csrf_token = Uuid::new_v4().to_string();
+    let login = ClientRequest::post(format!("{}{}", url, LOGIN_PATH))
+        .cookie(Cookie::new(CSRF_COOKIE_NAME, csrf_token.clone()))
+        .form(LoginForm {
+            username,
+            password,
+            csrf_token: &csrf_token,
+        })?;
+    let result = sys.block_on(
+        login
+            .send()
+            .from_err()
+            .and_then(|response| {
+                // A successful login redirects to the dashboard, while a failed login shows the
+                // login page again.
+                match response.cookie(AUTH_COOKIE_NAME) {
+                    Some(ref cookie) if response.status() == StatusCode::FOUND => {
+                        Ok(cookie.clone().into_owned())
+                    }
+                    _ => Err(ClientError::Login),
+                }
+            })
+            .and_then(move |cookie| match command {
+                ClientCommand::Punch(direction, note) => {
+                    Either::A(punch(&url, cookie, direction, note))
+                }
+                ClientCommand::Report => Either::B(report(&url, cookie)),
+            }),
+    );
+    actix::System::current().stop();
+    result
+}
+
+/// Ask the server to punch in or out.
+fn punch(
+    url: &str,
+    cookie: Cookie<'static>,
+    direction: PunchDirection,
+    note: Option<String>,
+) -> Box<dyn Future<Item = (), Error = ClientError>> {
+    let request = ClientRequest::post(format!("{}{}", url, API_PUNCH_PATH))
+        .cookie(cookie)
+        .json(ApiPunch { direction, note });
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+    Box::new(request.send().from_err().and_then(move |response| {
+        match response.status() {
+            StatusCode::NO_CONTENT => {
+                println!("Punched {}.", direction_name(direction));
+                Either::A(future::ok(()))
+            }
+            StatusCode::CONFLICT => Either::B(Either::A(
+                response
+                    .json::<ApiError>()
+                    .from_err()
+                    .and_then(|e| Err(ClientError::BadState(e.error))),
+            )),
+            _ => Either::B(Either::B(unexpected(response))),
+        }
+    }))
+}
+
+/// Fetch the summary report from the server and show it on standard output.
+fn report(url: &str, cookie: Cookie<'static>) -> Box<dyn Future<Item = (), Error = ClientError>> {
+    let request = ClientRequest::get(format!("{}{}", url, API_REPORT_PATH))
+        .cookie(cookie)
+        .finish();
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+    Box::new(
+        request
+            .send()
+            .from_err()
+            .and_then(|response| match response.status() {
+                StatusCode::OK => Either::A(response.json::<serde_json::Value>().from_err().map(
+                    |report| match serde_json::to_string_pretty(&report) {
+                        Ok(s) => println!("{}", s),
+                        Err(e) => eprintln!("Unable to format the report: {}", e),
+                    },
+                )),
+                _ => Either::B(unexpected(response)),
+            }),
+    )
+}
+
+/// Produce an error describing an unexpected response, using the error message provided by the
+/// server if there is one.
+fn unexpected(response: ClientResponse) -> impl Future<Item = (), Error = ClientError> {
+    let status = response.status();
+    response.json::<ApiError>().then(move |result| {
+        let message = match result {
+            Ok(e) => e.error,
+            Err(_) => "no details provided".to_string(),
+        };
+        Err(ClientError::Server(status, message))
+    })
+}
+
+fn direction_name(direction: PunchDirection) -> &'static str {
+    match direction {
+        PunchDirection::In => "in",
+        PunchDirection::Out => "out",
+    }
+}
@@ -0,0 +1,113 @@
+use actix_web;
+use actix_web::http::Cookie;
+use actix_web::middleware::{Middleware, Response, Started};
+use actix_web::{HttpRequest, HttpResponse};
+use cookie::SameSite;
+use uuid::Uuid;
+
+pub static CSRF_COOKIE_NAME: &str = "csrf";
+static CSRF_COOKIE_PATH: &str = "/";
+
+/// Middleware to issue the token which guards forms against cross-site request forgery.  Each
+/// browser session is given a random token in a cookie, and every state-changing form must echo it
+/// back in a hidden `csrf_token` field.  A forged form posted from another site can't read the
+/// cookie, so it can't supply the matching field.  The token is renewed whenever the identity
+/// changes, so a token seen before login is useless afterwards.
+pub struct CsrfService {
+    /// Only send the cookie over HTTPS.
+    secure: bool,
+}
+
+impl CsrfService {
+    pub fn new(secure: bool) -> CsrfService {
+        CsrfService { secure }
+    }
+}
+
+impl<S> Middleware<S> for CsrfService {
+    fn start(&self, request: &HttpRequest<S>) -> actix_web::error::Result<Started> {
+        let token = match request.cookie(CSRF_COOKIE_NAME) {
+            Some(ref cookie) if !cookie.value().is_empty() => Token {
+                value: cookie.value().to_string(),
+                issued: None,
+            },
+            _ => Token::new(None),
+        };
+        request.extensions_mut().insert(token);
+        Ok(Started::Done)
+    }
+
+    fn response(
+        &self,
+        req: &HttpRequest<S>,
+        mut response: HttpResponse,
+    ) -> actix_web::error::Result<Response> {
+        if let Some(token) = req.extensions().get::<Token>() {
+            if let Some(ref issued) = token.issued {
+                let mut cookie = Cookie::new(CSRF_COOKIE_NAME, issued.clone());
+                cookie.set_path(CSRF_COOKIE_PATH);
+                cookie.set_same_site(SameSite::Lax);
+                cookie.set_http_only(true);
+                cookie.set_secure(self.secure);
+                response.add_cookie(&cookie)?;
+            }
+        }
+
+        Ok(Response::Done(response))
+    }
+}
+
+struct Token {
+    /// The token presented by the browser's cookie, if any.
+    value: String,
+    /// A newly issued token which must be sent to the browser.
+    issued: Option<String>,
+}
+
+impl Token {
+    /// Issue a fresh token, keeping the token (if any) which the browser presented with this
+    /// request.
+    fn new(value: Option<String>) -> Token {
+        Token {
+            value: value.unwrap_or_default(),
+            issued: Some(Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+pub trait RequestCsrf {
+    /// The token to embed in forms rendered by this request.
+    fn csrf_token(&self) -> String;
+    /// Return true if a submitted form token matches the browser's cookie.
+    fn verify_csrf_token(&self, submitted: &str) -> bool;
+    /// Issue a new token, for use when the identity of the session changes.
+    fn renew_csrf_token(&self);
+}
+
+impl<S> RequestCsrf for HttpRequest<S> {
+    fn csrf_token(&self) -> String {
+        match self.extensions().get::<Token>() {
+            Some(Token {
+                issued: Some(issued),
+                ..
+            }) => issued.clone(),
+            Some(token) => token.value.clone(),
+            None => String::new(),
+        }
+    }
+
+    fn verify_csrf_token(&self, submitted: &str) -> bool {
+        match self.extensions().get::<Token>() {
+            Some(token) => !token.value.is_empty() && token.value == submitted,
+            None => false,
+        }
+    }
+
+    fn renew_csrf_token(&self) {
+        let value = self
+            .extensions()
+            .get::<Token>()
+            .map(|token| token.value.clone());
+        self.extensions_mut().insert(Token::new(value));
+    }
+}
@@ -1,61 +1,250 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{env, fmt, fs, io};
+
 use actix::prelude::*;
-use bcrypt;
-use chrono;
 use diesel;
+use diesel::dsl::sql;
+use diesel::expression::BoxableExpression;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
-use diesel_migrations;
+use diesel::sql_types::{Bool, Text};
+use diesel::sqlite::Sqlite;
 use r2d2;
 
+use audit::{AuditAction, AuditLog};
+use import::ImportRow;
 use models::{self, PunchDirection};
-use report::SummaryReport;
+use password::{self, PasswordError};
+use report::{
+    day_begins, project_date, DaySession, DayStatus, OrderingProblem, ReportCache, ReportSettings,
+    SummaryReport,
+};
 use schema;
 use time::*;
+use uuid::Uuid;
+
+/// The default size of the web server's pool of database connections.
+pub const DEFAULT_DB_CONNECTIONS: u32 = 3;
 
-const NUM_DB_CONNECTIONS: u32 = 3;
-const NUM_SYNC_THREADS: usize = 3;
+/// The default number of threads which run the web server's database queries.
+pub const DEFAULT_SYNC_THREADS: usize = 3;
 
 // TODO: Use transactions.
 
-// When the bcrypt crate is compiled in debug, it is excruciatingly slow.  So we'll use a cost of 6
-// for development, which is not very secure, but a more reasonable cost of 12 for production.
-#[cfg(debug_assertions)]
-const BCRYPT_COST: u32 = 6;
-#[cfg(not(debug_assertions))]
-const BCRYPT_COST: u32 = 12;
+/// Passwords shorter than this are rejected when changing a password.
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Project names longer than this are rejected when renaming a project.
+const MAX_PROJECT_NAME_LENGTH: usize = 64;
+
+/// Only punches made within this many minutes can be undone.
+const UNDO_WINDOW_MINUTES: i64 = 5;
 
 #[derive(Fail, Debug)]
 pub enum DatabaseError {
     #[fail(display = "Database error: {}", _0)]
     Diesel(diesel::result::Error),
+    #[fail(display = "Database connection error: {}", _0)]
+    Pool(r2d2::Error),
     #[fail(display = "Password error: {}", _0)]
-    Password(bcrypt::BcryptError),
+    Password(PasswordError),
     #[fail(display = "Transaction error: Inconsistent State")]
     BadState,
+    #[fail(display = "Password must be at least {} characters long", _0)]
+    WeakPassword(usize),
     #[fail(display = "Bad time encountered")]
     BadTime,
     #[fail(display = "Project not found")]
     BadProject,
+    #[fail(display = "Event not found")]
+    BadEvent,
+    #[fail(display = "Event would break the in/out ordering of events {}", _0)]
+    OrderingConflict(EventIds),
+    #[fail(
+        display = "Removing the event would leave a dangling interval with events {}",
+        _0
+    )]
+    DanglingInterval(EventIds),
+    #[fail(display = "The bcrypt cost must be between {} and {}", _0, _1)]
+    BadBcryptCost(u32, u32),
+    #[fail(display = "Vacation events must use a positive number of minutes")]
+    BadVacation,
+    #[fail(display = "Break events must use a positive number of minutes")]
+    BadBreak,
+    #[fail(display = "Notes can be at most {} characters long", _0)]
+    NoteTooLong(usize),
+    #[fail(display = "The maximum note length must be at least one character")]
+    BadMaxNoteLength,
+    #[fail(display = "Flash messages can't stay fresh for a negative number of seconds")]
+    BadFlashMaxAge,
+    #[fail(display = "The default overhead can't be a negative number of minutes")]
+    BadDefaultOverhead,
+    #[fail(display = "The event at {} is not on {}", _0, _1)]
+    NotOnDay(::chrono::NaiveDateTime, ::chrono::NaiveDate),
+    #[fail(display = "Only punches can replace a day's punches, not notes")]
+    NoteInReplacement,
+    #[fail(display = "The punch at {} would break the in/out ordering", _0)]
+    ReplacementOrdering(::chrono::NaiveDateTime),
+    #[fail(display = "Row {}: {}", _0, _1)]
+    ImportRow(usize, Box<DatabaseError>),
+    #[fail(
+        display = "The server clock is earlier than the most recent punch at {}",
+        _0
+    )]
+    TimeRegression(UtcDateTime),
+    #[fail(display = "Punches can't be dated in the future")]
+    FuturePunch,
+    #[fail(display = "A user named \"{}\" already exists", _0)]
+    UserExists(String),
+    #[fail(display = "User not found")]
+    BadUser,
+    #[fail(display = "Project names must be between 1 and {} characters long", _0)]
+    BadProjectName(usize),
+    #[fail(display = "A project named \"{}\" already exists", _0)]
+    ProjectExists(String),
+    #[fail(display = "Administrators cannot remove their own admin access")]
+    OwnAdminAccess,
+    #[fail(display = "There is no punch to undo")]
+    NothingToUndo,
+    #[fail(
+        display = "Only punches made within the last {} minutes can be undone",
+        _0
+    )]
+    UndoExpired(i64),
+    #[fail(display = "Invalid test data settings: {}", _0)]
+    BadTestData(String),
+    #[fail(
+        display = "There must be at least one sync thread and at least as many database \
+                   connections as sync threads, not {} connections for {} threads",
+        _0, _1
+    )]
+    BadPoolSize(u32, usize),
+    #[fail(display = "Unable to write the backup: {}", _0)]
+    Backup(io::Error),
+    #[fail(
+        display = "Usernames must differ by more than case before upgrading; rename one of each \
+                   of these users with the sqlite3 shell: {}",
+        _0
+    )]
+    DuplicateUsernames(String),
+    #[fail(display = "Database not initialized; run `punch-web init` first")]
+    NotInitialized,
+    #[fail(display = "Database is already set up.  (One or more admin users exist.)")]
+    AlreadyInitialized,
+}
+impl DatabaseError {
+    /// A stable name for this kind of error, such as "BAD_STATE", which API clients can act on
+    /// without parsing the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DatabaseError::Diesel(..) => "DATABASE",
+            DatabaseError::Pool(..) => "DATABASE_CONNECTION",
+            DatabaseError::Password(..) => "PASSWORD",
+            DatabaseError::BadState => "BAD_STATE",
+            DatabaseError::WeakPassword(..) => "WEAK_PASSWORD",
+            DatabaseError::BadTime => "BAD_TIME",
+            DatabaseError::BadProject => "BAD_PROJECT",
+            DatabaseError::BadEvent => "BAD_EVENT",
+            DatabaseError::OrderingConflict(..) => "ORDERING_CONFLICT",
+            DatabaseError::DanglingInterval(..) => "DANGLING_INTERVAL",
+            DatabaseError::BadBcryptCost(..) => "BAD_BCRYPT_COST",
+            DatabaseError::BadVacation => "BAD_VACATION",
+            DatabaseError::BadBreak => "BAD_BREAK",
+            DatabaseError::NoteTooLong(..) => "NOTE_TOO_LONG",
+            DatabaseError::BadMaxNoteLength => "BAD_MAX_NOTE_LENGTH",
+            DatabaseError::BadFlashMaxAge => "BAD_FLASH_MAX_AGE",
+            DatabaseError::BadDefaultOverhead => "BAD_DEFAULT_OVERHEAD",
+            DatabaseError::NotOnDay(..) => "NOT_ON_DAY",
+            DatabaseError::NoteInReplacement => "NOTE_IN_REPLACEMENT",
+            DatabaseError::ReplacementOrdering(..) => "REPLACEMENT_ORDERING",
+            DatabaseError::ImportRow(..) => "IMPORT_ROW",
+            DatabaseError::TimeRegression(..) => "TIME_REGRESSION",
+            DatabaseError::FuturePunch => "FUTURE_PUNCH",
+            DatabaseError::UserExists(..) => "USER_EXISTS",
+            DatabaseError::BadUser => "BAD_USER",
+            DatabaseError::BadProjectName(..) => "BAD_PROJECT_NAME",
+            DatabaseError::ProjectExists(..) => "PROJECT_EXISTS",
+            DatabaseError::OwnAdminAccess => "OWN_ADMIN_ACCESS",
+            DatabaseError::NothingToUndo => "NOTHING_TO_UNDO",
+            DatabaseError::UndoExpired(..) => "UNDO_EXPIRED",
+            DatabaseError::BadTestData(..) => "BAD_TEST_DATA",
+            DatabaseError::BadPoolSize(..) => "BAD_POOL_SIZE",
+            DatabaseError::Backup(..) => "BACKUP",
+            DatabaseError::DuplicateUsernames(..) => "DUPLICATE_USERNAMES",
+            DatabaseError::NotInitialized => "NOT_INITIALIZED",
+            DatabaseError::AlreadyInitialized => "ALREADY_INITIALIZED",
+        }
+    }
 }
 impl From<diesel::result::Error> for DatabaseError {
     fn from(e: diesel::result::Error) -> DatabaseError {
         DatabaseError::Diesel(e)
     }
 }
-impl From<bcrypt::BcryptError> for DatabaseError {
-    fn from(e: bcrypt::BcryptError) -> DatabaseError {
+impl From<PasswordError> for DatabaseError {
+    fn from(e: PasswordError) -> DatabaseError {
         DatabaseError::Password(e)
     }
 }
 
-/// The sync actor responsible for accessing the database.
-pub struct DbExecutor(pub Pool<ConnectionManager<SqliteConnection>>);
+/// A list of event ids, displayed in a form suitable for error messages.
+#[derive(Debug)]
+pub struct EventIds(pub Vec<i64>);
+impl fmt::Display for EventIds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<String> = self.0.iter().map(|id| id.to_string()).collect();
+        write!(f, "{}", ids.join(", "))
+    }
+}
+
+/// The sync actor responsible for accessing the database.  The report cache is shared by all of the
+/// sync threads, so that a change made on one thread invalidates the reports cached by the others.
+/// Changes to events are also recorded in the audit log, if there is one.
+pub struct DbExecutor(
+    pub Pool<ConnectionManager<SqliteConnection>>,
+    pub Arc<ReportCache>,
+    pub Option<Arc<AuditLog>>,
+);
+
+impl DbExecutor {
+    /// Record a committed change to an event in the audit log, if there is one.
+    fn audit(&self, username: &str, action: AuditAction, event_id: i64) {
+        if let Some(ref audit_log) = self.2 {
+            audit_log.record(username, action, event_id);
+        }
+    }
+}
+
+impl From<PunchDirection> for AuditAction {
+    fn from(direction: PunchDirection) -> AuditAction {
+        match direction {
+            PunchDirection::In => AuditAction::PunchIn,
+            PunchDirection::Out => AuditAction::PunchOut,
+        }
+    }
+}
 
 impl Actor for DbExecutor {
     type Context = SyncContext<Self>;
 }
 
+/// A filter for the user with the given name, without regard to case.  The comparison uses the
+/// NOCASE collation, as the unique index on usernames does, so that the index can serve it.
+fn user_named(
+    username: &str,
+) -> Box<dyn BoxableExpression<schema::users::table, Sqlite, SqlType = Bool>> {
+    Box::new(
+        sql::<Bool>("name = ")
+            .bind::<Text, _>(username.to_string())
+            .sql(" COLLATE NOCASE"),
+    )
+}
+
 /// Retrieve the row id of the last insert.
+#[allow(non_local_definitions)]
 fn last_insert_rowid(connection: &SqliteConnection) -> i64 {
     no_arg_sql_function!(last_insert_rowid, diesel::sql_types::BigInt);
     diesel::select(last_insert_rowid)
@@ -63,7 +252,13 @@ fn last_insert_rowid(connection: &SqliteConnection) -> i64 {
         .unwrap()
 }
 
-/// This customizes Sqlite connections from the R2D2 pool such that foreign keys are enabled.
+/// How long a connection waits for another connection's lock to be released before giving up with
+/// a "database is locked" error.
+const BUSY_TIMEOUT_MILLISECONDS: u32 = 5000;
+
+/// This customizes Sqlite connections from the R2D2 pool such that foreign keys are enabled.  The
+/// database is also put in write-ahead log mode, so that readers don't block the writer, and
+/// connections wait their turn to write instead of failing immediately.
 #[derive(Debug)]
 struct SqliteConnectionCustomizer {}
 
@@ -72,27 +267,54 @@ where
     C: diesel::connection::Connection,
 {
     fn on_acquire(&self, conn: &mut C) -> Result<(), diesel::r2d2::Error> {
-        conn.execute("PRAGMA foreign_keys = ON")
-            .map(|_| ())
-            .map_err(|e| diesel::r2d2::Error::QueryError(e))
+        conn.batch_execute(&format!(
+            "PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL; PRAGMA busy_timeout = {};",
+            BUSY_TIMEOUT_MILLISECONDS
+        ))
+        .map_err(diesel::r2d2::Error::QueryError)
     }
 }
 
-/// Create a pool of connections to the database.
-fn database_pool(
-    database: &str,
-) -> r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>> {
+/// The special database path which keeps the database in memory rather than in a file, which is
+/// useful for tests.
+pub const IN_MEMORY_DATABASE: &str = ":memory:";
+
+/// A pool of connections to the database.
+pub type DbPool = r2d2::Pool<ConnectionManager<SqliteConnection>>;
+
+/// Create a pool of up to the given number of connections to the database.  An in-memory database
+/// lasts only as long as its pool, so the pool should be shared by everything which uses it.
+pub fn database_pool(database: &str, max_size: u32) -> DbPool {
+    // Every connection to ":memory:" would otherwise get its own empty database, so an in-memory
+    // database is given a single shared connection which is never retired while the pool lives.
+    // Each pool names its own database, so that pools don't see each other's data.
+    if database == IN_MEMORY_DATABASE {
+        static NEXT_IN_MEMORY_DATABASE: AtomicUsize = AtomicUsize::new(0);
+        let manager = ConnectionManager::<SqliteConnection>::new(format!(
+            "file:punch-{}?mode=memory&cache=shared",
+            NEXT_IN_MEMORY_DATABASE.fetch_add(1, Ordering::Relaxed)
+        ));
+        return r2d2::Pool::builder()
+            .max_size(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connection_customizer(Box::new(SqliteConnectionCustomizer {}))
+            .build(manager)
+            .expect("Failed to create pool.");
+    }
+
     // Create an R2D2 pool
     let manager = ConnectionManager::<SqliteConnection>::new(database);
     r2d2::Pool::builder()
-        .max_size(NUM_DB_CONNECTIONS)
+        .max_size(max_size)
         .connection_customizer(Box::new(SqliteConnectionCustomizer {}))
         .build(manager)
         .expect("Failed to create pool.")
 }
 
 /// Perform migrations to update the database's schema, if needed.
-fn database_migrate(connection: &impl diesel_migrations::MigrationConnection) {
+fn database_migrate(connection: &SqliteConnection) -> Result<(), DatabaseError> {
+    check_usernames_differ_in_case(connection)?;
     // Allowing unused_imports is only needed to avoid a warning until
     // https://github.com/diesel-rs/diesel/issues/1739
     // is rolled into a stable diesel version.
@@ -101,41 +323,64 @@ fn database_migrate(connection: &impl diesel_migrations::MigrationConnection) {
         embed_migrations!();
         embedded_migrations::run(connection).unwrap();
     }
+    Ok(())
 }
 
-const DEFAULT_OVERHEAD_MINUTES: i32 = 15;
+/// Confirm that the unique index on usernames without regard to case can be built, if it hasn't
+/// been yet.  Older databases may have users such as "Alice" and "alice", which would make the
+/// migration fail partway, so they are named instead so that all but one can be renamed by hand.
+fn check_usernames_differ_in_case(connection: &SqliteConnection) -> Result<(), DatabaseError> {
+    use diesel::sql_types::{BigInt, Nullable};
+
+    let pending = diesel::select(sql::<BigInt>(
+        "(SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'users') - \
+         (SELECT count(*) FROM sqlite_master \
+         WHERE type = 'index' AND name = 'idx_users_name_nocase')",
+    ))
+    .get_result::<i64>(connection)?;
+    if pending <= 0 {
+        return Ok(());
+    }
+    let duplicates = diesel::select(sql::<Nullable<Text>>(
+        "(SELECT group_concat(names, '; ') FROM \
+         (SELECT group_concat(name, ', ') AS names FROM (SELECT name FROM users ORDER BY id) \
+         GROUP BY name COLLATE NOCASE HAVING count(*) > 1))",
+    ))
+    .get_result::<Option<String>>(connection)?;
+    match duplicates {
+        Some(names) => Err(DatabaseError::DuplicateUsernames(names)),
+        None => Ok(()),
+    }
+}
 
 /// Initialize a new punch database.
-pub fn database_setup(database: &str, username: &str, password: &str) -> Result<(), DatabaseError> {
+pub fn database_setup(pool: &DbPool, username: &str, password: &str) -> Result<(), DatabaseError> {
     use self::schema::projects::dsl as projects_dsl;
     use self::schema::users::dsl as users_dsl;
 
-    let pool = database_pool(database);
     let connection = pool.get().unwrap();
-    database_migrate(&connection);
+    database_migrate(&connection)?;
 
     // Is the database already set up?
     let admin_users = users_dsl::users
-        .filter(users_dsl::admin.eq(true))
+        .filter(users_dsl::role.eq(models::Role::Admin))
         .limit(1)
         .load::<models::User>(&connection)?
         .len();
     if admin_users > 0 {
-        panic!("Database is already set up.  (One or more admin users exist.)");
+        return Err(DatabaseError::AlreadyInitialized);
+    }
+    if load_user(&connection, username).optional()?.is_some() {
+        return Err(DatabaseError::UserExists(username.to_string()));
     }
 
     // Create the initial user
-    let hashed_password = match bcrypt::hash(password, BCRYPT_COST) {
-        Ok(h) => h,
-        Err(e) => {
-            error!("{}", e);
-            panic!("Cannot bcrypt-hash password: {}", e);
-        }
-    };
+    let config = load_config(&connection)?;
+    let hashed_password = password::hasher(&config).hash(password)?;
     let new_user = models::NewUser {
         name: username,
         password: Some(&hashed_password),
-        admin: true,
+        role: models::Role::Admin,
     };
     diesel::insert_into(users_dsl::users)
         .values(&new_user)
@@ -144,14 +389,14 @@ pub fn database_setup(database: &str, username: &str, password: &str) -> Result<
     // Fetch the newly created user
     let rowid = last_insert_rowid(&connection);
     let new_user = users_dsl::users
-        .filter(users_dsl::id.eq(rowid as i64))
+        .filter(users_dsl::id.eq(rowid))
         .first::<models::User>(&connection)?;
 
     // Create the initial project
     let new_project = models::NewProject {
         user_id: new_user.id,
         name: "Project",
-        overhead: DEFAULT_OVERHEAD_MINUTES,
+        overhead: config.default_overhead,
     };
     diesel::insert_into(projects_dsl::projects)
         .values(&new_project)
@@ -160,15 +405,67 @@ pub fn database_setup(database: &str, username: &str, password: &str) -> Result<
     Ok(())
 }
 
+/// Settings for generating random test data.
+pub struct TestDataOptions {
+    /// The same seed always generates the same sessions, relative to the current day.  Without a
+    /// seed, a fixed default is used.
+    pub seed: Option<u64>,
+    /// How many days of work history to generate, which is rounded back to a Monday.
+    pub days: i64,
+    pub min_session_minutes: i64,
+    pub max_session_minutes: i64,
+    /// Sessions are added to each working day until it has at least this much time.
+    pub min_minutes_per_day: i64,
+    /// The percent chance of working on any given weekend day.
+    pub weekend_percent: u32,
+    /// Don't list the generated work sessions on standard output.
+    pub quiet: bool,
+}
+
+impl Default for TestDataOptions {
+    fn default() -> TestDataOptions {
+        TestDataOptions {
+            seed: None,
+            days: 38,
+            min_session_minutes: 60,
+            max_session_minutes: 60 * 6,
+            min_minutes_per_day: 60 * 7,
+            weekend_percent: 30,
+            quiet: false,
+        }
+    }
+}
+
+impl TestDataOptions {
+    /// Make sure the settings describe data which can be generated.
+    fn validate(&self) -> Result<(), DatabaseError> {
+        let bad = |message: &str| Err(DatabaseError::BadTestData(message.to_string()));
+        if self.days <= 0 {
+            return bad("the number of days must be positive");
+        }
+        if self.min_session_minutes <= 0 {
+            return bad("the minimum session length must be positive");
+        }
+        if self.min_session_minutes >= self.max_session_minutes {
+            return bad("the minimum session length must be less than the maximum");
+        }
+        if self.weekend_percent > 100 {
+            return bad("the weekend percentage must be at most 100");
+        }
+        Ok(())
+    }
+}
+
 /// Initialize a new punch database, and populate it with random test data.
 pub fn database_setup_test(
-    database: &str,
+    pool: &DbPool,
     username: &str,
     password: &str,
+    options: &TestDataOptions,
 ) -> Result<(), DatabaseError> {
-    database_setup(database, username, password)?;
+    options.validate()?;
+    database_setup(pool, username, password)?;
 
-    let pool = database_pool(database);
     let connection = pool.get().unwrap();
     let user = load_singleton_user(&connection)?;
     let project = load_project_for_user(&connection, user.id)?;
@@ -182,37 +479,42 @@ pub fn database_setup_test(
         0x04, 0xC1, 0x1D, 0xB7, 0x1E, 0xDC, 0x6F, 0x41, 0x74, 0x1B, 0x8C, 0xD7, 0x32, 0x58, 0x34,
         0x99,
     ];
-    let mut rng: XorShiftRng = rand::SeedableRng::from_seed(RNG_SEED);
+    // A given seed replaces the first half of the default seed, so that the seed is never all
+    // zeros, which the xorshift generator can't use.
+    let mut rng_seed = RNG_SEED;
+    if let Some(seed) = options.seed {
+        rng_seed[..8].copy_from_slice(&seed.to_le_bytes());
+    }
+    let mut rng: XorShiftRng = rand::SeedableRng::from_seed(rng_seed);
 
-    const START_DAYS_IN_PAST: i64 = 38;
-    const MIN_SESSION: i64 = 60 * 60;
-    const MAX_SESSION: i64 = 60 * 60 * 6;
-    const MIN_TIME_PER_DAY: i64 = 60 * 60 * 7;
+    let min_session = options.min_session_minutes * 60;
+    let max_session = options.max_session_minutes * 60;
+    let min_time_per_day = options.min_minutes_per_day * 60;
     const MAX_FUZZ_TIME: i64 = 3600;
     let earliest_start_time = NaiveTime::from_num_seconds_from_midnight(60 * 60 * 7, 0); // 7:00am
 
-    // Determine the Monday at or before 38 days ago.
+    // Determine the Monday at or before the requested number of days ago.
     let today = Local::now().naive_local().date();
-    let mut day = today - Duration::days(START_DAYS_IN_PAST);
+    let mut day = today - Duration::days(options.days);
     while day.weekday() != Weekday::Mon {
         day -= Duration::days(1);
     }
 
     while day < today {
-        println!("day: {}", day);
+        if !options.quiet {
+            println!("day: {}", day);
+        }
 
         // Seldom work on weekends.
-        if day.weekday() == Weekday::Sat || day.weekday() == Weekday::Sun {
-            // 30% chance of working
-            if rng.gen_range(0, 100) >= 30 {
-                day += Duration::days(1);
-                continue;
-            }
+        let weekend = day.weekday() == Weekday::Sat || day.weekday() == Weekday::Sun;
+        if weekend && rng.gen_range(0, 100) >= options.weekend_percent {
+            day += Duration::days(1);
+            continue;
         }
 
         let mut time_today: i64 = 0;
         let mut tod = earliest_start_time;
-        while time_today < MIN_TIME_PER_DAY {
+        while time_today < min_time_per_day {
             // Fuzz start time
             let seconds_left_in_day = (NaiveTime::from_hms(23, 59, 59) - tod).num_seconds();
             let max_fuzz_time = MAX_FUZZ_TIME.min(seconds_left_in_day);
@@ -224,11 +526,11 @@ pub fn database_setup_test(
             if seconds_left_in_day < 60 {
                 break;
             }
-            let max_session = MAX_SESSION.min(seconds_left_in_day);
-            if max_session <= MIN_SESSION {
+            let max_session = max_session.min(seconds_left_in_day);
+            if max_session <= min_session {
                 break;
             }
-            let length = rng.gen_range(MIN_SESSION, max_session);
+            let length = rng.gen_range(min_session, max_session);
 
             // Calculate end time
             tod += Duration::seconds(length);
@@ -238,12 +540,16 @@ pub fn database_setup_test(
             let punch_in = NewEvent {
                 project_id: project.id,
                 event_type: EventType::In,
-                clock: to_utc(&NaiveDateTime::new(day, start_time))?,
+                clock: to_utc(&NaiveDateTime::new(day, start_time), Disambiguate::Earlier),
+                note: None,
+                minutes: None,
             };
             let punch_out = NewEvent {
                 project_id: project.id,
                 event_type: EventType::Out,
-                clock: to_utc(&NaiveDateTime::new(day, end_time))?,
+                clock: to_utc(&NaiveDateTime::new(day, end_time), Disambiguate::Earlier),
+                note: None,
+                minutes: None,
             };
 
             // Persist
@@ -255,19 +561,23 @@ pub fn database_setup_test(
                 .values(&punch_out)
                 .execute(&connection)?;
 
-            println!(
-                "\t{} -> {} ({:.2} hours)",
-                start_time,
-                end_time,
-                (length as f64) / 60.0 / 60.0
-            );
+            if !options.quiet {
+                println!(
+                    "\t{} -> {} ({:.2} hours)",
+                    start_time,
+                    end_time,
+                    (length as f64) / 60.0 / 60.0
+                );
+            }
 
             time_today += length;
         }
-        println!(
-            "\tTotal hours for day: {:.2} hours",
-            (time_today as f64) / 60.0 / 60.0
-        );
+        if !options.quiet {
+            println!(
+                "\tTotal hours for day: {:.2} hours",
+                (time_today as f64) / 60.0 / 60.0
+            );
+        }
 
         day += Duration::days(1);
     }
@@ -275,211 +585,2675 @@ pub fn database_setup_test(
     Ok(())
 }
 
-/// Initialize our database sync actor.
+/// Initialize our database sync actor, with the given number of threads sharing a pool of the given
+/// number of connections.  Each thread holds a connection while it runs a query, so the pool must
+/// be at least as large as the number of threads.  Changes to events are recorded in the given
+/// audit log, if any.
 pub fn database_init(
     database: &str,
+    connections: u32,
+    sync_threads: usize,
+    audit_log: Option<AuditLog>,
 ) -> Result<(actix::Addr<DbExecutor>, models::Config), DatabaseError> {
-    let pool = database_pool(database);
+    if sync_threads == 0 || (connections as usize) < sync_threads {
+        return Err(DatabaseError::BadPoolSize(connections, sync_threads));
+    }
+    let pool = database_pool(database, connections);
     let connection = pool.get().unwrap();
-    database_migrate(&connection);
+    database_migrate(&connection)?;
+
+    // A database which was never set up with "init" has no user or project to punch for.
+    let user = match load_singleton_user(&connection) {
+        Err(DatabaseError::Diesel(diesel::result::Error::NotFound)) => {
+            return Err(DatabaseError::NotInitialized)
+        }
+        result => result?,
+    };
+    match load_project_for_user(&connection, user.id) {
+        Err(DatabaseError::Diesel(diesel::result::Error::NotFound)) => {
+            return Err(DatabaseError::NotInitialized)
+        }
+        result => result?,
+    };
+
     let config = load_config(&connection)?;
+    let report_cache = Arc::new(ReportCache::new());
+    let audit_log = audit_log.map(Arc::new);
     Ok((
-        SyncArbiter::start(NUM_SYNC_THREADS, move || DbExecutor(pool.clone())),
+        SyncArbiter::start(sync_threads, move || {
+            DbExecutor(pool.clone(), report_cache.clone(), audit_log.clone())
+        }),
         config,
     ))
 }
 
-/// Generate a summary report.  This function opens a fresh database connection, and is meant to be
-/// used when generating a text report via the "report" command-line argument.
-pub fn do_report(database: &str) -> Result<SummaryReport, DatabaseError> {
-    let pool = database_pool(database);
+/// Generate a summary report.  This function takes a connection from the given pool, and is meant
+/// to be used when generating a text report via the "report" command-line argument.
+pub fn do_report(
+    pool: &DbPool,
+    weeks_in_past: u32,
+    max_events: usize,
+) -> Result<SummaryReport, DatabaseError> {
     let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
     let user = load_singleton_user(&connection)?;
     let project = load_project_for_user(&connection, user.id)?;
-    ::report::summary_report(&connection, project.id)
+    let settings = ReportSettings {
+        weeks_in_past,
+        max_events,
+        ..ReportSettings::from_project(&project)
+    };
+    ::report::summary_report(&connection, &project, &settings)
 }
 
-//////////////////////////////////////////////////////////////////////
-// AuthenticateUser
-//////////////////////////////////////////////////////////////////////
+/// Produce a plain-text digest of the week the given number of weeks ago.  This function takes a
+/// connection from the given pool, and is meant to be used by the "digest" command-line argument.
+pub fn do_digest(pool: &DbPool, weeks_ago: u32) -> Result<String, DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    let project = load_project_for_user(&connection, user.id)?;
+    ::report::weekly_digest(&connection, project.id, weeks_ago)
+}
 
-pub struct AuthenticateUser {
-    pub username: String,
-    pub password: String,
+/// Determine whether the user is punched in, and how much time has been logged today.  This
+/// function takes a connection from the given pool, and is meant to be used by the "status"
+/// command-line argument.
+pub fn do_status(pool: &DbPool) -> Result<DayStatus, DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    let project = load_project_for_user(&connection, user.id)?;
+    ::report::day_status(&connection, project.id)
 }
-impl Message for AuthenticateUser {
-    type Result = Result<bool, DatabaseError>;
+
+/// Check the event log for punches which break the in/out ordering.  This function takes a
+/// connection from the given pool, and is meant to be used by the "check" command-line argument.
+pub fn do_check(pool: &DbPool) -> Result<(usize, Vec<OrderingProblem>), DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    let project = load_project_for_user(&connection, user.id)?;
+    ::report::check_events(&connection, project.id)
 }
-impl Handler<AuthenticateUser> for DbExecutor {
-    type Result = Result<bool, DatabaseError>;
 
-    fn handle(&mut self, msg: AuthenticateUser, _: &mut Self::Context) -> Self::Result {
-        use self::schema::users::dsl::*;
-        let conn: &SqliteConnection = &self.0.get().unwrap();
+/// Manually add an event at a specific time, such as a punch that was forgotten at the time.  This
+/// function takes a connection from the given pool, and is meant to be used by the "add-event"
+/// command-line argument.  Returns the id of the new event.
+pub fn add_event(
+    pool: &DbPool,
+    event_type: models::EventType,
+    clock: UtcDateTime,
+    note: Option<String>,
+    minutes: Option<i32>,
+) -> Result<i64, DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    let project = load_project_for_user(&connection, user.id)?;
+    connection.transaction(|| {
+        insert_event_checked(&connection, project.id, event_type, clock, note, minutes)
+    })
+}
 
-        let user = users
-            .filter(name.eq(msg.username))
-            .first::<models::User>(conn)?;
-        match user.password {
-            Some(p) => Ok(bcrypt::verify(&msg.password, &p)?),
-            None => Ok(false),
+/// Import events in chronological order within a single transaction, so that the first row which
+/// would break the in/out ordering rolls back the entire import.  With `dry_run`, the events are
+/// validated but the transaction is always rolled back.  This function takes a connection from the
+/// given pool, and is meant to be used by the "import" command-line argument.  Returns the number
+/// of events imported (or that would have been imported).
+pub fn import_events(
+    pool: &DbPool,
+    mut rows: Vec<ImportRow>,
+    dry_run: bool,
+) -> Result<usize, DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    let project = load_project_for_user(&connection, user.id)?;
+    rows.sort_by_key(|r| r.clock);
+    let count = rows.len();
+    let result = connection.transaction(|| {
+        for r in rows {
+            let row = r.row;
+            insert_event_checked(&connection, project.id, r.event_type, r.clock, r.note, None)
+                .map_err(|e| DatabaseError::ImportRow(row, Box::new(e)))?;
         }
+        if dry_run {
+            Err(diesel::result::Error::RollbackTransaction.into())
+        } else {
+            Ok(())
+        }
+    });
+    match result {
+        Ok(()) | Err(DatabaseError::Diesel(diesel::result::Error::RollbackTransaction)) => {
+            Ok(count)
+        }
+        Err(e) => Err(e),
     }
 }
 
-//////////////////////////////////////////////////////////////////////
-// GetConfig
-//////////////////////////////////////////////////////////////////////
+/// A punch-in with no matching punch-out, along with the punch-out planned to close it.
+#[derive(Debug)]
+pub struct DanglingPunch {
+    pub punch_in: models::Event,
+    /// The time of the planned punch-out, or None if the requested closing time doesn't fall
+    /// between the punch-in and the next punch.
+    pub close_at: Option<UtcDateTime>,
+}
 
-fn load_config(connection: &SqliteConnection) -> Result<models::Config, DatabaseError> {
-    use self::schema::config::dsl::*;
-    use models::{Config, ConfigRow};
+/// The note attached to the punch-outs added by the "repair" command, so that they can be told
+/// apart from real punches.
+const REPAIR_NOTE: &str = "Added by repair";
 
-    let row = config.first::<ConfigRow>(connection).optional()?;
-    match row {
-        Some(ref row) => {
-            let c = match Config::parse_row(row) {
-                Ok(c) => c,
-                Err(_) => {
-                    panic!("Cannot parse configuration");
-                }
-            };
-            Ok(c)
+/// Find the punch-ins with no matching punch-out, which are those followed by another punch-in, and
+/// a final punch-in from before today.  Each is planned to close at the given time of day on the
+/// day it began.  With `apply`, the planned punch-outs are added within a single transaction;
+/// otherwise nothing is changed.  This function takes a connection from the given pool, and is
+/// meant to be used by the "repair" command-line argument.
+pub fn repair_dangling_punches(
+    pool: &DbPool,
+    close_at: ::chrono::NaiveTime,
+    apply: bool,
+) -> Result<Vec<DanglingPunch>, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    let project = load_project_for_user(&connection, user.id)?;
+    connection.transaction(|| {
+        let punches = events_dsl::events
+            .filter(events_dsl::project_id.eq(project.id))
+            .filter(
+                events_dsl::event_type
+                    .eq(models::EventType::In)
+                    .or(events_dsl::event_type.eq(models::EventType::Out)),
+            )
+            .order(events_dsl::clock)
+            .load::<models::Event>(&connection)?;
+
+        let now = ::chrono::Local::now().naive_local();
+        let mut dangling = Vec::new();
+        for (i, punch_in) in punches.iter().enumerate() {
+            if punch_in.event_type != models::EventType::In {
+                continue;
+            }
+            let start = to_local(&punch_in.clock);
+            let next = punches.get(i + 1);
+            match next {
+                Some(event) if event.event_type == models::EventType::Out => continue,
+                // The last punch-in is only dangling if it is left over from an earlier day.
+                None if start.date() == now.date() => continue,
+                _ => {}
+            }
+            let close = start.date().and_time(close_at);
+            let limit = next.map(|event| to_local(&event.clock)).unwrap_or(now);
+            dangling.push(DanglingPunch {
+                punch_in: punch_in.clone(),
+                close_at: if close > start && close < limit {
+                    Some(to_utc(&close, Disambiguate::Earlier))
+                } else {
+                    None
+                },
+            });
         }
-        None => {
-            // No config row present -- create a new one.
-            let row = ConfigRow::new();
-            diesel::insert_into(config)
-                .values(&row)
-                .execute(connection)?;
-            Ok(Config::parse_row(&row).unwrap())
+
+        if apply {
+            for clock in dangling.iter().filter_map(|d| d.close_at) {
+                insert_event_checked(
+                    &connection,
+                    project.id,
+                    models::EventType::Out,
+                    clock,
+                    Some(REPAIR_NOTE.to_string()),
+                    None,
+                )?;
+            }
         }
-    }
+        Ok(dangling)
+    })
 }
 
-pub struct GetConfig {}
-impl Message for GetConfig {
-    type Result = Result<models::Config, DatabaseError>;
+/// Change the time and/or note of an existing event.  This function takes a connection from the
+/// given pool, and is meant to be used by the "edit-event" command-line argument.
+pub fn edit_event(
+    pool: &DbPool,
+    event_id: i64,
+    new_clock: Option<UtcDateTime>,
+    new_note: Option<String>,
+) -> Result<(), DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    let project = load_project_for_user(&connection, user.id)?;
+    connection
+        .transaction(|| edit_event_checked(&connection, project.id, event_id, new_clock, new_note))
 }
-impl Handler<GetConfig> for DbExecutor {
-    type Result = Result<models::Config, DatabaseError>;
 
-    fn handle(&mut self, _: GetConfig, _: &mut Self::Context) -> Self::Result {
-        let conn: &SqliteConnection = &self.0.get().unwrap();
-        load_config(conn)
-    }
+/// Delete an existing event.  This function takes a connection from the given pool, and is meant to
+/// be used by the "delete-event" command-line argument.
+pub fn delete_event(pool: &DbPool, event_id: i64, force: bool) -> Result<(), DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    let project = load_project_for_user(&connection, user.id)?;
+    connection.transaction(|| delete_event_checked(&connection, project.id, event_id, force))
 }
 
-//////////////////////////////////////////////////////////////////////
-// PunchCommand
-//////////////////////////////////////////////////////////////////////
-
-pub struct PunchCommand {
-    // project_id: String,
-    pub username: String,
-    pub direction: PunchDirection,
-    pub note: Option<String>,
-}
-impl Message for PunchCommand {
-    type Result = Result<(), DatabaseError>;
-}
+/// Delete all of the singleton project's events, leaving the users, projects, and configuration as
+/// they are.  This function takes a connection from the given pool, and is meant to be used by the
+/// "reset-events" command-line argument.  Returns the number of events deleted.
+pub fn reset_events(pool: &DbPool) -> Result<usize, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
 
-/// This will load the sole user.  Some day we should support multiple users.
-fn load_singleton_user(connection: &SqliteConnection) -> Result<models::User, DatabaseError> {
-    use self::schema::users::dsl as users_dsl;
-    users_dsl::users
-        .order(users_dsl::id)
-        .first::<models::User>(connection)
-        .map_err(|e| e.into())
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    connection.transaction(|| {
+        let project = load_project_for_user(&connection, user.id)?;
+        Ok(
+            diesel::delete(events_dsl::events.filter(events_dsl::project_id.eq(project.id)))
+                .execute(&connection)?,
+        )
+    })
 }
 
-/// This will load the user's sole project.  Some day we should support multiple projects per user.
-fn load_project_for_user(
-    connection: &SqliteConnection,
-    user_id: i64,
+/// Apply any requested changes to the settings of the singleton project, and return the resulting
+/// project.  This function takes a connection from the given pool, and is meant to be used by the
+/// "configure" command-line argument.
+pub fn configure_project(
+    pool: &DbPool,
+    changes: &models::ProjectChanges,
 ) -> Result<models::Project, DatabaseError> {
     use self::schema::projects::dsl as projects_dsl;
-    projects_dsl::projects
-        .filter(projects_dsl::user_id.eq(user_id))
-        .order(projects_dsl::id)
-        .first::<models::Project>(connection)
-        .map_err(|e| e.into())
+
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    connection.transaction(|| {
+        let project = load_project_for_user(&connection, user.id)?;
+        if changes.is_empty() {
+            return Ok(project);
+        }
+        diesel::update(projects_dsl::projects.filter(projects_dsl::id.eq(project.id)))
+            .set(changes)
+            .execute(&connection)?;
+        load_project_for_user(&connection, user.id)
+    })
 }
 
-/// Determine the next expected punch direction, based on whether the previous punch direction was
-/// in, out, or non-existent.
-pub fn next_expected_punch_direction(
-    connection: &SqliteConnection,
-    project_id: i64,
-) -> Result<PunchDirection, DatabaseError> {
-    use self::schema::events::dsl as events_dsl;
-    let last_event = events_dsl::events
-        .filter(events_dsl::project_id.eq(project_id))
-        .filter(
-            events_dsl::event_type
-                .eq(models::EventType::In)
-                .or(events_dsl::event_type.eq(models::EventType::Out)),
-        )
-        .order(events_dsl::clock.desc())
-        .first::<models::Event>(connection)
-        .optional()?;
-    let next_direction = match &last_event.map(|e| e.event_type) {
-        Some(models::EventType::In) => PunchDirection::Out,
-        Some(models::EventType::Out) => PunchDirection::In,
-        Some(models::EventType::Note) => unreachable!(),
-        None => PunchDirection::In,
-    };
-    Ok(next_direction)
+/// Rename the singleton project, and return the resulting project.  This function takes a
+/// connection from the given pool, and is meant to be used by the "rename-project" command-line
+/// argument.
+pub fn rename_project(pool: &DbPool, new_name: &str) -> Result<models::Project, DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let user = load_singleton_user(&connection)?;
+    connection.transaction(|| {
+        let project = load_project_for_user(&connection, user.id)?;
+        rename_project_checked(&connection, project.id, new_name)?;
+        load_project_for_user(&connection, user.id)
+    })
 }
 
-impl Handler<PunchCommand> for DbExecutor {
-    type Result = Result<(), DatabaseError>;
+/// Change the bcrypt cost used when hashing new passwords, or just report the current cost if no
+/// new cost is given.  This function takes a connection from the given pool, and is meant to be
+/// used by the "configure" command-line argument.
+pub fn configure_bcrypt_cost(pool: &DbPool, new_cost: Option<u32>) -> Result<u32, DatabaseError> {
+    use self::schema::config::dsl as config_dsl;
 
-    fn handle(&mut self, msg: PunchCommand, _: &mut Self::Context) -> Self::Result {
-        use self::schema::events::dsl as events_dsl;
-        use self::schema::users::dsl as users_dsl;
-        let connection: &SqliteConnection = &self.0.get().unwrap();
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let config = load_config(&connection)?;
+    match new_cost {
+        Some(cost) => {
+            if !(models::MIN_BCRYPT_COST..=models::MAX_BCRYPT_COST).contains(&cost) {
+                return Err(DatabaseError::BadBcryptCost(
+                    models::MIN_BCRYPT_COST,
+                    models::MAX_BCRYPT_COST,
+                ));
+            }
+            diesel::update(config_dsl::config)
+                .set(config_dsl::bcrypt_cost.eq(cost as i32))
+                .execute(&connection)?;
+            Ok(cost)
+        }
+        None => Ok(config.bcrypt_cost),
+    }
+}
 
-        // Load the user and project
-        let user = users_dsl::users
-            .filter(users_dsl::name.eq(msg.username))
-            .first::<models::User>(connection)?;
-        let project = load_project_for_user(connection, user.id)?;
+/// Change the algorithm used when hashing new passwords, or just report the current algorithm if no
+/// new one is given.  This function takes a connection from the given pool, and is meant to be used
+/// by the "configure" command-line argument.
+pub fn configure_password_algorithm(
+    pool: &DbPool,
+    new_algorithm: Option<models::PasswordAlgorithm>,
+) -> Result<models::PasswordAlgorithm, DatabaseError> {
+    use self::schema::config::dsl as config_dsl;
 
-        // Confirm that this punch is consistent with the most recent punch.
-        if msg.direction != next_expected_punch_direction(connection, project.id)? {
-            return Err(DatabaseError::BadState);
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let config = load_config(&connection)?;
+    match new_algorithm {
+        Some(algorithm) => {
+            diesel::update(config_dsl::config)
+                .set(config_dsl::password_algorithm.eq(algorithm))
+                .execute(&connection)?;
+            Ok(algorithm)
         }
+        None => Ok(config.password_algorithm),
+    }
+}
 
-        // Create the punch event
-        let new_event = models::NewEvent {
-            project_id: project.id,
-            event_type: msg.direction.into(),
-            clock: chrono::offset::Utc::now().naive_utc(),
-        };
-        diesel::insert_into(events_dsl::events)
-            .values(&new_event)
-            .execute(connection)?;
+/// Change the overhead given to new projects, or just report the current overhead if no new one is
+/// given.  This function takes a connection from the given pool, and is meant to be used by the
+/// "set-config" command-line argument.
+pub fn configure_default_overhead(
+    pool: &DbPool,
+    new_overhead: Option<i32>,
+) -> Result<i32, DatabaseError> {
+    use self::schema::config::dsl as config_dsl;
 
-        Ok(())
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let config = load_config(&connection)?;
+    match new_overhead {
+        Some(overhead) if overhead < 0 => Err(DatabaseError::BadDefaultOverhead),
+        Some(overhead) => {
+            diesel::update(config_dsl::config)
+                .set(config_dsl::default_overhead.eq(overhead))
+                .execute(&connection)?;
+            Ok(overhead)
+        }
+        None => Ok(config.default_overhead),
     }
 }
 
-//////////////////////////////////////////////////////////////////////
-// GetReport
-//////////////////////////////////////////////////////////////////////
+/// Change how long flash messages stay fresh, or just report the current window if no new one is
+/// given.  This function takes a connection from the given pool, and is meant to be used by the
+/// "set-config" command-line argument.
+pub fn configure_flash_max_age(
+    pool: &DbPool,
+    new_max_age: Option<i32>,
+) -> Result<i32, DatabaseError> {
+    use self::schema::config::dsl as config_dsl;
 
-pub struct GetSummaryReport {}
-impl Message for GetSummaryReport {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let config = load_config(&connection)?;
+    match new_max_age {
+        Some(max_age) if max_age < 0 => Err(DatabaseError::BadFlashMaxAge),
+        Some(max_age) => {
+            diesel::update(config_dsl::config)
+                .set(config_dsl::flash_max_age.eq(max_age))
+                .execute(&connection)?;
+            Ok(max_age)
+        }
+        None => Ok(config.flash_max_age as i32),
+    }
+}
+
+/// Change the longest note which may be attached to an event, or just report the current maximum if
+/// no new one is given.  This function takes a connection from the given pool, and is meant to be
+/// used by the "set-config" command-line argument.
+pub fn configure_max_note_length(
+    pool: &DbPool,
+    new_max_length: Option<i32>,
+) -> Result<i32, DatabaseError> {
+    use self::schema::config::dsl as config_dsl;
+
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    let config = load_config(&connection)?;
+    match new_max_length {
+        Some(max_length) if max_length < 1 => Err(DatabaseError::BadMaxNoteLength),
+        Some(max_length) => {
+            diesel::update(config_dsl::config)
+                .set(config_dsl::max_note_length.eq(max_length))
+                .execute(&connection)?;
+            Ok(max_length)
+        }
+        None => Ok(config.max_note_length as i32),
+    }
+}
+
+/// Change a user's password.  This function takes a connection from the given pool, and is meant to
+/// be used by the "passwd" command-line argument.
+pub fn change_password(
+    pool: &DbPool,
+    username: &str,
+    new_password: &str,
+) -> Result<(), DatabaseError> {
+    let connection = pool.get().unwrap();
+    database_migrate(&connection)?;
+    set_password(&connection, username, new_password)
+}
+
+//////////////////////////////////////////////////////////////////////
+// Ping
+//////////////////////////////////////////////////////////////////////
+
+/// Confirm that a database connection can be obtained and used, for health checks.
+pub struct Ping {}
+impl Message for Ping {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<Ping> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, _: Ping, _: &mut Self::Context) -> Self::Result {
+        let connection = self.0.get().map_err(DatabaseError::Pool)?;
+        diesel::sql_query("SELECT 1").execute(&*connection)?;
+        Ok(())
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// BackupDatabase
+//////////////////////////////////////////////////////////////////////
+
+/// Create a directory under the system's temporary directory, with a random name and, on Unix,
+/// access for this user only.  Creating it fails rather than reusing a directory which someone
+/// else made first.
+fn private_temp_dir() -> io::Result<PathBuf> {
+    let path = env::temp_dir().join(format!("punch-backup-{}", Uuid::new_v4()));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&path)?;
+    Ok(path)
+}
+
+/// Take a snapshot of the whole database, returned as the contents of a SQLite database file.
+/// `VACUUM INTO` copies the database within a single read transaction, so the snapshot is
+/// consistent even while punches are being written.
+pub struct BackupDatabase {}
+impl Message for BackupDatabase {
+    type Result = Result<Vec<u8>, DatabaseError>;
+}
+impl Handler<BackupDatabase> for DbExecutor {
+    type Result = Result<Vec<u8>, DatabaseError>;
+
+    fn handle(&mut self, _: BackupDatabase, _: &mut Self::Context) -> Self::Result {
+        let connection = self.0.get().map_err(DatabaseError::Pool)?;
+
+        // The snapshot holds every password hash and the cookie key, so it's written where no
+        // other user can read it, or put a file of their own for it to be written into.
+        let dir = private_temp_dir().map_err(DatabaseError::Backup)?;
+        let path = dir.join("backup.db");
+        let result = diesel::sql_query("VACUUM INTO ?")
+            .bind::<Text, _>(path.to_string_lossy())
+            .execute(&*connection)
+            .map_err(DatabaseError::from)
+            .and_then(|_| fs::read(&path).map_err(DatabaseError::Backup));
+        let _ = fs::remove_dir_all(&dir);
+        result
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// AuthenticateUser
+//////////////////////////////////////////////////////////////////////
+
+/// Check a user's password.  Usernames match without regard to case, so the result is the name as
+/// stored if the password is right, which should be used to identify the user from then on.
+pub struct AuthenticateUser {
+    pub username: String,
+    pub password: String,
+}
+impl Message for AuthenticateUser {
+    type Result = Result<Option<String>, DatabaseError>;
+}
+impl Handler<AuthenticateUser> for DbExecutor {
+    type Result = Result<Option<String>, DatabaseError>;
+
+    fn handle(&mut self, msg: AuthenticateUser, _: &mut Self::Context) -> Self::Result {
+        let conn: &SqliteConnection = &self.0.get().unwrap();
+
+        let user = load_user(conn, &msg.username)?;
+        match user.password {
+            Some(ref p) if password::verify(&msg.password, p)? => Ok(Some(user.name)),
+            _ => Ok(None),
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// ChangePassword
+//////////////////////////////////////////////////////////////////////
+
+/// Hash and store a new password for the given user.
+fn set_password(
+    connection: &SqliteConnection,
+    username: &str,
+    new_password: &str,
+) -> Result<(), DatabaseError> {
+    use self::schema::users::dsl as users_dsl;
+
+    if new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(DatabaseError::WeakPassword(MIN_PASSWORD_LENGTH));
+    }
+    let user = load_user(connection, username)?;
+    let config = load_config(connection)?;
+    let hashed_password = password::hasher(&config).hash(new_password)?;
+    diesel::update(users_dsl::users.find(user.id))
+        .set(users_dsl::password.eq(Some(hashed_password)))
+        .execute(connection)?;
+    Ok(())
+}
+
+pub struct ChangePassword {
+    pub username: String,
+    pub new_password: String,
+}
+impl Message for ChangePassword {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<ChangePassword> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: ChangePassword, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        set_password(connection, &msg.username, &msg.new_password)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// User administration
+//////////////////////////////////////////////////////////////////////
+
+/// Create a new user with the given password, along with the user's project.  Returns the id of the
+/// new user.
+fn create_user(
+    connection: &SqliteConnection,
+    username: &str,
+    password: &str,
+    role: models::Role,
+) -> Result<i64, DatabaseError> {
+    use self::schema::projects::dsl as projects_dsl;
+    use self::schema::users::dsl as users_dsl;
+
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(DatabaseError::WeakPassword(MIN_PASSWORD_LENGTH));
+    }
+    if load_user(connection, username).optional()?.is_some() {
+        return Err(DatabaseError::UserExists(username.to_string()));
+    }
+    // Project names must be unique, so each user's project is named after the user, unless another
+    // project has been renamed to that already.
+    let existing = projects_dsl::projects
+        .filter(projects_dsl::name.eq(username))
+        .first::<models::Project>(connection)
+        .optional()?;
+    if existing.is_some() {
+        return Err(DatabaseError::ProjectExists(username.to_string()));
+    }
+    let config = load_config(connection)?;
+    let hashed_password = password::hasher(&config).hash(password)?;
+    let new_user = models::NewUser {
+        name: username,
+        password: Some(&hashed_password),
+        role,
+    };
+    diesel::insert_into(users_dsl::users)
+        .values(&new_user)
+        .execute(connection)?;
+    let user_id = last_insert_rowid(connection);
+
+    let new_project = models::NewProject {
+        user_id,
+        name: username,
+        overhead: config.default_overhead,
+    };
+    diesel::insert_into(projects_dsl::projects)
+        .values(&new_project)
+        .execute(connection)?;
+    Ok(user_id)
+}
+
+/// Determine what the given user is allowed to do.
+pub struct GetRole {
+    pub username: String,
+}
+impl Message for GetRole {
+    type Result = Result<models::Role, DatabaseError>;
+}
+impl Handler<GetRole> for DbExecutor {
+    type Result = Result<models::Role, DatabaseError>;
+
+    fn handle(&mut self, msg: GetRole, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        Ok(load_user(connection, &msg.username)?.role)
+    }
+}
+
+/// List all users, in order of creation.
+pub struct ListUsers {}
+impl Message for ListUsers {
+    type Result = Result<Vec<models::User>, DatabaseError>;
+}
+impl Handler<ListUsers> for DbExecutor {
+    type Result = Result<Vec<models::User>, DatabaseError>;
+
+    fn handle(&mut self, _: ListUsers, _: &mut Self::Context) -> Self::Result {
+        use self::schema::users::dsl as users_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        users_dsl::users
+            .order(users_dsl::id)
+            .load::<models::User>(connection)
+            .map_err(|e| e.into())
+    }
+}
+
+/// Create a new user, along with the user's project.
+pub struct CreateUser {
+    pub name: String,
+    pub password: String,
+    pub role: models::Role,
+}
+impl Message for CreateUser {
+    type Result = Result<i64, DatabaseError>;
+}
+impl Handler<CreateUser> for DbExecutor {
+    type Result = Result<i64, DatabaseError>;
+
+    fn handle(&mut self, msg: CreateUser, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        connection.transaction(|| create_user(connection, &msg.name, &msg.password, msg.role))
+    }
+}
+
+/// Change a user's role on behalf of an administrator.  Administrators may not revoke their own
+/// admin access, so that at least one administrator always remains.
+pub struct SetRole {
+    pub username: String,
+    pub user_id: i64,
+    pub role: models::Role,
+}
+impl Message for SetRole {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<SetRole> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: SetRole, _: &mut Self::Context) -> Self::Result {
+        use self::schema::users::dsl as users_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        if user.id == msg.user_id && msg.role != models::Role::Admin {
+            return Err(DatabaseError::OwnAdminAccess);
+        }
+        let updated = diesel::update(users_dsl::users.find(msg.user_id))
+            .set(users_dsl::role.eq(msg.role))
+            .execute(connection)?;
+        if updated == 0 {
+            return Err(DatabaseError::BadUser);
+        }
+        Ok(())
+    }
+}
+
+/// Look up the given user's preference for showing gross and net times.
+pub struct GetTimeDisplay {
+    pub username: String,
+}
+impl Message for GetTimeDisplay {
+    type Result = Result<models::TimeDisplay, DatabaseError>;
+}
+impl Handler<GetTimeDisplay> for DbExecutor {
+    type Result = Result<models::TimeDisplay, DatabaseError>;
+
+    fn handle(&mut self, msg: GetTimeDisplay, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        Ok(load_user(connection, &msg.username)?.time_display)
+    }
+}
+
+/// Remember the given user's preference for showing gross and net times.
+pub struct SetTimeDisplay {
+    pub username: String,
+    pub time_display: models::TimeDisplay,
+}
+impl Message for SetTimeDisplay {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<SetTimeDisplay> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: SetTimeDisplay, _: &mut Self::Context) -> Self::Result {
+        use self::schema::users::dsl as users_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let updated = diesel::update(users_dsl::users.filter(user_named(&msg.username)))
+            .set(users_dsl::time_display.eq(msg.time_display))
+            .execute(connection)?;
+        if updated == 0 {
+            return Err(DatabaseError::BadUser);
+        }
+        Ok(())
+    }
+}
+
+/// Look up the given user's preference for how durations are written.
+pub struct GetDurationFormat {
+    pub username: String,
+}
+impl Message for GetDurationFormat {
+    type Result = Result<models::DurationFormat, DatabaseError>;
+}
+impl Handler<GetDurationFormat> for DbExecutor {
+    type Result = Result<models::DurationFormat, DatabaseError>;
+
+    fn handle(&mut self, msg: GetDurationFormat, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        Ok(load_user(connection, &msg.username)?.duration_format)
+    }
+}
+
+/// Remember the given user's preference for how durations are written.
+pub struct SetDurationFormat {
+    pub username: String,
+    pub duration_format: models::DurationFormat,
+}
+impl Message for SetDurationFormat {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<SetDurationFormat> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: SetDurationFormat, _: &mut Self::Context) -> Self::Result {
+        use self::schema::users::dsl as users_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let updated = diesel::update(users_dsl::users.filter(user_named(&msg.username)))
+            .set(users_dsl::duration_format.eq(msg.duration_format))
+            .execute(connection)?;
+        if updated == 0 {
+            return Err(DatabaseError::BadUser);
+        }
+        Ok(())
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// Project administration
+//////////////////////////////////////////////////////////////////////
+
+/// Rename a project, after checking that the new name is neither empty, too long, nor already used
+/// by another project.  Surrounding whitespace is removed from the name.
+fn rename_project_checked(
+    connection: &SqliteConnection,
+    project_id: i64,
+    new_name: &str,
+) -> Result<(), DatabaseError> {
+    use self::schema::projects::dsl as projects_dsl;
+
+    let new_name = new_name.trim();
+    let length = new_name.chars().count();
+    if length == 0 || length > MAX_PROJECT_NAME_LENGTH {
+        return Err(DatabaseError::BadProjectName(MAX_PROJECT_NAME_LENGTH));
+    }
+    let existing = projects_dsl::projects
+        .filter(projects_dsl::name.eq(new_name))
+        .filter(projects_dsl::id.ne(project_id))
+        .first::<models::Project>(connection)
+        .optional()?;
+    if existing.is_some() {
+        return Err(DatabaseError::ProjectExists(new_name.to_string()));
+    }
+    let updated = diesel::update(projects_dsl::projects.find(project_id))
+        .set(projects_dsl::name.eq(new_name))
+        .execute(connection)?;
+    if updated == 0 {
+        return Err(DatabaseError::BadProject);
+    }
+    Ok(())
+}
+
+/// Load the given user's project.
+pub struct GetProject {
+    pub username: String,
+}
+impl Message for GetProject {
+    type Result = Result<models::Project, DatabaseError>;
+}
+impl Handler<GetProject> for DbExecutor {
+    type Result = Result<models::Project, DatabaseError>;
+
+    fn handle(&mut self, msg: GetProject, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        load_project_for_user(connection, user.id)
+    }
+}
+
+/// Give a project a new name.
+pub struct RenameProject {
+    pub project_id: i64,
+    pub new_name: String,
+}
+impl Message for RenameProject {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<RenameProject> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: RenameProject, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let result = connection
+            .transaction(|| rename_project_checked(connection, msg.project_id, &msg.new_name));
+        self.1.invalidate(msg.project_id);
+        result
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// GetConfig
+//////////////////////////////////////////////////////////////////////
+
+fn load_config(connection: &SqliteConnection) -> Result<models::Config, DatabaseError> {
+    use self::schema::config::dsl::*;
+    use models::{Config, ConfigRow};
+
+    let row = config.first::<ConfigRow>(connection).optional()?;
+    match row {
+        Some(ref row) => {
+            let c = match Config::parse_row(row) {
+                Ok(c) => c,
+                Err(_) => {
+                    panic!("Cannot parse configuration");
+                }
+            };
+            Ok(c)
+        }
+        None => {
+            // No config row present -- create a new one.
+            let row = ConfigRow::new();
+            diesel::insert_into(config)
+                .values(&row)
+                .execute(connection)?;
+            Ok(Config::parse_row(&row).unwrap())
+        }
+    }
+}
+
+/// Confirm that a note isn't longer than the configured maximum.
+fn check_note_length(
+    connection: &SqliteConnection,
+    note: Option<&str>,
+) -> Result<(), DatabaseError> {
+    if let Some(note) = note {
+        let max_length = load_config(connection)?.max_note_length;
+        if note.chars().count() > max_length {
+            return Err(DatabaseError::NoteTooLong(max_length));
+        }
+    }
+    Ok(())
+}
+
+pub struct GetConfig {}
+impl Message for GetConfig {
+    type Result = Result<models::Config, DatabaseError>;
+}
+impl Handler<GetConfig> for DbExecutor {
+    type Result = Result<models::Config, DatabaseError>;
+
+    fn handle(&mut self, _: GetConfig, _: &mut Self::Context) -> Self::Result {
+        let conn: &SqliteConnection = &self.0.get().unwrap();
+        load_config(conn)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// PunchCommand
+//////////////////////////////////////////////////////////////////////
+
+/// Punch in or out, now or at the given local time, which may correct a punch made a little late.
+/// A punch dated earlier than now must still follow the most recent punch.
+pub struct PunchCommand {
+    // project_id: String,
+    pub username: String,
+    pub direction: PunchDirection,
+    pub note: Option<String>,
+    pub at: Option<::chrono::NaiveDateTime>,
+}
+impl Message for PunchCommand {
+    type Result = Result<(), DatabaseError>;
+}
+
+/// This will load the sole user.  Some day we should support multiple users.
+fn load_singleton_user(connection: &SqliteConnection) -> Result<models::User, DatabaseError> {
+    use self::schema::users::dsl as users_dsl;
+    users_dsl::users
+        .order(users_dsl::id)
+        .first::<models::User>(connection)
+        .map_err(|e| e.into())
+}
+
+/// Load a user by name, without regard to case.
+fn load_user(
+    connection: &SqliteConnection,
+    username: &str,
+) -> Result<models::User, diesel::result::Error> {
+    use self::schema::users::dsl as users_dsl;
+    users_dsl::users
+        .filter(user_named(username))
+        .first::<models::User>(connection)
+}
+
+/// This will load the user's sole project.  Some day we should support multiple projects per user.
+fn load_project_for_user(
+    connection: &SqliteConnection,
+    user_id: i64,
+) -> Result<models::Project, DatabaseError> {
+    use self::schema::projects::dsl as projects_dsl;
+    projects_dsl::projects
+        .filter(projects_dsl::user_id.eq(user_id))
+        .order(projects_dsl::id)
+        .first::<models::Project>(connection)
+        .map_err(|e| e.into())
+}
+
+/// Determine the next expected punch direction, based on whether the previous punch direction was
+/// in, out, or non-existent.
+pub fn next_expected_punch_direction(
+    connection: &SqliteConnection,
+    project_id: i64,
+) -> Result<PunchDirection, DatabaseError> {
+    let last_event = load_last_punch(connection, project_id)?;
+    Ok(next_direction_after(last_event.as_ref()))
+}
+
+/// Determine the punch direction which should follow the given punch event.
+fn next_direction_after(last_event: Option<&models::Event>) -> PunchDirection {
+    match last_event.map(|e| &e.event_type) {
+        Some(models::EventType::In) => PunchDirection::Out,
+        Some(models::EventType::Out) => PunchDirection::In,
+        Some(models::EventType::Note)
+        | Some(models::EventType::Vacation)
+        | Some(models::EventType::Break) => unreachable!(),
+        None => PunchDirection::In,
+    }
+}
+
+/// Load the most recent in or out event.
+pub fn load_last_punch(
+    connection: &SqliteConnection,
+    project_id: i64,
+) -> Result<Option<models::Event>, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+    events_dsl::events
+        .filter(events_dsl::project_id.eq(project_id))
+        .filter(
+            events_dsl::event_type
+                .eq(models::EventType::In)
+                .or(events_dsl::event_type.eq(models::EventType::Out)),
+        )
+        .order(events_dsl::clock.desc())
+        .first::<models::Event>(connection)
+        .optional()
+        .map_err(|e| e.into())
+}
+
+/// Find the in/out events immediately before and after the given time, optionally ignoring one
+/// event (such as an event that is being moved or removed).
+fn neighboring_punches(
+    connection: &SqliteConnection,
+    project_id: i64,
+    clock: UtcDateTime,
+    ignore_id: Option<i64>,
+) -> Result<(Option<models::Event>, Option<models::Event>), DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+    use models::EventType;
+
+    let punches = || {
+        let query = events_dsl::events
+            .filter(events_dsl::project_id.eq(project_id))
+            .filter(
+                events_dsl::event_type
+                    .eq(EventType::In)
+                    .or(events_dsl::event_type.eq(EventType::Out)),
+            )
+            .into_boxed();
+        match ignore_id {
+            Some(id) => query.filter(events_dsl::id.ne(id)),
+            None => query,
+        }
+    };
+    let previous = punches()
+        .filter(events_dsl::clock.le(clock))
+        .order(events_dsl::clock.desc())
+        .first::<models::Event>(connection)
+        .optional()?;
+    let next = punches()
+        .filter(events_dsl::clock.gt(clock))
+        .order(events_dsl::clock)
+        .first::<models::Event>(connection)
+        .optional()?;
+    Ok((previous, next))
+}
+
+/// Confirm that an in or out event at the given time would not be adjacent to another event of the
+/// same direction.  Other events don't participate in the in/out ordering, so they are always
+/// accepted.
+fn check_event_ordering(
+    connection: &SqliteConnection,
+    project_id: i64,
+    event_type: &models::EventType,
+    clock: UtcDateTime,
+    ignore_id: Option<i64>,
+) -> Result<(), DatabaseError> {
+    if !event_type.is_punch() {
+        return Ok(());
+    }
+    let (previous, next) = neighboring_punches(connection, project_id, clock, ignore_id)?;
+    let conflicts: Vec<i64> = previous
+        .iter()
+        .chain(next.iter())
+        .filter(|e| e.event_type == *event_type)
+        .map(|e| e.id)
+        .collect();
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(DatabaseError::OrderingConflict(EventIds(conflicts)))
+    }
+}
+
+/// Confirm that removing an event would not leave a dangling interval: two adjacent events of the
+/// same direction, an "out" with no preceding "in", or a completed session turned back into an open
+/// one.
+fn check_event_removal(
+    connection: &SqliteConnection,
+    event: &models::Event,
+) -> Result<(), DatabaseError> {
+    use models::EventType;
+
+    if !event.event_type.is_punch() {
+        return Ok(());
+    }
+    let (previous, next) =
+        neighboring_punches(connection, event.project_id, event.clock, Some(event.id))?;
+    let conflicts = match (previous, next) {
+        (Some(p), Some(n)) => {
+            if p.event_type == n.event_type {
+                vec![p.id, n.id]
+            } else {
+                vec![]
+            }
+        }
+        (None, Some(n)) => {
+            if n.event_type == EventType::Out {
+                vec![n.id]
+            } else {
+                vec![]
+            }
+        }
+        (Some(p), None) => {
+            if p.event_type == EventType::In {
+                vec![p.id]
+            } else {
+                vec![]
+            }
+        }
+        (None, None) => vec![],
+    };
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(DatabaseError::DanglingInterval(EventIds(conflicts)))
+    }
+}
+
+/// Confirm that moving an event to a new time keeps the in/out ordering intact.  Moving an event
+/// within the gap between its neighbors is always fine; moving it past another in or out event is
+/// only fine if both its old and new positions remain consistent.
+fn check_event_move(
+    connection: &SqliteConnection,
+    event: &models::Event,
+    new_clock: UtcDateTime,
+) -> Result<(), DatabaseError> {
+    if !event.event_type.is_punch() {
+        return Ok(());
+    }
+    let ids =
+        |(p, n): (Option<models::Event>, Option<models::Event>)| (p.map(|e| e.id), n.map(|e| e.id));
+    let old_neighbors = ids(neighboring_punches(
+        connection,
+        event.project_id,
+        event.clock,
+        Some(event.id),
+    )?);
+    let new_neighbors = ids(neighboring_punches(
+        connection,
+        event.project_id,
+        new_clock,
+        Some(event.id),
+    )?);
+    if old_neighbors == new_neighbors {
+        return Ok(());
+    }
+    check_event_removal(connection, event).map_err(|e| match e {
+        DatabaseError::DanglingInterval(ids) => DatabaseError::OrderingConflict(ids),
+        e => e,
+    })?;
+    check_event_ordering(
+        connection,
+        event.project_id,
+        &event.event_type,
+        new_clock,
+        Some(event.id),
+    )
+}
+
+/// Load an event, confirming that it belongs to the given project.
+fn load_event(
+    connection: &SqliteConnection,
+    project_id: i64,
+    event_id: i64,
+) -> Result<models::Event, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+    events_dsl::events
+        .filter(events_dsl::id.eq(event_id))
+        .filter(events_dsl::project_id.eq(project_id))
+        .first::<models::Event>(connection)
+        .optional()?
+        .ok_or(DatabaseError::BadEvent)
+}
+
+/// Insert an event at an arbitrary time, after confirming that it doesn't violate the in/out
+/// ordering relative to its neighbors.  Vacation events must give the amount of vacation time used,
+/// and break events the length of the break.
+/// Returns the id of the new event.
+fn insert_event_checked(
+    connection: &SqliteConnection,
+    project_id: i64,
+    event_type: models::EventType,
+    clock: UtcDateTime,
+    note: Option<String>,
+    minutes: Option<i32>,
+) -> Result<i64, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    if event_type == models::EventType::Vacation && minutes.unwrap_or(0) <= 0 {
+        return Err(DatabaseError::BadVacation);
+    }
+    if event_type == models::EventType::Break && minutes.unwrap_or(0) <= 0 {
+        return Err(DatabaseError::BadBreak);
+    }
+    check_note_length(connection, note.as_deref())?;
+    check_event_ordering(connection, project_id, &event_type, clock, None)?;
+    let new_event = models::NewEvent {
+        project_id,
+        event_type,
+        clock,
+        note,
+        minutes,
+    };
+    diesel::insert_into(events_dsl::events)
+        .values(&new_event)
+        .execute(connection)?;
+    Ok(last_insert_rowid(connection))
+}
+
+/// Change the time and/or note of an existing event, after confirming that the in/out ordering
+/// remains intact.  An empty note clears the existing note.
+fn edit_event_checked(
+    connection: &SqliteConnection,
+    project_id: i64,
+    event_id: i64,
+    new_clock: Option<UtcDateTime>,
+    new_note: Option<String>,
+) -> Result<(), DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    check_note_length(connection, new_note.as_deref())?;
+    let event = load_event(connection, project_id, event_id)?;
+    if let Some(new_clock) = new_clock {
+        check_event_move(connection, &event, new_clock)?;
+        diesel::update(events_dsl::events.find(event.id))
+            .set(events_dsl::clock.eq(new_clock))
+            .execute(connection)?;
+    }
+    if let Some(new_note) = new_note {
+        let new_note = if new_note.is_empty() {
+            None
+        } else {
+            Some(new_note)
+        };
+        diesel::update(events_dsl::events.find(event.id))
+            .set(events_dsl::note.eq(new_note))
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+/// Delete an existing event.  Unless forced, the deletion is refused if it would leave a dangling
+/// interval.
+fn delete_event_checked(
+    connection: &SqliteConnection,
+    project_id: i64,
+    event_id: i64,
+    force: bool,
+) -> Result<(), DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    let event = load_event(connection, project_id, event_id)?;
+    if !force {
+        check_event_removal(connection, &event)?;
+    }
+    diesel::delete(events_dsl::events.find(event.id)).execute(connection)?;
+    Ok(())
+}
+
+impl Handler<PunchCommand> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: PunchCommand, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+
+        // Load the user and project
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+
+        // Confirm that this punch is consistent with the most recent punch, in the same
+        // transaction as the punch, so that a concurrent punch can't slip in between.
+        let (direction, note, at) = (msg.direction, msg.note, msg.at);
+        let event_id = connection.transaction::<_, DatabaseError, _>(|| {
+            let last_punch = load_last_punch(connection, project.id)?;
+            if direction != next_direction_after(last_punch.as_ref()) {
+                return Err(DatabaseError::BadState);
+            }
+            insert_punch(connection, project.id, last_punch, direction, note, at)
+        })?;
+        self.1.invalidate(project.id);
+        self.audit(&msg.username, direction.into(), event_id);
+
+        Ok(())
+    }
+}
+
+/// Punch in if the user is punched out, or out if they are punched in, for clients such as a
+/// physical button which can't know which is due.  Returns the direction of the punch recorded.
+pub struct TogglePunch {
+    pub username: String,
+    pub note: Option<String>,
+}
+impl Message for TogglePunch {
+    type Result = Result<PunchDirection, DatabaseError>;
+}
+impl Handler<TogglePunch> for DbExecutor {
+    type Result = Result<PunchDirection, DatabaseError>;
+
+    fn handle(&mut self, msg: TogglePunch, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+
+        // The direction is decided in the same transaction as the punch, so that two presses in
+        // quick succession punch in and then out.
+        let note = msg.note;
+        let (direction, event_id) = connection.transaction::<_, DatabaseError, _>(|| {
+            let last_punch = load_last_punch(connection, project.id)?;
+            let direction = next_direction_after(last_punch.as_ref());
+            let event_id = insert_punch(connection, project.id, last_punch, direction, note, None)?;
+            Ok((direction, event_id))
+        })?;
+        self.1.invalidate(project.id);
+        self.audit(&msg.username, direction.into(), event_id);
+
+        Ok(direction)
+    }
+}
+
+/// Record a punch in the given direction, following the given most recent punch, now or at the
+/// given local time.  Returns the id of the new event.
+fn insert_punch(
+    connection: &SqliteConnection,
+    project_id: i64,
+    last_punch: Option<models::Event>,
+    direction: PunchDirection,
+    note: Option<String>,
+    at: Option<::chrono::NaiveDateTime>,
+) -> Result<i64, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    check_note_length(connection, note.as_deref())?;
+    let clock = punch_clock(UtcDateTime::now(), at, last_punch.as_ref())?;
+
+    // Create the punch event
+    let new_event = models::NewEvent {
+        project_id,
+        event_type: direction.into(),
+        clock,
+        note,
+        minutes: None,
+    };
+    diesel::insert_into(events_dsl::events)
+        .values(&new_event)
+        .execute(connection)?;
+    Ok(last_insert_rowid(connection))
+}
+
+/// Determine the time of a punch made at the given current time, either now or at the given local
+/// time, following the given most recent punch.  If the server clock has jumped backward, the new
+/// punch would land before the most recent one and produce a negative interval, so it is refused.
+/// A punch dated explicitly can't be in the future, and likewise must follow the most recent punch.
+fn punch_clock(
+    now: UtcDateTime,
+    at: Option<::chrono::NaiveDateTime>,
+    last_punch: Option<&models::Event>,
+) -> Result<UtcDateTime, DatabaseError> {
+    let clock = match at {
+        Some(at) => to_utc(&at, Disambiguate::Earlier),
+        None => now,
+    };
+    if clock > now {
+        return Err(DatabaseError::FuturePunch);
+    }
+    if let Some(last_punch) = last_punch {
+        if clock < last_punch.clock {
+            return Err(match at {
+                Some(_) => DatabaseError::OrderingConflict(EventIds(vec![last_punch.id])),
+                None => DatabaseError::TimeRegression(last_punch.clock),
+            });
+        }
+    }
+    Ok(clock)
+}
+
+//////////////////////////////////////////////////////////////////////
+// AutoPunchOut
+//////////////////////////////////////////////////////////////////////
+
+/// Punch out any open session which has been idle for longer than its project's idle timeout.  A
+/// session is idle from the later of its punch-in and the user's most recent request, given by
+/// username in `last_activity`, and the punch-out is dated at the end of the timeout.  Returns the
+/// names of the users who were punched out.
+pub struct AutoPunchOut {
+    pub last_activity: HashMap<String, UtcDateTime>,
+}
+impl Message for AutoPunchOut {
+    type Result = Result<Vec<String>, DatabaseError>;
+}
+impl Handler<AutoPunchOut> for DbExecutor {
+    type Result = Result<Vec<String>, DatabaseError>;
+
+    fn handle(&mut self, msg: AutoPunchOut, _: &mut Self::Context) -> Self::Result {
+        use self::schema::projects::dsl as projects_dsl;
+        use self::schema::users::dsl as users_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+
+        let now = UtcDateTime::now();
+        let projects = projects_dsl::projects
+            .filter(projects_dsl::idle_timeout_minutes.gt(0))
+            .load::<models::Project>(connection)?;
+        let mut punched_out = Vec::new();
+        for project in projects {
+            let punched_out_event = connection.transaction::<_, DatabaseError, _>(|| {
+                let punch_in = match load_last_punch(connection, project.id)? {
+                    Some(ref event) if event.event_type == models::EventType::In => event.clock,
+                    _ => return Ok(None),
+                };
+                let user = users_dsl::users
+                    .find(project.user_id)
+                    .first::<models::User>(connection)?;
+                let last_active = match msg.last_activity.get(&user.name) {
+                    Some(active) if *active > punch_in => *active,
+                    _ => punch_in,
+                };
+                let timeout = ::chrono::Duration::minutes(project.idle_timeout_minutes as i64);
+                if now.0 - last_active.0 < timeout {
+                    return Ok(None);
+                }
+                let event_id = insert_event_checked(
+                    connection,
+                    project.id,
+                    models::EventType::Out,
+                    UtcDateTime(last_active.0 + timeout),
+                    Some(format!(
+                        "Automatically punched out after {} idle minutes",
+                        project.idle_timeout_minutes
+                    )),
+                    None,
+                )?;
+                Ok(Some((user.name, event_id)))
+            })?;
+            if let Some((user_name, event_id)) = punched_out_event {
+                self.1.invalidate(project.id);
+                self.audit(&user_name, AuditAction::PunchOut, event_id);
+                punched_out.push(user_name);
+            }
+        }
+        Ok(punched_out)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// UndoLastPunch
+//////////////////////////////////////////////////////////////////////
+
+/// Delete the most recent punch, as long as it was made within the last few minutes, so that an
+/// accidental punch can be reverted without clobbering real history.  Note events are never
+/// deleted.  Returns the punch direction expected next, now that the punch is gone.
+pub struct UndoLastPunch {
+    pub username: String,
+}
+impl Message for UndoLastPunch {
+    type Result = Result<PunchDirection, DatabaseError>;
+}
+impl Handler<UndoLastPunch> for DbExecutor {
+    type Result = Result<PunchDirection, DatabaseError>;
+
+    fn handle(&mut self, msg: UndoLastPunch, _: &mut Self::Context) -> Self::Result {
+        use self::schema::events::dsl as events_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        let result = connection.transaction(|| {
+            let last_punch =
+                load_last_punch(connection, project.id)?.ok_or(DatabaseError::NothingToUndo)?;
+            let age = UtcDateTime::now().0 - last_punch.clock.0;
+            if age > ::chrono::Duration::minutes(UNDO_WINDOW_MINUTES) {
+                return Err(DatabaseError::UndoExpired(UNDO_WINDOW_MINUTES));
+            }
+            diesel::delete(events_dsl::events.find(last_punch.id)).execute(connection)?;
+            Ok((
+                next_expected_punch_direction(connection, project.id)?,
+                last_punch.id,
+            ))
+        });
+        self.1.invalidate(project.id);
+        let (direction, event_id) = result?;
+        self.audit(&msg.username, AuditAction::Delete, event_id);
+        Ok(direction)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// EditEvent
+//////////////////////////////////////////////////////////////////////
+
+pub struct EditEvent {
+    pub username: String,
+    pub id: i64,
+    pub new_clock: Option<UtcDateTime>,
+    pub new_note: Option<String>,
+}
+impl Message for EditEvent {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<EditEvent> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: EditEvent, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        let event_id = msg.id;
+        let result = connection.transaction(|| {
+            edit_event_checked(connection, project.id, msg.id, msg.new_clock, msg.new_note)
+        });
+        self.1.invalidate(project.id);
+        result?;
+        self.audit(&user.name, AuditAction::Edit, event_id);
+        Ok(())
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// DeleteEvent
+//////////////////////////////////////////////////////////////////////
+
+pub struct DeleteEvent {
+    pub username: String,
+    pub id: i64,
+    pub force: bool,
+}
+impl Message for DeleteEvent {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<DeleteEvent> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: DeleteEvent, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        let result = connection
+            .transaction(|| delete_event_checked(connection, project.id, msg.id, msg.force));
+        self.1.invalidate(project.id);
+        result?;
+        self.audit(&msg.username, AuditAction::Delete, msg.id);
+        Ok(())
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// ReplaceDayEvents
+//////////////////////////////////////////////////////////////////////
+
+/// Replace all of the punches of a day of the project with the given events, each of which is given
+/// by its type, local time, and note.  The replacement is checked as a whole, together with the
+/// punches on either side of the day, and nothing is changed unless the in/out ordering holds.
+/// Notes, vacation, and breaks on the day are kept, and can't be given as replacements, so that
+/// repeating a replacement changes nothing.
+pub struct ReplaceDayEvents {
+    pub username: String,
+    pub date: ::chrono::NaiveDate,
+    pub events: Vec<(models::EventType, ::chrono::NaiveDateTime, Option<String>)>,
+}
+impl Message for ReplaceDayEvents {
+    type Result = Result<(), DatabaseError>;
+}
+impl Handler<ReplaceDayEvents> for DbExecutor {
+    type Result = Result<(), DatabaseError>;
+
+    fn handle(&mut self, msg: ReplaceDayEvents, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        let result = connection
+            .transaction(|| replace_day_events_checked(connection, &project, msg.date, msg.events));
+        self.1.invalidate(project.id);
+        for (action, event_id) in result? {
+            self.audit(&user.name, action, event_id);
+        }
+        Ok(())
+    }
+}
+
+/// Replace the punches of a day of the project, as described for `ReplaceDayEvents`.  The day
+/// begins at the project's day start hour.  This should be called within a transaction, so that the
+/// old punches are never deleted without the new ones being inserted.  Returns the changes made, as
+/// the ids of the deleted punches and then of the inserted ones, for the audit log.
+fn replace_day_events_checked(
+    connection: &SqliteConnection,
+    project: &models::Project,
+    date: ::chrono::NaiveDate,
+    mut events: Vec<(models::EventType, ::chrono::NaiveDateTime, Option<String>)>,
+) -> Result<Vec<(AuditAction, i64)>, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+    use models::EventType;
+
+    let day_start = to_utc(&day_begins(project, date), Disambiguate::Earlier);
+    let day_end = to_utc(&day_begins(project, date.succ()), Disambiguate::Earlier);
+    let punches = || {
+        events_dsl::events
+            .filter(events_dsl::project_id.eq(project.id))
+            .filter(
+                events_dsl::event_type
+                    .eq(EventType::In)
+                    .or(events_dsl::event_type.eq(EventType::Out)),
+            )
+    };
+
+    // The new punches must alternate, starting from the last punch before the day and leading
+    // into the first punch after it.
+    events.sort_by_key(|&(_, time, _)| time);
+    let previous = punches()
+        .filter(events_dsl::clock.lt(day_start))
+        .order(events_dsl::clock.desc())
+        .first::<models::Event>(connection)
+        .optional()?;
+    let next = punches()
+        .filter(events_dsl::clock.ge(day_end))
+        .order(events_dsl::clock)
+        .first::<models::Event>(connection)
+        .optional()?;
+    let mut expected_type = match previous {
+        Some(ref event) if event.event_type == EventType::In => EventType::Out,
+        _ => EventType::In,
+    };
+    for &(ref event_type, time, ref note) in &events {
+        // The replacement events have no minutes, which vacation and break events need.
+        if *event_type == EventType::Vacation {
+            return Err(DatabaseError::BadVacation);
+        }
+        if *event_type == EventType::Break {
+            return Err(DatabaseError::BadBreak);
+        }
+        if *event_type == EventType::Note {
+            return Err(DatabaseError::NoteInReplacement);
+        }
+        check_note_length(connection, note.as_deref())?;
+        if project_date(project, &time) != date {
+            return Err(DatabaseError::NotOnDay(time, date));
+        }
+        if *event_type != expected_type {
+            return Err(DatabaseError::ReplacementOrdering(time));
+        }
+        expected_type = match event_type {
+            EventType::In => EventType::Out,
+            _ => EventType::In,
+        };
+    }
+    if let Some(next) = next {
+        if next.event_type != expected_type {
+            return Err(DatabaseError::OrderingConflict(EventIds(vec![next.id])));
+        }
+    }
+
+    let day_punches = || {
+        punches()
+            .filter(events_dsl::clock.ge(day_start))
+            .filter(events_dsl::clock.lt(day_end))
+    };
+    let mut changes: Vec<(AuditAction, i64)> = day_punches()
+        .select(events_dsl::id)
+        .order(events_dsl::clock)
+        .load::<i64>(connection)?
+        .into_iter()
+        .map(|id| (AuditAction::Delete, id))
+        .collect();
+    diesel::delete(day_punches()).execute(connection)?;
+    for (event_type, time, note) in events {
+        let action = match event_type {
+            EventType::In => AuditAction::PunchIn,
+            _ => AuditAction::PunchOut,
+        };
+        let new_event = models::NewEvent {
+            project_id: project.id,
+            event_type,
+            clock: to_utc(&time, Disambiguate::Earlier),
+            note,
+            minutes: None,
+        };
+        diesel::insert_into(events_dsl::events)
+            .values(&new_event)
+            .execute(connection)?;
+        changes.push((action, last_insert_rowid(connection)));
+    }
+    Ok(changes)
+}
+
+//////////////////////////////////////////////////////////////////////
+// GetNextDirection
+//////////////////////////////////////////////////////////////////////
+
+pub struct GetNextDirection {
+    pub username: String,
+}
+impl Message for GetNextDirection {
+    type Result = Result<PunchDirection, DatabaseError>;
+}
+impl Handler<GetNextDirection> for DbExecutor {
+    type Result = Result<PunchDirection, DatabaseError>;
+
+    fn handle(&mut self, msg: GetNextDirection, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        next_expected_punch_direction(connection, project.id)
+    }
+}
+
+/// Determine whether any project has a work session in progress.
+pub struct AnySessionInProgress {}
+impl Message for AnySessionInProgress {
+    type Result = Result<bool, DatabaseError>;
+}
+impl Handler<AnySessionInProgress> for DbExecutor {
+    type Result = Result<bool, DatabaseError>;
+
+    fn handle(&mut self, _: AnySessionInProgress, _: &mut Self::Context) -> Self::Result {
+        use self::schema::projects::dsl as projects_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let project_ids = projects_dsl::projects
+            .select(projects_dsl::id)
+            .load::<i64>(connection)?;
+        for project_id in project_ids {
+            if next_expected_punch_direction(connection, project_id)? == PunchDirection::Out {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// GetReport
+//////////////////////////////////////////////////////////////////////
+
+pub struct GetDayStatus {
+    pub username: String,
+}
+impl Message for GetDayStatus {
+    type Result = Result<DayStatus, DatabaseError>;
+}
+impl Handler<GetDayStatus> for DbExecutor {
+    type Result = Result<DayStatus, DatabaseError>;
+
+    fn handle(&mut self, msg: GetDayStatus, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        ::report::day_status(connection, project.id)
+    }
+}
+
+/// List the work sessions of one local day.
+pub struct GetDaySessions {
+    pub username: String,
+    pub date: ::chrono::NaiveDate,
+}
+impl Message for GetDaySessions {
+    type Result = Result<Vec<DaySession>, DatabaseError>;
+}
+impl Handler<GetDaySessions> for DbExecutor {
+    type Result = Result<Vec<DaySession>, DatabaseError>;
+
+    fn handle(&mut self, msg: GetDaySessions, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        ::report::day_sessions(connection, project.id, msg.date)
+    }
+}
+
+/// Request the summary report of a user's project.  The daily totals cover the given number of
+/// days, or the days of the current week if no number is given.
+pub struct GetSummaryReport {
+    pub username: String,
+    pub weeks_in_past: u32,
+    pub days: Option<u32>,
+    pub max_events: usize,
+}
+impl Message for GetSummaryReport {
     type Result = Result<SummaryReport, DatabaseError>;
 }
 impl Handler<GetSummaryReport> for DbExecutor {
     type Result = Result<SummaryReport, DatabaseError>;
 
-    fn handle(&mut self, _: GetSummaryReport, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: GetSummaryReport, _: &mut Self::Context) -> Self::Result {
         let connection: &SqliteConnection = &self.0.get().unwrap();
-        let user = load_singleton_user(&connection)?;
-        let project = load_project_for_user(&connection, user.id)?;
-        ::report::summary_report(&connection, project.id)
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        let settings = ReportSettings {
+            weeks_in_past: msg.weeks_in_past,
+            days: msg.days,
+            max_events: msg.max_events,
+            ..ReportSettings::from_project(&project)
+        };
+        self.1.summary_report(connection, &project, &settings)
+    }
+}
+
+/// Request a summary report as `GetSummaryReport` does, but as if the user's project had the given
+/// overhead settings, where given.  The project is left unchanged, and the report cache isn't used.
+pub struct PreviewReport {
+    pub username: String,
+    pub overhead: Option<i32>,
+    pub overhead_mode: Option<models::OverheadMode>,
+    pub weeks_in_past: u32,
+    pub days: Option<u32>,
+    pub max_events: usize,
+}
+impl Message for PreviewReport {
+    type Result = Result<SummaryReport, DatabaseError>;
+}
+impl Handler<PreviewReport> for DbExecutor {
+    type Result = Result<SummaryReport, DatabaseError>;
+
+    fn handle(&mut self, msg: PreviewReport, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        let settings = ReportSettings {
+            overhead: msg.overhead.unwrap_or(project.overhead),
+            overhead_mode: msg.overhead_mode.unwrap_or(project.overhead_mode),
+            weeks_in_past: msg.weeks_in_past,
+            days: msg.days,
+            max_events: msg.max_events,
+            ..ReportSettings::from_project(&project)
+        };
+        ::report::summary_report(connection, &project, &settings)
+    }
+}
+
+/// Request the net and gross time of the current week for each of a user's projects, for an
+/// overview across all of them.
+pub struct GetAllProjectsSummary {
+    pub username: String,
+}
+impl Message for GetAllProjectsSummary {
+    type Result = Result<Vec<(models::Project, WorkTime)>, DatabaseError>;
+}
+impl Handler<GetAllProjectsSummary> for DbExecutor {
+    type Result = Result<Vec<(models::Project, WorkTime)>, DatabaseError>;
+
+    fn handle(&mut self, msg: GetAllProjectsSummary, _: &mut Self::Context) -> Self::Result {
+        use self::schema::projects::dsl as projects_dsl;
+
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let projects = projects_dsl::projects
+            .filter(projects_dsl::user_id.eq(user.id))
+            .order(projects_dsl::id)
+            .load::<models::Project>(connection)?;
+        projects
+            .into_iter()
+            .map(|project| {
+                let total = ::report::current_week_total(connection, &project)?;
+                Ok((project, total))
+            })
+            .collect()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// GetLifetimeStats
+//////////////////////////////////////////////////////////////////////
+
+/// Request aggregates covering everything a user has ever recorded, across all of their projects.
+pub struct GetLifetimeStats {
+    pub username: String,
+}
+impl Message for GetLifetimeStats {
+    type Result = Result<LifetimeStats, DatabaseError>;
+}
+
+/// A user's totals since their first punch.
+#[derive(Serialize)]
+pub struct LifetimeStats {
+    pub total_events: i64,
+    pub total_projects: i64,
+    /// The time of the earliest punch-in, or None if the user has never punched in.
+    pub first_punch: Option<UtcDateTime>,
+    pub work_time: WorkTime,
+}
+
+impl Handler<GetLifetimeStats> for DbExecutor {
+    type Result = Result<LifetimeStats, DatabaseError>;
+
+    fn handle(&mut self, msg: GetLifetimeStats, _: &mut Self::Context) -> Self::Result {
+        use self::schema::events::dsl as events_dsl;
+        use self::schema::projects::dsl as projects_dsl;
+        use diesel::expression::functions::aggregate_ordering::min;
+
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let user_projects = projects_dsl::projects.filter(projects_dsl::user_id.eq(user.id));
+        let user_events = events_dsl::events
+            .filter(events_dsl::project_id.eq_any(user_projects.select(projects_dsl::id)));
+
+        let total_projects = user_projects.count().get_result::<i64>(connection)?;
+        let total_events = user_events.count().get_result::<i64>(connection)?;
+        let first_punch = user_events
+            .filter(events_dsl::event_type.eq(models::EventType::In))
+            .select(min(events_dsl::clock))
+            .first::<Option<UtcDateTime>>(connection)?;
+
+        // Net time depends on each project's rules for overhead and short sessions, so it can't be
+        // summed in SQL.
+        let mut work_time = WorkTime::new();
+        for project in user_projects.load::<models::Project>(connection)? {
+            work_time += &::report::lifetime_total(connection, &project)?;
+        }
+
+        Ok(LifetimeStats {
+            total_events,
+            total_projects,
+            first_punch,
+            work_time,
+        })
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// GetEvents
+//////////////////////////////////////////////////////////////////////
+
+/// Request a page of a user's events, newest first.  The page starts after the event given by
+/// `before_id`, or with the newest event if there is no such cursor.
+pub struct GetEvents {
+    pub username: String,
+    pub before_id: Option<i64>,
+    pub limit: usize,
+}
+impl Message for GetEvents {
+    type Result = Result<EventPage, DatabaseError>;
+}
+
+/// A page of events, along with the cursor to use for requesting the following (older) page.
+#[derive(Serialize)]
+pub struct EventPage {
+    pub events: Vec<models::Event>,
+    pub next_before_id: Option<i64>,
+}
+
+impl Handler<GetEvents> for DbExecutor {
+    type Result = Result<EventPage, DatabaseError>;
+
+    fn handle(&mut self, msg: GetEvents, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        load_event_page(connection, project.id, None, msg.before_id, msg.limit)
+    }
+}
+
+/// Request a page of a user's note events, newest first, paged in the same way as `GetEvents`.
+pub struct GetNotes {
+    pub username: String,
+    pub before_id: Option<i64>,
+    pub limit: usize,
+}
+impl Message for GetNotes {
+    type Result = Result<EventPage, DatabaseError>;
+}
+impl Handler<GetNotes> for DbExecutor {
+    type Result = Result<EventPage, DatabaseError>;
+
+    fn handle(&mut self, msg: GetNotes, _: &mut Self::Context) -> Self::Result {
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+        load_event_page(
+            connection,
+            project.id,
+            Some(models::EventType::Note),
+            msg.before_id,
+            msg.limit,
+        )
+    }
+}
+
+/// Load a page of a project's events, newest first, optionally only those of one type.  The page
+/// starts after the event given by `before_id`, or with the newest event if there is no cursor.
+fn load_event_page(
+    connection: &SqliteConnection,
+    project_id: i64,
+    event_type: Option<models::EventType>,
+    before_id: Option<i64>,
+    limit: usize,
+) -> Result<EventPage, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    // Events are ordered by time, with the id breaking ties between events at the same time, so
+    // the cursor event determines where the page starts in that ordering.
+    let mut query = events_dsl::events
+        .filter(events_dsl::project_id.eq(project_id))
+        .into_boxed();
+    if let Some(event_type) = event_type {
+        query = query.filter(events_dsl::event_type.eq(event_type));
+    }
+    if let Some(before_id) = before_id {
+        let cursor = load_event(connection, project_id, before_id)?;
+        query = query.filter(
+            events_dsl::clock.lt(cursor.clock).or(events_dsl::clock
+                .eq(cursor.clock)
+                .and(events_dsl::id.lt(cursor.id))),
+        );
+    }
+
+    // Fetch one extra event to learn whether there is another page.
+    let mut events = query
+        .order((events_dsl::clock.desc(), events_dsl::id.desc()))
+        .limit(limit as i64 + 1)
+        .load::<models::Event>(connection)?;
+    let next_before_id = if events.len() > limit {
+        events.truncate(limit);
+        events.last().map(|e| e.id)
+    } else {
+        None
+    };
+    Ok(EventPage {
+        events,
+        next_before_id,
+    })
+}
+
+/// Request a user's events from the given time onward, oldest first, for clients which poll for
+/// new events.  Only the time of each event is considered, so edits to older events are not seen.
+pub struct GetEventsSince {
+    pub username: String,
+    pub since: UtcDateTime,
+}
+impl Message for GetEventsSince {
+    type Result = Result<EventsSince, DatabaseError>;
+}
+
+/// The events since a given time, along with the server's time at the start of the query, which
+/// the client can pass back as the starting time of its next poll.
+#[derive(Serialize)]
+pub struct EventsSince {
+    pub server_time: UtcDateTime,
+    pub events: Vec<models::Event>,
+    /// The time of the punch-in, if the user is currently punched in.
+    pub in_progress_since: Option<UtcDateTime>,
+}
+
+impl Handler<GetEventsSince> for DbExecutor {
+    type Result = Result<EventsSince, DatabaseError>;
+
+    fn handle(&mut self, msg: GetEventsSince, _: &mut Self::Context) -> Self::Result {
+        use self::schema::events::dsl as events_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+
+        let server_time = UtcDateTime::now();
+        connection.transaction(|| {
+            let events = events_dsl::events
+                .filter(events_dsl::project_id.eq(project.id))
+                .filter(events_dsl::clock.ge(msg.since))
+                .order((events_dsl::clock.asc(), events_dsl::id.asc()))
+                .load::<models::Event>(connection)?;
+            let in_progress_since = match load_last_punch(connection, project.id)? {
+                Some(ref event) if event.event_type == models::EventType::In => Some(event.clock),
+                _ => None,
+            };
+            Ok(EventsSince {
+                server_time,
+                events,
+                in_progress_since,
+            })
+        })
+    }
+}
+
+//////////////////////////////////////////////////////////////////////
+// SearchNotes
+//////////////////////////////////////////////////////////////////////
+
+/// The maximum number of events returned by a note search.
+const MAX_SEARCH_RESULTS: i64 = 100;
+
+/// Find a user's events with notes containing the given text, newest first.  Matching is
+/// case-insensitive.
+pub struct SearchNotes {
+    pub username: String,
+    pub query: String,
+}
+impl Message for SearchNotes {
+    type Result = Result<Vec<models::Event>, DatabaseError>;
+}
+impl Handler<SearchNotes> for DbExecutor {
+    type Result = Result<Vec<models::Event>, DatabaseError>;
+
+    fn handle(&mut self, msg: SearchNotes, _: &mut Self::Context) -> Self::Result {
+        use self::schema::events::dsl as events_dsl;
+        let connection: &SqliteConnection = &self.0.get().unwrap();
+        let user = load_user(connection, &msg.username)?;
+        let project = load_project_for_user(connection, user.id)?;
+
+        // SQLite's LIKE is case-insensitive for ASCII.  Escape the wildcard characters so the query
+        // is matched literally.
+        let pattern = format!(
+            "%{}%",
+            msg.query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        events_dsl::events
+            .filter(events_dsl::project_id.eq(project.id))
+            .filter(events_dsl::note.like(pattern).escape('\\'))
+            .order((events_dsl::clock.desc(), events_dsl::id.desc()))
+            .limit(MAX_SEARCH_RESULTS)
+            .load::<models::Event>(connection)
+            .map_err(|e| e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Local, NaiveDateTime, NaiveTime, Timelike};
+    use diesel::query_builder::QueryFragment;
+    use models::EventType;
+
+    /// Set up an in-memory database with the given administrator.
+    fn setup(username: &str) -> DbPool {
+        let pool = database_pool(IN_MEMORY_DATABASE, DEFAULT_DB_CONNECTIONS);
+        database_setup(&pool, username, "password123").unwrap();
+        pool
+    }
+
+    /// The local date/time at the given hour and minute of the day the given number of days ago.
+    fn days_ago_at(days: i64, hour: u32, minute: u32) -> NaiveDateTime {
+        let date = Local::now().naive_local().date() - Duration::days(days);
+        NaiveDateTime::new(date, NaiveTime::from_hms(hour, minute, 0))
+    }
+
+    /// Begin each of the project's days at the given hour.
+    fn set_day_start_hour(pool: &DbPool, hour: i32) {
+        let changes = models::ProjectChanges {
+            day_start_hour: Some(hour),
+            ..Default::default()
+        };
+        configure_project(pool, &changes).unwrap();
+    }
+
+    /// Add an event at the given local time.
+    fn add_event_at(pool: &DbPool, event_type: EventType, at: NaiveDateTime) {
+        let clock = to_utc(&at, Disambiguate::Earlier);
+        add_event(pool, event_type, clock, None, None).unwrap();
+    }
+
+    /// Send a message to a database actor running on the given pool, and wait for its result.
+    fn send<M>(pool: &DbPool, message: M) -> M::Result
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        DbExecutor: Handler<M>,
+    {
+        let mut system = System::new("test");
+        let pool = pool.clone();
+        let report_cache = Arc::new(ReportCache::new());
+        let addr = SyncArbiter::start(1, move || {
+            DbExecutor(pool.clone(), report_cache.clone(), None)
+        });
+        system.block_on(addr.send(message)).unwrap()
+    }
+
+    #[test]
+    fn setup_creates_an_administrator_with_a_project() {
+        let pool = setup("alice");
+        {
+            let connection = pool.get().unwrap();
+            let user = load_user(&connection, "Alice").unwrap();
+            assert_eq!(user.name, "alice");
+            assert_eq!(user.role, models::Role::Admin);
+            let project = load_project_for_user(&connection, user.id).unwrap();
+            assert_eq!(project.name, "Project");
+        }
+        match database_setup(&pool, "bob", "password123") {
+            Err(DatabaseError::AlreadyInitialized) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn in_memory_databases_are_separate() {
+        let _alice = setup("alice");
+        let _bob = setup("bob");
+    }
+
+    #[test]
+    fn configure_default_overhead_refuses_a_negative_overhead() {
+        let pool = setup("alice");
+        match configure_default_overhead(&pool, Some(-5)) {
+            Err(DatabaseError::BadDefaultOverhead) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(configure_default_overhead(&pool, None).unwrap(), 15);
+        assert_eq!(configure_default_overhead(&pool, Some(0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn configure_flash_max_age_refuses_a_negative_age() {
+        let pool = setup("alice");
+        match configure_flash_max_age(&pool, Some(-1)) {
+            Err(DatabaseError::BadFlashMaxAge) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // The configuration is left readable and unchanged.
+        let max_age = configure_flash_max_age(&pool, None).unwrap();
+        assert_eq!(configure_flash_max_age(&pool, Some(0)).unwrap(), 0);
+        assert!(max_age > 0);
+    }
+
+    #[test]
+    fn replacing_a_day_twice_changes_nothing() {
+        let pool = setup("alice");
+        let replace = |events| ReplaceDayEvents {
+            username: "alice".to_string(),
+            date: days_ago_at(3, 0, 0).date(),
+            events,
+        };
+        let punches = || {
+            vec![
+                (
+                    EventType::In,
+                    days_ago_at(3, 9, 0),
+                    Some("Start".to_string()),
+                ),
+                (EventType::Out, days_ago_at(3, 17, 0), None),
+            ]
+        };
+        let event_count = || {
+            let connection = pool.get().unwrap();
+            schema::events::table
+                .count()
+                .get_result::<i64>(&connection)
+                .unwrap()
+        };
+
+        send(&pool, replace(punches())).unwrap();
+        send(&pool, replace(punches())).unwrap();
+        assert_eq!(event_count(), 2);
+
+        let mut with_note = punches();
+        with_note.push((
+            EventType::Note,
+            days_ago_at(3, 12, 0),
+            Some("Lunch".to_string()),
+        ));
+        match send(&pool, replace(with_note)) {
+            Err(DatabaseError::NoteInReplacement) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(event_count(), 2);
+    }
+
+    #[test]
+    fn replacing_a_day_reports_its_changes_for_the_audit_log() {
+        let pool = setup("alice");
+        add_event_at(&pool, EventType::In, days_ago_at(3, 8, 0));
+        add_event_at(&pool, EventType::Out, days_ago_at(3, 16, 0));
+        let connection = pool.get().unwrap();
+        let user = load_user(&connection, "alice").unwrap();
+        let project = load_project_for_user(&connection, user.id).unwrap();
+        let old_ids: Vec<i64> = schema::events::table
+            .select(schema::events::id)
+            .order(schema::events::clock)
+            .load(&connection)
+            .unwrap();
+
+        let changes = replace_day_events_checked(
+            &connection,
+            &project,
+            days_ago_at(3, 0, 0).date(),
+            vec![
+                (EventType::In, days_ago_at(3, 9, 0), None),
+                (EventType::Out, days_ago_at(3, 17, 0), None),
+            ],
+        )
+        .unwrap();
+        let new_ids: Vec<i64> = schema::events::table
+            .select(schema::events::id)
+            .order(schema::events::clock)
+            .load(&connection)
+            .unwrap();
+        let changes: Vec<String> = changes
+            .iter()
+            .map(|(action, id)| format!("{:?} {}", action, id))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                format!("Delete {}", old_ids[0]),
+                format!("Delete {}", old_ids[1]),
+                format!("PunchIn {}", new_ids[0]),
+                format!("PunchOut {}", new_ids[1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn backup_is_a_database_written_privately() {
+        let pool = setup("alice");
+        let backup = send(&pool, BackupDatabase {}).unwrap();
+        assert!(backup.starts_with(b"SQLite format 3\0"));
+
+        let dir = private_temp_dir().unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        let other = private_temp_dir().unwrap();
+        assert!(other != dir);
+        fs::remove_dir(&dir).unwrap();
+        fs::remove_dir(&other).unwrap();
+    }
+
+    #[test]
+    fn migrating_refuses_usernames_which_differ_only_in_case() {
+        use diesel::connection::SimpleConnection;
+
+        // A database from before usernames were matched without regard to case.
+        let pool = setup("alice");
+        let connection = pool.get().unwrap();
+        connection
+            .batch_execute(
+                "DROP INDEX idx_users_name_nocase;
+                 INSERT INTO users (name, role, time_display, duration_format)
+                 SELECT 'Alice', role, time_display, duration_format FROM users;",
+            )
+            .unwrap();
+        match database_migrate(&connection) {
+            Err(DatabaseError::DuplicateUsernames(ref names)) if names == "alice, Alice" => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        connection
+            .batch_execute("UPDATE users SET name = 'Alice2' WHERE name = 'Alice'")
+            .unwrap();
+        check_usernames_differ_in_case(&connection).unwrap();
+    }
+
+    #[test]
+    fn creating_a_user_refuses_a_name_taken_by_a_project() {
+        let pool = setup("alice");
+        rename_project(&pool, "bob").unwrap();
+        let create = |name: &str| CreateUser {
+            name: name.to_string(),
+            password: "password123".to_string(),
+            role: models::Role::Worker,
+        };
+        match send(&pool, create("bob")) {
+            Err(DatabaseError::ProjectExists(ref name)) if name == "bob" => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        send(&pool, create("carol")).unwrap();
+    }
+
+    #[test]
+    fn punch_command_alternates_directions() {
+        let pool = setup("alice");
+        let punch = |direction, at| PunchCommand {
+            username: "alice".to_string(),
+            direction,
+            note: None,
+            at: Some(at),
+        };
+        send(&pool, punch(PunchDirection::In, days_ago_at(1, 9, 0))).unwrap();
+        match send(&pool, punch(PunchDirection::In, days_ago_at(1, 10, 0))) {
+            Err(DatabaseError::BadState) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        send(&pool, punch(PunchDirection::Out, days_ago_at(1, 11, 30))).unwrap();
+
+        let connection = pool.get().unwrap();
+        let user = load_user(&connection, "alice").unwrap();
+        let project = load_project_for_user(&connection, user.id).unwrap();
+        let last_punch = load_last_punch(&connection, project.id).unwrap().unwrap();
+        assert_eq!(last_punch.event_type, models::EventType::Out);
+        assert_eq!(to_local(&last_punch.clock), days_ago_at(1, 11, 30));
+    }
+
+    #[test]
+    fn summary_report_totals_a_day() {
+        let pool = setup("alice");
+        let clock = |hour, minute| to_utc(&days_ago_at(1, hour, minute), Disambiguate::Earlier);
+        add_event(&pool, models::EventType::In, clock(9, 0), None, None).unwrap();
+        add_event(&pool, models::EventType::Out, clock(11, 30), None, None).unwrap();
+
+        let connection = pool.get().unwrap();
+        let user = load_user(&connection, "alice").unwrap();
+        let project = load_project_for_user(&connection, user.id).unwrap();
+        let settings = ReportSettings {
+            overhead: 0,
+            days: Some(2),
+            ..ReportSettings::from_project(&project)
+        };
+        let report = ::report::summary_report(&connection, &project, &settings).unwrap();
+        assert_eq!(report.next_direction, PunchDirection::In);
+        let yesterday = days_ago_at(1, 0, 0).date();
+        let (_, work_time) = report
+            .days
+            .iter()
+            .find(|(date, _)| *date == yesterday)
+            .unwrap();
+        assert_eq!(work_time.gross.0, Duration::minutes(150));
+        assert_eq!(work_time.net.0, Duration::minutes(150));
+    }
+
+    #[test]
+    fn day_sessions_follow_the_day_start_hour() {
+        let pool = setup("alice");
+        set_day_start_hour(&pool, 4);
+        // Before 4am, it's still the previous day.
+        add_event_at(&pool, EventType::In, days_ago_at(3, 3, 0));
+        add_event_at(&pool, EventType::Out, days_ago_at(3, 3, 30));
+        // The whole of a session through the night belongs to the day on which it began.
+        add_event_at(&pool, EventType::In, days_ago_at(3, 23, 0));
+        add_event_at(&pool, EventType::Out, days_ago_at(2, 2, 0));
+        add_event_at(&pool, EventType::In, days_ago_at(2, 4, 0));
+        add_event_at(&pool, EventType::Out, days_ago_at(2, 5, 0));
+
+        let sessions = send(
+            &pool,
+            GetDaySessions {
+                username: "alice".to_string(),
+                date: days_ago_at(3, 0, 0).date(),
+            },
+        )
+        .unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].start, days_ago_at(3, 23, 0));
+        assert_eq!(sessions[0].end, days_ago_at(2, 2, 0));
+        assert_eq!(sessions[0].work_time.gross.0, Duration::hours(3));
+    }
+
+    #[test]
+    fn day_status_follows_the_day_start_hour() {
+        let pool = setup("alice");
+        let now = Local::now().naive_local();
+        let day_start = now.date().and_hms(now.hour(), 0, 0);
+        set_day_start_hour(&pool, now.hour() as i32);
+        // An hour of work which ended before the day began isn't counted today.
+        add_event_at(&pool, EventType::In, day_start - Duration::minutes(90));
+        add_event_at(&pool, EventType::Out, day_start - Duration::minutes(30));
+        add_event_at(&pool, EventType::In, day_start);
+
+        let status = send(
+            &pool,
+            GetDayStatus {
+                username: "alice".to_string(),
+            },
+        )
+        .unwrap();
+        assert!(status.punched_in);
+        assert_eq!(
+            status.since,
+            Some(to_utc(&day_start, Disambiguate::Earlier))
+        );
+        assert!(status.gross_today < 60 * 60);
+    }
+
+    #[test]
+    fn replacing_a_day_follows_the_day_start_hour() {
+        let pool = setup("alice");
+        set_day_start_hour(&pool, 4);
+        add_event_at(&pool, EventType::In, days_ago_at(3, 3, 0));
+        add_event_at(&pool, EventType::Out, days_ago_at(3, 3, 30));
+        add_event_at(&pool, EventType::In, days_ago_at(2, 1, 0));
+        add_event_at(&pool, EventType::Out, days_ago_at(2, 2, 0));
+        let replace = |events| ReplaceDayEvents {
+            username: "alice".to_string(),
+            date: days_ago_at(3, 0, 0).date(),
+            events,
+        };
+
+        // 3:45am on the calendar date is still the previous day.
+        match send(
+            &pool,
+            replace(vec![
+                (EventType::In, days_ago_at(3, 3, 45), None),
+                (EventType::Out, days_ago_at(3, 5, 0), None),
+            ]),
+        ) {
+            Err(DatabaseError::NotOnDay(..)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // 3am the next morning is still the day, and the punches after midnight are replaced.
+        send(
+            &pool,
+            replace(vec![
+                (EventType::In, days_ago_at(3, 9, 0), None),
+                (EventType::Out, days_ago_at(2, 3, 0), None),
+            ]),
+        )
+        .unwrap();
+        use self::schema::events::dsl as events_dsl;
+        let connection = pool.get().unwrap();
+        let clocks: Vec<NaiveDateTime> = events_dsl::events
+            .order(events_dsl::clock)
+            .load::<models::Event>(&connection)
+            .unwrap()
+            .iter()
+            .map(|event| to_local(&event.clock))
+            .collect();
+        assert_eq!(
+            clocks,
+            vec![
+                days_ago_at(3, 3, 0),
+                days_ago_at(3, 3, 30),
+                days_ago_at(3, 9, 0),
+                days_ago_at(2, 3, 0),
+            ]
+        );
+    }
+
+    /// A punch-in at the given time.
+    fn punch_in_at(clock: UtcDateTime) -> models::Event {
+        models::Event {
+            id: 1,
+            project_id: 1,
+            event_type: EventType::In,
+            clock,
+            note: None,
+            minutes: None,
+        }
+    }
+
+    #[test]
+    fn punch_clock_refuses_a_clock_which_went_backward() {
+        let now = UtcDateTime::now();
+        let last_punch = punch_in_at(UtcDateTime(now.0 + Duration::minutes(5)));
+        match punch_clock(now, None, Some(&last_punch)) {
+            Err(DatabaseError::TimeRegression(clock)) => assert_eq!(clock, last_punch.clock),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn punch_clock_follows_the_last_punch() {
+        let now = UtcDateTime::now();
+        let last_punch = punch_in_at(UtcDateTime(now.0 - Duration::minutes(5)));
+        assert_eq!(punch_clock(now, None, Some(&last_punch)).unwrap(), now);
+        assert_eq!(punch_clock(now, None, None).unwrap(), now);
+    }
+
+    #[test]
+    fn concurrent_writes_wait_for_the_lock() {
+        use diesel::connection::SimpleConnection;
+        use std::thread;
+        use std::time::{Duration as StdDuration, Instant};
+
+        let path = env::temp_dir().join(format!("punch-test-{}-concurrent.db", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let pool = database_pool(&path, 2);
+        database_setup(&pool, "alice", "password123").unwrap();
+        let project_id = {
+            let connection = pool.get().unwrap();
+            let user = load_user(&connection, "alice").unwrap();
+            load_project_for_user(&connection, user.id).unwrap().id
+        };
+        let note = move |connection: &SqliteConnection, text: &str| {
+            diesel::insert_into(schema::events::table)
+                .values(&models::NewEvent {
+                    project_id,
+                    event_type: EventType::Note,
+                    clock: UtcDateTime::now(),
+                    note: Some(text.to_string()),
+                    minutes: None,
+                })
+                .execute(connection)
+        };
+
+        // While one connection holds the write lock, a write on another waits its turn rather
+        // than failing with "database is locked".
+        let first = pool.get().unwrap();
+        first.batch_execute("BEGIN IMMEDIATE").unwrap();
+        note(&first, "first").unwrap();
+        let second_pool = pool.clone();
+        let started = Instant::now();
+        let second = thread::spawn(move || {
+            let connection = second_pool.get().unwrap();
+            note(&connection, "second").map(|_| started.elapsed())
+        });
+        thread::sleep(StdDuration::from_millis(200));
+        first.batch_execute("COMMIT").unwrap();
+        let waited = second.join().unwrap().unwrap();
+        assert!(waited >= StdDuration::from_millis(200));
+
+        let notes = schema::events::table
+            .filter(schema::events::event_type.eq(EventType::Note))
+            .count()
+            .get_result::<i64>(&first)
+            .unwrap();
+        assert_eq!(notes, 2);
+        // The write-ahead log is kept beside the database while it's open.
+        assert!(fs::metadata(format!("{}-wal", path)).is_ok());
+
+        drop(first);
+        drop(pool);
+        for suffix in &["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    /// The details of the steps of SQLite's plan for the given query.
+    fn query_plan<Q: QueryFragment<Sqlite>>(
+        connection: &SqliteConnection,
+        query: &Q,
+    ) -> Vec<String> {
+        use diesel::deserialize::{self, QueryableByName};
+        use diesel::row::NamedRow;
+
+        /// One step of SQLite's query plan.
+        struct PlanStep {
+            detail: String,
+        }
+
+        impl QueryableByName<Sqlite> for PlanStep {
+            fn build<R: NamedRow<Sqlite>>(row: &R) -> deserialize::Result<Self> {
+                Ok(PlanStep {
+                    detail: row.get::<Text, String>("detail")?,
+                })
+            }
+        }
+
+        // The query's parameters are left unbound, which doesn't change the plan.
+        let query = diesel::debug_query::<Sqlite, _>(query).to_string();
+        let query = query.split(" -- binds").next().unwrap();
+        diesel::sql_query(format!("EXPLAIN QUERY PLAN {}", query))
+            .load::<PlanStep>(connection)
+            .unwrap()
+            .into_iter()
+            .map(|step| step.detail)
+            .collect()
+    }
+
+    #[test]
+    fn report_query_uses_the_project_clock_index() {
+        use self::schema::events::dsl as events_dsl;
+
+        // The summary report loads a project's events from a given time onward, in clock order.
+        let query = events_dsl::events
+            .filter(events_dsl::project_id.eq(1))
+            .filter(events_dsl::clock.ge(UtcDateTime::now()))
+            .order(events_dsl::clock);
+        let pool = setup("alice");
+        let plan = query_plan(&pool.get().unwrap(), &query);
+        assert!(
+            plan.iter()
+                .any(|step| step.contains("USING INDEX idx_events_project_clock")),
+            "{:?}",
+            plan
+        );
+        // The index supplies the clock order, so no separate sort is needed.
+        assert!(!plan.iter().any(|step| step.contains("TEMP B-TREE")));
+    }
+
+    #[test]
+    fn users_are_found_by_the_case_insensitive_index() {
+        let query = schema::users::table.filter(user_named("Alice"));
+        let pool = setup("alice");
+        let plan = query_plan(&pool.get().unwrap(), &query);
+        assert!(
+            plan.iter()
+                .any(|step| step.contains("USING INDEX idx_users_name_nocase")),
+            "{:?}",
+            plan
+        );
     }
 }
@@ -2,31 +2,40 @@ use actix_web;
 use actix_web::http::Cookie;
 use actix_web::middleware::{Middleware, Response, Started};
 use actix_web::{HttpRequest, HttpResponse};
+use cookie::SameSite;
+use libtime;
 use serde_json;
 
 use std::time::SystemTime;
 
 static FLASH_COOKIE_NAME: &str = "flash";
 static FLASH_COOKIE_PATH: &str = "/";
-static MAX_ELAPSED_SECS: u64 = 60;
 
 /// Middleware to manage "flash" messages that allow errors to be displayed to the user after a
 /// redirect.  This isn't a watertight solution, but the need may go away in the future if Punch is
 /// migrated to full-AJAX with a proper web API.
-pub struct FlashService {}
+pub struct FlashService {
+    /// Only send the cookie over HTTPS.
+    secure: bool,
+    /// How many seconds a message may wait to be shown before it is considered stale.
+    max_age: u64,
+}
 
 impl FlashService {
-    pub fn new() -> FlashService {
-        FlashService {}
+    pub fn new(secure: bool, max_age: u64) -> FlashService {
+        FlashService { secure, max_age }
     }
 
     fn parse_cookie<S>(&self, request: &HttpRequest<S>) -> Option<Message> {
         let cookie = request.cookie(FLASH_COOKIE_NAME)?;
         let message: Message = serde_json::from_str(cookie.value()).ok()?;
 
-        // Enforce freshness
-        if message.time.elapsed().ok()?.as_secs() > MAX_ELAPSED_SECS {
-            return None;
+        // Enforce freshness.  If the clock has moved backward since the message was set, its age
+        // is unknown, so it is shown rather than silently dropped.
+        if let Ok(elapsed) = message.time.elapsed() {
+            if elapsed.as_secs() > self.max_age {
+                return None;
+            }
         }
 
         Some(message)
@@ -46,29 +55,35 @@ impl<S> Middleware<S> for FlashService {
         req: &HttpRequest<S>,
         mut response: HttpResponse,
     ) -> actix_web::error::Result<Response> {
-        match req.extensions().get::<Message>() {
-            Some(message) => {
-                if message.delete {
-                    // Actually deleting a cookie from the browser is problematic, but this should
-                    // at least invalidate it.
-                    let mut cookie = Cookie::named(FLASH_COOKIE_NAME);
-                    cookie.set_path(FLASH_COOKIE_PATH);
-                    response.add_cookie(&cookie)?;
-                } else if message.create {
-                    // This message is newly created, so add a fresh cookie.
-                    let json = serde_json::to_string(message)?;
-                    let mut cookie = Cookie::new(FLASH_COOKIE_NAME, json);
-                    cookie.set_path(FLASH_COOKIE_PATH);
-                    response.add_cookie(&cookie)?;
-                }
+        if let Some(message) = req.extensions().get::<Message>() {
+            if message.delete {
+                // A zero max-age and an expiry in the past tell the browser to remove the cookie.
+                let mut cookie = Cookie::named(FLASH_COOKIE_NAME);
+                cookie.set_max_age(libtime::Duration::zero());
+                cookie.set_expires(libtime::at_utc(libtime::Timespec::new(0, 0)));
+                response.add_cookie(&flash_cookie(cookie, self.secure))?;
+            } else if message.create {
+                // This message is newly created, so add a fresh cookie.
+                let json = serde_json::to_string(message)?;
+                let cookie = Cookie::new(FLASH_COOKIE_NAME, json);
+                response.add_cookie(&flash_cookie(cookie, self.secure))?;
             }
-            None => {}
         }
 
         Ok(Response::Done(response))
     }
 }
 
+/// Apply the attributes shared by the flash cookie and its removal.  Without an explicit
+/// `SameSite=Lax`, some browsers drop the cookie when it is set on the redirect after a form post.
+fn flash_cookie(mut cookie: Cookie, secure: bool) -> Cookie {
+    cookie.set_path(FLASH_COOKIE_PATH);
+    cookie.set_same_site(SameSite::Lax);
+    cookie.set_http_only(true);
+    cookie.set_secure(secure);
+    cookie
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Message {
     time: SystemTime,
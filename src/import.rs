@@ -0,0 +1,230 @@
+use std::fs;
+use std::io;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use models::EventType;
+use time::{to_utc, Disambiguate, UtcDateTime};
+
+/// The header line of the CSV format, which is skipped if present.
+const CSV_HEADER: [&str; 3] = ["event_type", "clock_utc", "note"];
+
+/// The format of the `clock_utc` column.  RFC 3339 timestamps are also accepted.
+const CLOCK_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+/// The columns of a Toggl export which are imported, in the order used by `read_toggl_csv`.  The
+/// exports have other columns too, which are ignored.
+const TOGGL_COLUMNS: [&str; 5] = [
+    "Start date",
+    "Start time",
+    "End date",
+    "End time",
+    "Description",
+];
+
+/// The format of the date and time columns of a Toggl export, joined by a space.
+const TOGGL_DATE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Fail, Debug)]
+pub enum ImportError {
+    #[fail(display = "Unable to read the import file: {}", _0)]
+    Io(io::Error),
+    #[fail(display = "Row {}: {}", _0, _1)]
+    BadRow(usize, String),
+}
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> ImportError {
+        ImportError::Io(e)
+    }
+}
+
+/// An event read from an import file, along with the row it came from so that problems can be
+/// reported in terms the user can find in the file.
+pub struct ImportRow {
+    pub row: usize,
+    pub event_type: EventType,
+    pub clock: UtcDateTime,
+    pub note: Option<String>,
+}
+
+/// Read events from a CSV file with the columns `event_type,clock_utc,note`.  The rows are returned
+/// in file order.
+pub fn read_csv(path: &str) -> Result<Vec<ImportRow>, ImportError> {
+    let text = fs::read_to_string(path)?;
+    let mut rows = Vec::new();
+    for (row, fields) in split_records(&text)? {
+        if rows.is_empty() && fields == CSV_HEADER {
+            continue;
+        }
+        rows.push(parse_row(row, &fields)?);
+    }
+    Ok(rows)
+}
+
+/// Read time entries from a CSV file exported from Toggl, which are found by the names in its header
+/// line.  Each entry becomes a punch-in at its local start time, with the description as its note,
+/// and a punch-out at its local end time.  The events are returned in chronological order, and
+/// entries which overlap are rejected.
+pub fn read_toggl_csv(path: &str) -> Result<Vec<ImportRow>, ImportError> {
+    let text = fs::read_to_string(path)?;
+    let mut records = split_records(&text)?.into_iter();
+    let (header_row, header) = records
+        .next()
+        .ok_or_else(|| ImportError::BadRow(1, "Missing the header line".to_string()))?;
+    let columns = TOGGL_COLUMNS
+        .iter()
+        .map(|name| {
+            header
+                .iter()
+                .position(|h| h.trim_start_matches('\u{feff}').trim() == *name)
+                .ok_or_else(|| {
+                    ImportError::BadRow(header_row, format!("Missing the \"{}\" column", name))
+                })
+        })
+        .collect::<Result<Vec<usize>, ImportError>>()?;
+
+    let mut entries = Vec::new();
+    for (row, fields) in records {
+        let field = |i: usize| fields.get(columns[i]).map(|f| f.trim()).unwrap_or("");
+        let parse_local = |date: &str, time: &str| {
+            NaiveDateTime::parse_from_str(&format!("{} {}", date, time), TOGGL_DATE_TIME_FORMAT)
+                .map(|t| to_utc(&t, Disambiguate::Earlier))
+                .map_err(|_| {
+                    ImportError::BadRow(row, format!("Cannot parse time \"{} {}\"", date, time))
+                })
+        };
+        let start = parse_local(field(0), field(1))?;
+        let end = parse_local(field(2), field(3))?;
+        if end <= start {
+            return Err(ImportError::BadRow(
+                row,
+                "The entry doesn't end after it starts".to_string(),
+            ));
+        }
+        let note = match field(4) {
+            "" => None,
+            description => Some(description.to_string()),
+        };
+        entries.push((row, start, end, note));
+    }
+
+    entries.sort_by_key(|&(_, start, _, _)| start);
+    for pair in entries.windows(2) {
+        if pair[1].1 < pair[0].2 {
+            return Err(ImportError::BadRow(
+                pair[1].0,
+                format!("The entry overlaps the entry on row {}", pair[0].0),
+            ));
+        }
+    }
+    let mut rows = Vec::new();
+    for (row, start, end, note) in entries {
+        rows.push(ImportRow {
+            row,
+            event_type: EventType::In,
+            clock: start,
+            note,
+        });
+        rows.push(ImportRow {
+            row,
+            event_type: EventType::Out,
+            clock: end,
+            note: None,
+        });
+    }
+    Ok(rows)
+}
+
+/// Interpret the fields of a single CSV record as an event.
+fn parse_row(row: usize, fields: &[String]) -> Result<ImportRow, ImportError> {
+    let bad_row = |message: String| ImportError::BadRow(row, message);
+    if fields.len() < 2 || fields.len() > 3 {
+        return Err(bad_row(format!(
+            "Expected 3 columns (event_type,clock_utc,note) but found {}",
+            fields.len()
+        )));
+    }
+    let event_type = match fields[0].trim() {
+        "in" => EventType::In,
+        "out" => EventType::Out,
+        "note" => EventType::Note,
+        t => return Err(bad_row(format!("Unsupported event type \"{}\"", t))),
+    };
+    let clock = parse_clock(fields[1].trim())
+        .ok_or_else(|| bad_row(format!("Cannot parse time \"{}\"", fields[1])))?;
+    let note = match fields.get(2) {
+        Some(note) if !note.is_empty() => Some(note.clone()),
+        _ => None,
+    };
+    Ok(ImportRow {
+        row,
+        event_type,
+        clock,
+        note,
+    })
+}
+
+/// Parse a UTC timestamp, either in our own format or as RFC 3339.
+fn parse_clock(clock: &str) -> Option<UtcDateTime> {
+    if let Ok(t) = NaiveDateTime::parse_from_str(clock, CLOCK_FORMAT) {
+        return Some(UtcDateTime(DateTime::from_utc(t, Utc)));
+    }
+    DateTime::parse_from_rfc3339(clock)
+        .ok()
+        .map(|t| UtcDateTime(t.with_timezone(&Utc)))
+}
+
+/// Split CSV text into records of fields, following RFC 4180: fields may be quoted, and quoted
+/// fields may contain commas, line breaks, and doubled quotes.  Each record is returned with the
+/// row number on which it starts, and blank lines are skipped.
+fn split_records(text: &str) -> Result<Vec<(usize, Vec<String>)>, ImportError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut row = 1;
+    let mut start_row = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                '\n' => {
+                    row += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => fields.push(field.split_off(0)),
+            '\r' => {}
+            '\n' => {
+                if !fields.is_empty() || !field.is_empty() {
+                    fields.push(field.split_off(0));
+                    records.push((start_row, fields.split_off(0)));
+                }
+                row += 1;
+                start_row = row;
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(ImportError::BadRow(
+            start_row,
+            "Unterminated quoted field".to_string(),
+        ));
+    }
+    if !fields.is_empty() || !field.is_empty() {
+        fields.push(field);
+        records.push((start_row, fields));
+    }
+    Ok(records)
+}
@@ -7,7 +7,26 @@
 //! Punch has the notion of "gross time" versus "net time", the former being the total time elapsed
 //! between punch-in and punch-out events, and the latter subtracting a fixed amount of overhead
 //! time per work session (currently 15 minutes) to account for the ramp-up period before one is
-//! productive after starting work for the day or after an interruption.
+//! productive after starting work for the day or after an interruption.  With
+//! `configure --overhead-mode per-day`, the overhead is instead subtracted once from each day's
+//! total, however many sessions the day has.
+//! New projects start with 15 minutes of overhead, which `set-config --default-overhead 10`
+//! changes for projects created afterward.
+//! A message shown after a redirect, such as a failed punch, is discarded if the browser takes
+//! more than 60 seconds to display it; `set-config --flash-seconds 300` lengthens that window.
+//! Notes are limited to 1000 characters, or another length set with `set-config --max-note-length`.
+//!
+//! A forgotten punch-out can leave a session open overnight.  With
+//! `configure --auto-split-hours N`, any completed session longer than N hours counts only its
+//! first N hours, and the report shows a warning naming the session's event ids so that the events
+//! can be corrected with "edit-event".
+//! Alternatively, `configure --idle-timeout 30` has the web server punch you out once 30 minutes
+//! pass without any requests from you, with the punch-out dated at the end of those 30 minutes.
+//!
+//! An accidental punch-in followed right away by a punch-out makes a session of a few seconds.
+//! With `configure --min-session-seconds 60`, completed sessions shorter than a minute are left out
+//! of the report's totals, with a warning.  This only affects the report: the events are kept, and
+//! the day's session list still shows them.
 //!
 //! Punch is written in [Rust](https://www.rust-lang.org/) using the [actix-web](https://actix.rs/)
 //! web framework, [Diesel](http://diesel.rs/) for database ORM, and numerous other crates.  When
@@ -22,7 +41,14 @@
 //! ```
 //! punch-web init --database-url=/path/to/punch.db myusername mypassword
 //! ```
-//! The `--database-url` argument is optional, and defaults to "punch.db" in the current directory.
+//! The `--database-url` argument is optional.  Without it, the database URL is taken from the
+//! `PUNCH_DATABASE_URL` environment variable if that is set, and is otherwise "punch.db" in the
+//! current directory.
+//! To try punch out, the "testdb" subcommand initializes a database with several weeks of random
+//! work sessions.  The same `--seed <number>` always generates the same sessions, `--random` picks
+//! a seed and shows it, and `--quiet` skips listing the sessions.
+//! Options such as `--days 365`, `--min-session-minutes`, `--max-session-minutes`, and
+//! `--weekend-percent` shape the data; see `punch-web testdb --help`.
 //!
 //! To run the web server, use the "server" subcommand:
 //! ```
@@ -31,7 +57,189 @@
 //! ```
 //! The bind address and port defaults to `127.0.0.1:8080`, the database URL defaults once again
 //! to "punch.db" in the current directory, and the path to static resources defaults to "static/"
-//! in the current directory.
+//! in the current directory.  The server won't start if the static path isn't a readable
+//! directory, unless it is missing and `--create-static-path` is given to create it.
+//! Likewise, the `PUNCH_BIND` and `PUNCH_STATIC_PATH` environment variables stand in for `--bind`
+//! and `--static-path` when they aren't given, which suits deployment under systemd or Docker.
+//! On a busier server, `--db-connections` and `--sync-threads` set the size of the database
+//! connection pool and the number of threads running queries, which are both 3 by default.  The
+//! pool must be at least as large as the number of threads, since each thread holds a connection.
+//! Directories under the static path aren't listed unless `--listing` is given, so a request for
+//! one gets a 404.
+//! When punch is served over HTTPS, such as behind a TLS-terminating proxy, pass
+//! `--secure-cookies` so that browsers never send the session cookie over plain HTTP.
+//! A proxy which serves punch under a path, such as `https://host/time/`, should pass the path on
+//! unchanged, with `--base-path /time` given to the server so that its pages, links, and
+//! redirects all fall under it.  The API is then found at paths such as `/time/api/report`.
+//! The session cookie is signed with a random key kept in the database, so recreating the database
+//! signs everyone out.  To keep sessions across databases, or share them between several servers,
+//! give `--secret-file` the path of a file holding exactly 32 random bytes, such as one made with
+//! `head -c 32 /dev/urandom > punch.key`, or set `PUNCH_SECRET` to the same 32 bytes in base64.
+//! For an audit trail which outlives corrections to the events, pass `--audit-log audit.jsonl`,
+//! and each punch, edit, and deletion made through the server is appended to that file as a line
+//! such as `{"time": "...", "username": "joe", "action": "PunchIn", "event_id": 42}`.
+//! Each line is synced to disk before the request is answered.
+//!
+//! The database is kept in SQLite's write-ahead log mode, so that the report can be read while a
+//! punch is being written.  While punch is running, recent changes live in the "punch.db-wal" and
+//! "punch.db-shm" files beside the database, which are folded back into it when the last
+//! connection closes.  To back up a database in use, copy all three files together, or use
+//! `sqlite3 punch.db ".backup copy.db"`.
+//! The web server keeps the completed sessions behind the dashboard's report in memory until a
+//! punch or edit through the server changes them, so events added from the command line while the
+//! server is running can take up to a minute to appear on the dashboard.
+//!
+//! If you forget to punch in or out, you can add the event after the fact with the "add-event"
+//! subcommand, giving the time in the local time zone:
+//! ```
+//! punch-web add-event --database-url=/path/to/punch.db --type out --time "2018-07-20 17:30"
+//! ```
+//! The event is rejected if it would result in two consecutive punch-ins or punch-outs.
+//! A time in the hour skipped when clocks spring forward is moved forward past the gap, and a time
+//! in the hour repeated when clocks fall back means the earlier occurrence unless `--dst later` is
+//! given.
+//! Existing events can be corrected with the "edit-event" and "delete-event" subcommands, or from
+//! the recent events list on the web dashboard.
+//! To start over, such as after trying out "testdb", `reset-events --yes` deletes every event
+//! while keeping the users, project, and settings.
+//! On the dashboard, a punch made a little late can be given the time it should have been made
+//! with the "Adjust time" field, as long as that time is after the most recent punch.
+//! The "check" subcommand scans the whole event log for punches which break the in/out ordering,
+//! such as two punch-ins in a row or a punch-out before any punch-in.  It lists each one with its
+//! event id and time, and exits with a failure status if any were found.
+//! The "repair" subcommand closes punch-ins which have no punch-out, such as one left behind by a
+//! crash days ago.  It plans a punch-out at 17:00 on the day of each such punch-in, or at another
+//! time of day given with `--close-at 18:30`, and lists the plan without changing anything unless
+//! `--yes` is given.  The punch-outs are added within a single transaction, with a note saying that
+//! they were added by repair.
+//!
+//! To bring over history from another time tracker, the "import" subcommand reads a CSV file with
+//! the columns `event_type,clock_utc,note`, where the event type is "in", "out", or "note" and the
+//! time is UTC, as "YYYY-MM-DD HH:MM:SS":
+//! ```
+//! punch-web import --database-url=/path/to/punch.db --dry-run events.csv
+//! ```
+//! The events are added in chronological order within a single transaction, so if any row would
+//! break the in/out ordering, the row is reported and nothing is imported.  With `--dry-run`, the
+//! file is only validated.
+//! With `--format toggl`, the file is instead a CSV export from Toggl, and each time entry is
+//! imported as a punch-in and a punch-out at its local start and end times, with the description as
+//! a note on the punch-in.  Entries which overlap are rejected.
+//!
+//! A lunch break needn't mean punching out and back in, which would deduct the overhead twice.
+//! Instead, a "break" event records a break of some minutes taken from its time onward, and
+//! the part of the break within the session is left out of the session's gross time:
+//! ```
+//! punch-web add-event --database-url=/path/to/punch.db --type break --minutes 30 \
+//!     --time "2018-07-27 12:00"
+//! ```
+//!
+//! Punch can accrue vacation time as a fraction of the net time worked.  To earn, for example, 0.04
+//! hours of vacation per hour worked, set the ratio with the "configure" subcommand, and then record
+//! vacation time as it is taken with a "vacation" event:
+//! ```
+//! punch-web configure --database-url=/path/to/punch.db --vacation-ratio 0.04
+//! punch-web add-event --database-url=/path/to/punch.db --type vacation --hours 8 \
+//!     --time "2018-07-27 09:00"
+//! ```
+//! The report shows the vacation time earned and used over the reported weeks, and the balance.
+//!
+//! Similarly, `configure --weekly-target 40` sets a goal of 40 net hours per week.  The report then
+//! shows the percentage of the target met each week, and whether the current week is on track.
+//! With `configure --daily-overtime 8`, net time beyond 8 hours in a day is reported as overtime.
+//! Weeks begin on Monday, or on another day with `configure --week-start sun`, for example.
+//! Days begin at midnight, but for shifts which run past midnight, `configure --day-start-hour 18`
+//! has each day begin at 6pm instead, so that time worked before 6pm counts toward the day before.
+//! On slow hardware, `configure --bcrypt-cost 10` makes hashing new passwords cheaper.  Existing
+//! passwords keep working, since each hash records the cost it was made with.
+//! New passwords can be hashed with Argon2 instead after `configure --password-algorithm argon2`.
+//! Passwords hashed with bcrypt still work, since each hash is checked with the algorithm which
+//! made it, and they are hashed with Argon2 the next time they are changed.
+//! The report also counts the current streak of consecutive days with some net time logged, and
+//! the longest streak so far.  With `configure --skip-weekends yes`, idle weekends are overlooked.
+//! The month-to-date and year-to-date totals are shown beneath the monthly totals.
+//! The "report" subcommand prints the report as text, or with `--format json`, as the same JSON
+//! that `/api/report` returns, for use with tools such as `jq`.
+//!
+//! The "digest" subcommand prints a plain-text summary of last week, or of the week `--week N`
+//! weeks before this one, with the net time, the days worked, and progress against the weekly
+//! target.  It is meant to be piped into a mailer from cron:
+//! ```
+//! punch-web digest | mail -s "Weekly hours" me@example.com
+//! ```
+//!
+//! The "status" subcommand prints the punch state and the net time logged today on one line,
+//! such as `IN 1h23m | today 5h10m` or `OUT | today 7h00m`, where the time after `IN` is that of
+//! the current session.  There is no trailing newline, so it can be put in a shell prompt:
+//! ```
+//! PS1='[$(punch-web status)] \$ '
+//! ```
+//!
+//! The "client" subcommand punches in or out, or shows the report, by making requests to a running
+//! web server, which is handy for punching from a shell alias:
+//! ```
+//! punch-web client in --url http://127.0.0.1:8080 --user myusername --password mypassword
+//! ```
+//! The client uses the JSON endpoints `/api/punch` and `/api/report`, which are also available to
+//! other programs after logging in.  Instead of logging in, API clients may send HTTP Basic
+//! credentials with each request, as in `curl -u myusername:mypassword`; a request without either
+//! is refused with a `WWW-Authenticate: Basic` challenge.  The full event history can be browsed a
+//! page at a time at
+//! `/events`, or fetched as JSON from `/api/events?before=<id>`.  Event notes can be searched at
+//! `/search`, or from `/api/search?q=<text>`.  The notes alone can be read as a work journal at
+//! `/notes`, or fetched a page at a time from `/api/notes?before=<id>`.  For a quick check from a
+//! phone, `/api/status` tells whether you are punched in and how much time has been logged today.
+//! A client keeping its own copy of the log can poll `/api/events?since=<time>`, with an RFC 3339
+//! UTC time such as `2024-01-15T09:00:00Z`, for the events from that time onward.  The response
+//! also gives the server's time, to pass as `since` in the next poll, and the time of the current
+//! punch-in, if any.
+//! A taskbar widget can ask `/api/next-direction` whether the next punch is `In` or `Out`.
+//! A client which can't know, such as a physical button, can `POST` to `/api/punch/toggle` to punch
+//! in or out, whichever is due, and is told which with a body such as `{"direction": "In"}`.
+//! Forms on other pages can post to `/punch/toggle` in the same way.
+//! The time worked this week on each of your projects is shown at `/projects`, or fetched as JSON
+//! from `/api/projects/summary`.
+//! The page opens with the net time logged since your first punch, and `/api/stats` gives that
+//! along with your total numbers of events and projects and the time of the first punch.
+//! Each day on the dashboard links to a list of its sessions at `/day/<date>`, which can also be
+//! fetched as JSON from `/api/day/<date>`, with the date given as `YYYY-MM-DD`.
+//! A botched day can be replaced wholesale with a `PUT` to `/api/day/<date>` of a body such as
+//! `{"events": [{"event_type": "In", "time": "2024-01-15T09:00:00", "note": null}, ...]}`, with
+//! local times.  The day's punches are replaced only if the new ones keep the in/out ordering.
+//! Only `In` and `Out` events may be given; the day's notes, vacation, and breaks are kept as
+//! they are.
+//! Durations in the JSON responses are given in the ISO 8601 format, such as `PT2H15M`.
+//! Failed API requests are answered with a body such as
+//! `{"error": "Event not found", "code": "BAD_EVENT"}` and a status suited to the failure: 404
+//! for something which doesn't exist, 409 for a conflict with the events already logged, such as
+//! punching in twice, and 422 for a time or other value which can't be accepted.
+//! The dashboard's past weeks, daily totals, and recent events can be changed in the query string,
+//! as in `/?weeks=8&days=14&events=25` for eight past weeks, the last fourteen days, and the last
+//! 25 events.  At most 104 weeks, 93 days, and 500 events can be shown.  `/api/report` takes the
+//! same parameters, and the "report" subcommand takes `--weeks` and `--events`.
+//! Above the daily totals, the dashboard lays out the current week as a row of days, from the
+//! project's first day of the week, each with its net time and a bar comparing it to the week's
+//! longest day.  `/api/report` gives the same as `week_grid`.
+//! To see what a change of overhead would do before making it, `/api/report/preview` returns
+//! the report as it would be with the `overhead` (in minutes) and `overhead_mode` (`per-session`
+//! or `per-day`) given in the query string, as in `/api/report/preview?overhead=15`.
+//! The dashboard can show the gross time, the net time, or both, and remembers the choice for each
+//! user.  The "report" subcommand takes `--show net` or `--show gross` to do the same.
+//! Durations can likewise be shown as hours and minutes, such as `7h15m`, or as decimal hours,
+//! such as `7.25h`, which suit timesheets.  The "report" subcommand takes `--decimal` for the
+//! latter.
+//! Administrators can list and create users, and change their roles, at `/admin/users`.  Besides
+//! administrators, users may be workers, who can punch and edit events, or viewers, who can see the
+//! dashboard but can't change anything.
+//! Usernames are not case-sensitive, so `Alice` can log in as `alice`, and the two can't both
+//! exist.
+//! The project can be renamed on the same page, or with `punch-web rename-project "New name"`.
+//! Administrators can also download a copy of the database from `/admin/backup`, which is taken
+//! within a single transaction and so is consistent even while the server is in use.
+//! Every form carries a token which must match the `csrf` cookie, so other sites can't submit
+//! forms on a logged-in user's behalf.  For the same reason, the session cookie is only accepted by
+//! the API's `POST` and `PUT` endpoints along with a `Content-Type: application/json` header, which
+//! other sites can't send; Basic credentials work without it.
 //!
 //! ## Ideas for future improvements
 //!
@@ -39,22 +247,19 @@
 //! A few possible ideas are:
 //!
 //! * Support adding text notes to punch-in and punch-out events.  Also support a "note" event for
-//! adding timestamped notes without punching in or out.
+//!   adding timestamped notes without punching in or out.
 //! * Support multiple projects and users.  The database schema is in place for this, but this
-//! minimally viable code currently looks for a singleton user and project.
+//!   minimally viable code currently looks for a singleton user and project.
 //! * Dates are always stored in the database as UTC, but we currently use the server's local time
-//! zone when interpreting dates.  This may or may not be the user's preferred time zone.  We
-//! should support per-user or per-project configurable time zones.
+//!   zone when interpreting dates.  This may or may not be the user's preferred time zone.  We
+//!   should support per-user or per-project configurable time zones.
 //! * A proper frontend with AJAX calls could lead to a cleaner implementation, at the expense of
-//! having to develop such frontend code.  (For example, this could avoid the hokey system of
-//! storing error messages in a cookie to survive the redirect after a form post.)
+//!   having to develop such frontend code.  (For example, this could avoid the hokey system of
+//!   storing error messages in a cookie to survive the redirect after a form post.)
 //! * Numerous per-project parameters could be added to alter time accounting.  For example:
 //!   * Configurable overhead time.
 //!   * Rounding time up, down, or to the nearest hour (or half hour, quarter hour, etc.) on a
 //!     per-session, per-week, or per-day basis.
-//!   * Accumulation of "vacation" time at specified rates to allow the user to reward himself or
-//!   herself after logging enough productive time.
-//! * A command-line interface, which could be implemented as HTTP client calls to REST endpoints.
 //! * More reports.
 //!
 //! ## License
@@ -70,8 +275,11 @@
 
 extern crate actix;
 extern crate actix_web;
+extern crate argon2;
+extern crate base64;
 extern crate bcrypt;
 extern crate clap;
+extern crate cookie;
 #[macro_use]
 extern crate diesel;
 #[macro_use]
@@ -87,31 +295,54 @@ extern crate serde;
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
-#[macro_use]
 extern crate askama;
 extern crate chrono;
 extern crate rand;
 #[macro_use]
 extern crate diesel_derive_enum;
+// Renamed to avoid clashing with our own time module.
+extern crate time as libtime;
+extern crate uuid;
 
-use clap::{App as Clap, AppSettings, Arg, SubCommand};
+use chrono::{NaiveDateTime, NaiveTime};
+use clap::{App as Clap, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 use std::process;
 
+mod audit;
+mod client;
+mod csrf;
 mod db;
 mod flash;
+mod import;
+mod metrics;
+// The Diesel derives in `models` and the `table!` declarations in `schema` nest their trait impls
+// inside named constants, which newer compilers warn about.
+#[allow(non_local_definitions)]
 mod models;
+mod password;
 mod report;
+mod request_id;
+#[allow(non_local_definitions)]
 mod schema;
 mod server;
 mod time;
+mod timing;
 
 // Possible exit codes
-const _EXIT_SUCCESS: i32 = 0;
+const EXIT_SUCCESS: i32 = 0;
 const EXIT_FAILURE: i32 = 1;
 
 const DEFAULT_DATABASE_URL: &str = "punch.db";
 const DEFAULT_BIND: &str = "127.0.0.1:8080";
+const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:8080";
 const DEFAULT_STATIC_PATH: &str = "static/";
+const EVENT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+const CLOSE_AT_FORMAT: &str = "%H:%M";
+const DEFAULT_CLOSE_AT: &str = "17:00";
+const SECRET_ENV_VAR: &str = "PUNCH_SECRET";
 
 fn main() {
     // Parse command-line arguments and dispatch
@@ -119,9 +350,16 @@ fn main() {
         .short("d")
         .long("database-url")
         .takes_value(true)
+        .env("PUNCH_DATABASE_URL")
         .default_value(DEFAULT_DATABASE_URL)
         .help("Specify the path to the database")
         .required(false);
+    let dst_arg = Arg::with_name("dst")
+        .long("dst")
+        .takes_value(true)
+        .possible_values(&["earlier", "later"])
+        .help("Which occurrence of a time repeated when clocks fall back (default earlier).")
+        .required(false);
     let app = Clap::new("Punch time-tracking tool")
         .version("0.1.0")
         .about("Punch in, punch out, and report on time usage.")
@@ -141,13 +379,446 @@ fn main() {
                 .about("Create a new Punch database populated with test data.")
                 .arg(Arg::with_name("username").required(true))
                 .arg(Arg::with_name("password").required(true))
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("Seed the random test data, so that it can be reproduced.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("random")
+                        .long("random")
+                        .conflicts_with("seed")
+                        .help("Use a random seed, which is shown so the data can be reproduced."),
+                )
+                .arg(
+                    Arg::with_name("days")
+                        .long("days")
+                        .takes_value(true)
+                        .help("The number of past days of work to generate (default 38).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("min_session")
+                        .long("min-session-minutes")
+                        .takes_value(true)
+                        .help("The minimum length of a work session (default 60).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("max_session")
+                        .long("max-session-minutes")
+                        .takes_value(true)
+                        .help("The maximum length of a work session (default 360).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("min_per_day")
+                        .long("min-minutes-per-day")
+                        .takes_value(true)
+                        .help("The minimum work time on each working day (default 420).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("weekend_percent")
+                        .long("weekend-percent")
+                        .takes_value(true)
+                        .help("The percent chance of working on a weekend day (default 30).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("quiet")
+                        .short("q")
+                        .long("quiet")
+                        .help("Don't list the generated work sessions."),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("passwd")
+                .about("Change a user's password.")
+                .arg(Arg::with_name("username").required(true))
+                .arg(Arg::with_name("password").required(true))
                 .arg(database_arg.clone()),
         )
         .subcommand(
             SubCommand::with_name("report")
                 .about("Display a summary report.")
+                .arg(
+                    Arg::with_name("weeks")
+                        .short("w")
+                        .long("weeks")
+                        .takes_value(true)
+                        .help("The number of past weeks to include (default 5).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("events")
+                        .short("e")
+                        .long("events")
+                        .takes_value(true)
+                        .help("The number of recent events to include (default 10).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["text", "json"])
+                        .default_value("text")
+                        .help("Show the report as text, or as JSON like the /api/report endpoint."),
+                )
+                .arg(
+                    Arg::with_name("show")
+                        .long("show")
+                        .takes_value(true)
+                        .possible_values(&["both", "net", "gross"])
+                        .default_value("both")
+                        .help("Show the gross time, the net time, or both in the text report."),
+                )
+                .arg(
+                    Arg::with_name("decimal")
+                        .long("decimal")
+                        .help("Write durations in the text report as decimal hours."),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("digest")
+                .about("Display a plain-text digest of one week, suitable for email.")
+                .arg(
+                    Arg::with_name("week")
+                        .long("week")
+                        .takes_value(true)
+                        .help("The number of weeks before this week (default 1, last week).")
+                        .required(false),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Display the punch state and today's time on one line, for shell prompts.")
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check the event log for punches which break the in/out ordering.")
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .about("Add punch-outs to close punch-ins which have none.")
+                .arg(
+                    Arg::with_name("close_at")
+                        .long("close-at")
+                        .takes_value(true)
+                        .default_value(DEFAULT_CLOSE_AT)
+                        .help("The time of day (HH:MM) at which to close each punch-in."),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .help("Add the planned punch-outs, rather than only listing them."),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("add-event")
+                .about("Add an event at a specific time, such as a forgotten punch.")
+                .arg(
+                    Arg::with_name("type")
+                        .short("t")
+                        .long("type")
+                        .takes_value(true)
+                        .possible_values(&["in", "out", "note", "vacation", "break"])
+                        .help("The type of event.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("time")
+                        .long("time")
+                        .takes_value(true)
+                        .help("The local time of the event, as \"YYYY-MM-DD HH:MM\".")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("note")
+                        .short("n")
+                        .long("note")
+                        .takes_value(true)
+                        .help("An optional note to attach to the event.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("hours")
+                        .long("hours")
+                        .takes_value(true)
+                        .help("The hours of vacation time used, for vacation events.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("minutes")
+                        .long("minutes")
+                        .takes_value(true)
+                        .help("The length of the break in minutes, for break events.")
+                        .required(false),
+                )
+                .arg(dst_arg.clone())
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("edit-event")
+                .about("Change the time and/or note of an existing event.")
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .help("The id of the event.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("time")
+                        .long("time")
+                        .takes_value(true)
+                        .help("The new local time of the event, as \"YYYY-MM-DD HH:MM\".")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("note")
+                        .short("n")
+                        .long("note")
+                        .takes_value(true)
+                        .help("The new note for the event.  An empty note removes the note.")
+                        .required(false),
+                )
+                .arg(dst_arg.clone())
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("delete-event")
+                .about("Delete an existing event.")
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .help("The id of the event.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Delete the event even if it leaves a dangling interval.")
+                        .required(false),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("reset-events")
+                .about("Delete all events, keeping the users, project, and settings.")
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .help("Confirm that all events should be deleted.")
+                        .required(false),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import events from a CSV file with the columns event_type,clock_utc,note.")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("The CSV file to import."),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["native", "toggl"])
+                        .default_value("native")
+                        .help("Read punch's own CSV format, or a CSV export from Toggl."),
+                )
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .help("Validate the events without writing them to the database.")
+                        .required(false),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("rename-project")
+                .about("Rename the project.")
+                .arg(
+                    Arg::with_name("name")
+                        .required(true)
+                        .help("The new name of the project."),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("configure")
+                .about("Show or change the project settings.")
+                .arg(
+                    Arg::with_name("vacation_ratio")
+                        .long("vacation-ratio")
+                        .takes_value(true)
+                        .help("Hours of vacation earned per net hour worked (0 to disable).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("weekly_target")
+                        .long("weekly-target")
+                        .takes_value(true)
+                        .help("The net hours to aim for each week (0 to disable).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("daily_overtime")
+                        .long("daily-overtime")
+                        .takes_value(true)
+                        .help("The net hours per day beyond which work is overtime (0 to disable).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("skip_weekends")
+                        .long("skip-weekends")
+                        .takes_value(true)
+                        .possible_values(&["yes", "no"])
+                        .help("Whether weekends without work time are ignored by streaks.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("overhead_mode")
+                        .long("overhead-mode")
+                        .takes_value(true)
+                        .possible_values(&["per-session", "per-day"])
+                        .help("Whether overhead is deducted from each session or once per day.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("auto_split_hours")
+                        .long("auto-split-hours")
+                        .takes_value(true)
+                        .help("Cap sessions longer than this many hours (0 to disable).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("idle_timeout")
+                        .long("idle-timeout")
+                        .takes_value(true)
+                        .help("Punch out after this many minutes without activity (0 to disable).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("min_session_seconds")
+                        .long("min-session-seconds")
+                        .takes_value(true)
+                        .help("Leave shorter sessions out of the report's totals (0 to disable).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("week_start")
+                        .long("week-start")
+                        .takes_value(true)
+                        .possible_values(&["mon", "tue", "wed", "thu", "fri", "sat", "sun"])
+                        .help("The day on which weeks begin in the report (default mon).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("day_start_hour")
+                        .long("day-start-hour")
+                        .takes_value(true)
+                        .help("The hour (0 to 23) at which each day begins in the report.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("bcrypt_cost")
+                        .long("bcrypt-cost")
+                        .takes_value(true)
+                        .help("The bcrypt cost used when hashing new passwords (4 to 31).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("password_algorithm")
+                        .long("password-algorithm")
+                        .takes_value(true)
+                        .possible_values(&["bcrypt", "argon2"])
+                        .help("The algorithm used when hashing new passwords.")
+                        .required(false),
+                )
                 .arg(database_arg.clone()),
         )
+        .subcommand(
+            SubCommand::with_name("set-config")
+                .about("Show or change the settings which apply to the whole database.")
+                .arg(
+                    Arg::with_name("default_overhead")
+                        .long("default-overhead")
+                        .takes_value(true)
+                        .help("The overhead minutes given to new projects.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("flash_seconds")
+                        .long("flash-seconds")
+                        .takes_value(true)
+                        .help("How many seconds a message shown after a redirect stays fresh.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("max_note_length")
+                        .long("max-note-length")
+                        .takes_value(true)
+                        .help("The most characters allowed in an event's note.")
+                        .required(false),
+                )
+                .arg(database_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("client")
+                .about("Punch in or out, or show the report, using a running web server.")
+                .arg(
+                    Arg::with_name("command")
+                        .possible_values(&["in", "out", "report"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .long("url")
+                        .takes_value(true)
+                        .default_value(DEFAULT_SERVER_URL)
+                        .help("The base URL of the web server.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .short("u")
+                        .long("user")
+                        .takes_value(true)
+                        .help("The username to log in with.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .takes_value(true)
+                        .help("The password to log in with.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("note")
+                        .short("n")
+                        .long("note")
+                        .takes_value(true)
+                        .help("An optional note to attach to a punch.")
+                        .required(false),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("server")
                 .about("Start the web server")
@@ -156,6 +827,7 @@ fn main() {
                         .short("b")
                         .long("bind")
                         .takes_value(true)
+                        .env("PUNCH_BIND")
                         .default_value(DEFAULT_BIND)
                         .help("Specify the ip:port for binding.")
                         .required(false),
@@ -165,10 +837,69 @@ fn main() {
                         .short("s")
                         .long("static-path")
                         .takes_value(true)
+                        .env("PUNCH_STATIC_PATH")
                         .default_value(DEFAULT_STATIC_PATH)
                         .help("Path to static resources.")
                         .required(false),
                 )
+                .arg(
+                    Arg::with_name("create_static_path")
+                        .long("create-static-path")
+                        .help("Create the static path if it doesn't exist."),
+                )
+                .arg(
+                    Arg::with_name("listing")
+                        .long("listing")
+                        .overrides_with("no_listing")
+                        .help("List directories under the static path."),
+                )
+                .arg(
+                    Arg::with_name("no_listing")
+                        .long("no-listing")
+                        .overrides_with("listing")
+                        .help("Don't list directories under the static path (the default)."),
+                )
+                .arg(
+                    Arg::with_name("base_path")
+                        .long("base-path")
+                        .takes_value(true)
+                        .env("PUNCH_BASE_PATH")
+                        .help("Serve every page under this path, such as /time behind a proxy.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("secure_cookies")
+                        .long("secure-cookies")
+                        .help("Only send cookies over HTTPS, for use behind a TLS proxy."),
+                )
+                .arg(
+                    Arg::with_name("secret_file")
+                        .long("secret-file")
+                        .takes_value(true)
+                        .help("Sign cookies with the 32-byte key in this file.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("audit_log")
+                        .long("audit-log")
+                        .takes_value(true)
+                        .help("Append a JSON line to this file for each punch, edit, and deletion.")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("db_connections")
+                        .long("db-connections")
+                        .takes_value(true)
+                        .help("The size of the database connection pool (default 3).")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("sync_threads")
+                        .long("sync-threads")
+                        .takes_value(true)
+                        .help("The number of threads running database queries (default 3).")
+                        .required(false),
+                )
                 .arg(database_arg),
         );
     let mut app_clone = app.clone();
@@ -179,17 +910,189 @@ fn main() {
             m.value_of("username").unwrap(),
             m.value_of("password").unwrap(),
         ),
-        ("testdb", Some(m)) => cmd_testdb(
-            m.value_of("database").unwrap(),
+        ("testdb", Some(m)) => {
+            let defaults = db::TestDataOptions::default();
+            cmd_testdb(
+                m.value_of("database").unwrap(),
+                m.value_of("username").unwrap(),
+                m.value_of("password").unwrap(),
+                &db::TestDataOptions {
+                    seed: if m.is_present("random") {
+                        Some(rand::random())
+                    } else {
+                        m.value_of("seed").map(|s| parse_count(Some(s), "seed", 0))
+                    },
+                    days: parse_count(m.value_of("days"), "days", defaults.days),
+                    min_session_minutes: parse_count(
+                        m.value_of("min_session"),
+                        "minimum session minutes",
+                        defaults.min_session_minutes,
+                    ),
+                    max_session_minutes: parse_count(
+                        m.value_of("max_session"),
+                        "maximum session minutes",
+                        defaults.max_session_minutes,
+                    ),
+                    min_minutes_per_day: parse_count(
+                        m.value_of("min_per_day"),
+                        "minimum minutes per day",
+                        defaults.min_minutes_per_day,
+                    ),
+                    weekend_percent: parse_count(
+                        m.value_of("weekend_percent"),
+                        "weekend percent",
+                        defaults.weekend_percent,
+                    ),
+                    quiet: m.is_present("quiet"),
+                },
+            )
+        }
+        ("passwd", Some(m)) => cmd_passwd(
+            &database_pool(m),
             m.value_of("username").unwrap(),
             m.value_of("password").unwrap(),
         ),
-        ("report", Some(m)) => cmd_report(m.value_of("database").unwrap()),
-        ("server", Some(m)) => cmd_server(
-            m.value_of("database").unwrap(),
-            m.value_of("bind").unwrap(),
-            m.value_of("static_path").unwrap(),
+        ("report", Some(m)) => cmd_report(
+            &database_pool(m),
+            m.value_of("weeks"),
+            m.value_of("events"),
+            m.value_of("format") == Some("json"),
+            match m.value_of("show") {
+                Some("net") => models::TimeDisplay::Net,
+                Some("gross") => models::TimeDisplay::Gross,
+                _ => models::TimeDisplay::Both,
+            },
+            if m.is_present("decimal") {
+                models::DurationFormat::DecimalHours
+            } else {
+                models::DurationFormat::HoursMinutes
+            },
+        ),
+        ("digest", Some(m)) => cmd_digest(&database_pool(m), m.value_of("week")),
+        ("status", Some(m)) => cmd_status(&database_pool(m)),
+        ("check", Some(m)) => cmd_check(&database_pool(m)),
+        ("repair", Some(m)) => cmd_repair(
+            &database_pool(m),
+            m.value_of("close_at").unwrap(),
+            m.is_present("yes"),
+        ),
+        ("add-event", Some(m)) => cmd_add_event(
+            &database_pool(m),
+            m.value_of("type").unwrap(),
+            m.value_of("time").unwrap(),
+            m.value_of("note"),
+            m.value_of("hours"),
+            m.value_of("minutes"),
+            parse_dst(m.value_of("dst")),
+        ),
+        ("edit-event", Some(m)) => cmd_edit_event(
+            &database_pool(m),
+            m.value_of("id").unwrap(),
+            m.value_of("time"),
+            m.value_of("note"),
+            parse_dst(m.value_of("dst")),
+        ),
+        ("delete-event", Some(m)) => cmd_delete_event(
+            &database_pool(m),
+            m.value_of("id").unwrap(),
+            m.is_present("force"),
+        ),
+        ("reset-events", Some(m)) => cmd_reset_events(&database_pool(m), m.is_present("yes")),
+        ("import", Some(m)) => cmd_import(
+            &database_pool(m),
+            m.value_of("file").unwrap(),
+            m.value_of("format").unwrap(),
+            m.is_present("dry_run"),
         ),
+        ("rename-project", Some(m)) => {
+            cmd_rename_project(&database_pool(m), m.value_of("name").unwrap())
+        }
+        ("configure", Some(m)) => cmd_configure(
+            &database_pool(m),
+            &models::ProjectChanges {
+                vacation_accrual_ratio: m
+                    .value_of("vacation_ratio")
+                    .map(|r| parse_decimal(r, "vacation ratio")),
+                weekly_target_minutes: m
+                    .value_of("weekly_target")
+                    .map(|t| parse_hours(t, "weekly target")),
+                daily_overtime_threshold_minutes: m
+                    .value_of("daily_overtime")
+                    .map(|t| parse_hours(t, "daily overtime threshold")),
+                skip_weekends: m.value_of("skip_weekends").map(|s| s == "yes"),
+                overhead_mode: m.value_of("overhead_mode").map(|mode| match mode {
+                    "per-day" => models::OverheadMode::PerDay,
+                    _ => models::OverheadMode::PerSession,
+                }),
+                auto_split_hours: m
+                    .value_of("auto_split_hours")
+                    .map(|h| parse_count(Some(h), "auto-split hours", 0)),
+                week_start: m.value_of("week_start").map(|day| match day {
+                    "tue" => models::WeekStart::Tue,
+                    "wed" => models::WeekStart::Wed,
+                    "thu" => models::WeekStart::Thu,
+                    "fri" => models::WeekStart::Fri,
+                    "sat" => models::WeekStart::Sat,
+                    "sun" => models::WeekStart::Sun,
+                    _ => models::WeekStart::Mon,
+                }),
+                idle_timeout_minutes: m
+                    .value_of("idle_timeout")
+                    .map(|t| parse_count(Some(t), "idle timeout minutes", 0)),
+                min_session_seconds: m
+                    .value_of("min_session_seconds")
+                    .map(|s| parse_count(Some(s), "minimum session seconds", 0)),
+                day_start_hour: m.value_of("day_start_hour").map(parse_hour),
+            },
+            m.value_of("bcrypt_cost")
+                .map(|c| parse_count(Some(c), "bcrypt cost", 0)),
+            m.value_of("password_algorithm")
+                .map(|algorithm| match algorithm {
+                    "argon2" => models::PasswordAlgorithm::Argon2,
+                    _ => models::PasswordAlgorithm::Bcrypt,
+                }),
+        ),
+        ("set-config", Some(m)) => cmd_set_config(
+            &database_pool(m),
+            m.value_of("default_overhead")
+                .map(|o| parse_count(Some(o), "default overhead minutes", 0)),
+            m.value_of("flash_seconds")
+                .map(|s| parse_count(Some(s), "flash message seconds", 0)),
+            m.value_of("max_note_length")
+                .map(|n| parse_count(Some(n), "maximum note length", 0)),
+        ),
+        ("client", Some(m)) => cmd_client(
+            m.value_of("url").unwrap(),
+            m.value_of("user").unwrap(),
+            m.value_of("password").unwrap(),
+            m.value_of("command").unwrap(),
+            m.value_of("note"),
+        ),
+        ("server", Some(m)) => {
+            check_static_path(
+                m.value_of("static_path").unwrap(),
+                m.is_present("create_static_path"),
+            );
+            cmd_server(
+                m.value_of("database").unwrap(),
+                m.value_of("bind").unwrap(),
+                server::PathOptions {
+                    base_path: m
+                        .value_of("base_path")
+                        .map(parse_base_path)
+                        .unwrap_or_default(),
+                    static_path: m.value_of("static_path").unwrap(),
+                    listing: m.is_present("listing"),
+                },
+                server::CookieOptions {
+                    secure: m.is_present("secure_cookies"),
+                    secret: load_secret(m.value_of("secret_file")),
+                },
+                m.value_of("audit_log"),
+                m.value_of("db_connections"),
+                m.value_of("sync_threads"),
+            )
+        }
         _ => {
             app_clone.print_help().unwrap();
             println!();
@@ -197,26 +1100,635 @@ fn main() {
         }
     };
 
-    std::process::exit(1);
+    // Each command exits with EXIT_FAILURE itself if it fails, so reaching this point means the
+    // command succeeded.
+    process::exit(EXIT_SUCCESS);
 }
 
 /// Initialize a new punch instance.
 fn cmd_init(database: &str, username: &str, password: &str) {
-    db::database_setup(database, username, password).unwrap();
+    check_database_directory(database);
+    let pool = db::database_pool(database, db::DEFAULT_DB_CONNECTIONS);
+    if let Err(e) = db::database_setup(&pool, username, password) {
+        eprintln!("{}", e);
+        process::exit(EXIT_FAILURE);
+    }
+    let path = fs::canonicalize(database)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| database.to_string());
+    println!("Created database {} with user {}.", path, username);
 }
 
 /// Initialize a new punch instance, and populate the database with random test data.
-fn cmd_testdb(database: &str, username: &str, password: &str) {
-    db::database_setup_test(database, username, password).unwrap();
+fn cmd_testdb(database: &str, username: &str, password: &str, options: &db::TestDataOptions) {
+    if let Some(seed) = options.seed {
+        if !options.quiet {
+            println!("seed: {}", seed);
+        }
+    }
+    check_database_directory(database);
+    let pool = db::database_pool(database, db::DEFAULT_DB_CONNECTIONS);
+    if let Err(e) = db::database_setup_test(&pool, username, password, options) {
+        eprintln!("{}", e);
+        process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Change a user's password.
+fn cmd_passwd(pool: &db::DbPool, username: &str, password: &str) {
+    match db::change_password(pool, username, password) {
+        Ok(()) => println!("Changed password for {}.", username),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Show the current summary report on standard output, either as text or as JSON.
+fn cmd_report(
+    pool: &db::DbPool,
+    weeks: Option<&str>,
+    events: Option<&str>,
+    json: bool,
+    time_display: models::TimeDisplay,
+    duration_format: models::DurationFormat,
+) {
+    let weeks = parse_count(weeks, "weeks", report::DEFAULT_REPORT_WEEKS);
+    let events = parse_count(events, "events", report::DEFAULT_REPORT_EVENTS);
+    match db::do_report(pool, weeks, events) {
+        Ok(ref report) if json => match serde_json::to_string(report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(EXIT_FAILURE);
+            }
+        },
+        Ok(report) => print!("{}", report.text(time_display, duration_format)),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Show the digest of one week on standard output.
+fn cmd_digest(pool: &db::DbPool, week: Option<&str>) {
+    let weeks_ago = parse_count(week, "weeks", report::DEFAULT_DIGEST_WEEKS_AGO);
+    match db::do_digest(pool, weeks_ago) {
+        Ok(digest) => print!("{}", digest),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Write a one-line status, such as "IN 1h23m | today 5h10m", without a trailing newline so that
+/// it can be substituted into a shell prompt.  The time after "IN" is that of the current session.
+fn cmd_status(pool: &db::DbPool) {
+    match db::do_status(pool) {
+        Ok(status) => {
+            let today = time::Elapsed(chrono::Duration::seconds(status.net_today));
+            match status.since {
+                Some(since) => {
+                    let session =
+                        time::Elapsed(time::UtcDateTime::now().0.signed_duration_since(since.0));
+                    print!("IN {} | today {}", session, today);
+                }
+                None => print!("OUT | today {}", today),
+            }
+            io::stdout().flush().ok();
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Report any punches which break the in/out ordering, exiting with failure if there are any.
+fn cmd_check(pool: &db::DbPool) {
+    match db::do_check(pool) {
+        Ok((count, ref problems)) if problems.is_empty() => {
+            println!("Checked {} events.  No problems were found.", count)
+        }
+        Ok((count, problems)) => {
+            for problem in &problems {
+                println!("{}.", problem);
+            }
+            eprintln!(
+                "Checked {} events.  Found {} problem{}.",
+                count,
+                problems.len(),
+                if problems.len() == 1 { "" } else { "s" }
+            );
+            process::exit(EXIT_FAILURE);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// List the punch-outs needed to close punch-ins which have none, and add them if `apply` is set.
+fn cmd_repair(pool: &db::DbPool, close_at: &str, apply: bool) {
+    let close_at = match NaiveTime::parse_from_str(close_at, CLOSE_AT_FORMAT) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Cannot parse time \"{}\": {}", close_at, e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    let dangling = match db::repair_dangling_punches(pool, close_at, apply) {
+        Ok(dangling) => dangling,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    if dangling.is_empty() {
+        println!("Every punch-in has a punch-out.  Nothing needs repair.");
+        return;
+    }
+    let mut planned = 0;
+    for d in &dangling {
+        let punch_in = format!(
+            "Event {} (punch-in at {})",
+            d.punch_in.id,
+            d.punch_in.clock.format_local(EVENT_TIME_FORMAT)
+        );
+        match d.close_at {
+            Some(clock) => {
+                planned += 1;
+                println!(
+                    "{} {} a punch-out at {}.",
+                    punch_in,
+                    if apply {
+                        "was closed with"
+                    } else {
+                        "will be closed with"
+                    },
+                    clock.format_local(EVENT_TIME_FORMAT)
+                );
+            }
+            None => println!(
+                "{} can't be closed at {}, which isn't between it and the next punch.  Try \
+                 another --close-at, or use add-event.",
+                punch_in,
+                close_at.format(CLOSE_AT_FORMAT)
+            ),
+        }
+    }
+    if apply {
+        println!(
+            "Added {} punch-out{}.",
+            planned,
+            if planned == 1 { "" } else { "s" }
+        );
+    } else if planned > 0 {
+        println!("Nothing was changed.  Run again with --yes to add the punch-outs.");
+    }
+}
+
+/// Add an event at a specific local time.
+fn cmd_add_event(
+    pool: &db::DbPool,
+    event_type: &str,
+    time: &str,
+    note: Option<&str>,
+    hours: Option<&str>,
+    minutes: Option<&str>,
+    dst: time::Disambiguate,
+) {
+    let event_type = match event_type {
+        "in" => models::EventType::In,
+        "out" => models::EventType::Out,
+        "vacation" => models::EventType::Vacation,
+        "break" => models::EventType::Break,
+        _ => models::EventType::Note,
+    };
+    let minutes = match (&event_type, hours, minutes) {
+        (models::EventType::Vacation, Some(hours), None) => Some(parse_hours(hours, "hours")),
+        (models::EventType::Vacation, _, _) => {
+            eprintln!("Vacation events require --hours.");
+            process::exit(EXIT_FAILURE);
+        }
+        (models::EventType::Break, None, Some(minutes)) => {
+            Some(parse_count(Some(minutes), "minutes", 0))
+        }
+        (models::EventType::Break, _, _) => {
+            eprintln!("Break events require --minutes.");
+            process::exit(EXIT_FAILURE);
+        }
+        (_, Some(_), _) => {
+            eprintln!("Only vacation events may be given --hours.");
+            process::exit(EXIT_FAILURE);
+        }
+        (_, _, Some(_)) => {
+            eprintln!("Only break events may be given --minutes.");
+            process::exit(EXIT_FAILURE);
+        }
+        (_, None, None) => None,
+    };
+    let clock = time::to_utc(&parse_event_time(time), dst);
+    let result = db::add_event(pool, event_type, clock, note.map(String::from), minutes);
+    match result {
+        Ok(id) => println!("Added event {}.", id),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Change the time and/or note of an existing event.
+fn cmd_edit_event(
+    pool: &db::DbPool,
+    id: &str,
+    time: Option<&str>,
+    note: Option<&str>,
+    dst: time::Disambiguate,
+) {
+    let id = parse_event_id(id);
+    let clock = time.map(|t| time::to_utc(&parse_event_time(t), dst));
+    let result = db::edit_event(pool, id, clock, note.map(String::from));
+    match result {
+        Ok(()) => println!("Updated event {}.", id),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Delete an existing event.
+fn cmd_delete_event(pool: &db::DbPool, id: &str, force: bool) {
+    let id = parse_event_id(id);
+    match db::delete_event(pool, id, force) {
+        Ok(()) => println!("Deleted event {}.", id),
+        Err(e @ db::DatabaseError::DanglingInterval(_)) => {
+            eprintln!("{}  (Use --force to delete it anyway.)", e);
+            process::exit(EXIT_FAILURE);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
 }
 
-/// Show the current summary report on standard output.
-fn cmd_report(database: &str) {
-    print!("{}", db::do_report(database).unwrap());
+/// Delete all events, once the deletion has been confirmed with `--yes`.
+fn cmd_reset_events(pool: &db::DbPool, confirmed: bool) {
+    if !confirmed {
+        eprintln!("This deletes every event.  Use --yes to confirm.");
+        process::exit(EXIT_FAILURE);
+    }
+    match db::reset_events(pool) {
+        Ok(count) => println!("Deleted {} events.", count),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Import events from a CSV file.
+fn cmd_import(pool: &db::DbPool, file: &str, format: &str, dry_run: bool) {
+    let rows = match format {
+        "toggl" => import::read_toggl_csv(file),
+        _ => import::read_csv(file),
+    };
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    match db::import_events(pool, rows, dry_run) {
+        Ok(count) if dry_run => println!("Validated {} events.  Nothing was imported.", count),
+        Ok(count) => println!("Imported {} events.", count),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Show the project settings, after applying any requested changes.
+fn cmd_configure(
+    pool: &db::DbPool,
+    changes: &models::ProjectChanges,
+    bcrypt_cost: Option<u32>,
+    password_algorithm: Option<models::PasswordAlgorithm>,
+) {
+    let minutes = |m: i32| time::Elapsed(chrono::Duration::minutes(m as i64));
+    match db::configure_bcrypt_cost(pool, bcrypt_cost) {
+        Ok(cost) => println!("Bcrypt cost: {}", cost),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+    match db::configure_password_algorithm(pool, password_algorithm) {
+        Ok(algorithm) => println!("Password hashing: {}", algorithm),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+    match db::configure_project(pool, changes) {
+        Ok(project) => {
+            println!("Vacation ratio: {}", project.vacation_accrual_ratio);
+            println!("Weekly target: {}", minutes(project.weekly_target_minutes));
+            println!(
+                "Daily overtime threshold: {}",
+                minutes(project.daily_overtime_threshold_minutes)
+            );
+            println!(
+                "Overhead: {} minutes {}",
+                project.overhead,
+                match project.overhead_mode {
+                    models::OverheadMode::PerSession => "per session",
+                    models::OverheadMode::PerDay => "per day",
+                }
+            );
+            println!(
+                "Auto-split sessions longer than: {}h",
+                project.auto_split_hours
+            );
+            println!("Week starts on: {:?}", project.week_start.weekday());
+            println!("Idle timeout: {}", minutes(project.idle_timeout_minutes));
+            println!(
+                "Minimum session length: {} seconds",
+                project.min_session_seconds
+            );
+            println!("Days start at: {:02}:00", project.day_start_hour);
+            println!(
+                "Skip weekends in streaks: {}",
+                if project.skip_weekends { "yes" } else { "no" }
+            );
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Show the database-wide settings, after applying any requested changes.
+fn cmd_set_config(
+    pool: &db::DbPool,
+    default_overhead: Option<i32>,
+    flash_seconds: Option<i32>,
+    max_note_length: Option<i32>,
+) {
+    match db::configure_default_overhead(pool, default_overhead) {
+        Ok(overhead) => println!("Default overhead for new projects: {} minutes", overhead),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+    match db::configure_flash_max_age(pool, flash_seconds) {
+        Ok(max_age) => println!("Flash messages stay fresh for: {} seconds", max_age),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+    match db::configure_max_note_length(pool, max_note_length) {
+        Ok(max_length) => println!("Maximum note length: {} characters", max_length),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+fn cmd_rename_project(pool: &db::DbPool, name: &str) {
+    match db::rename_project(pool, name) {
+        Ok(project) => println!("Renamed the project to \"{}\".", project.name),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Make sure that the static path is a readable directory before starting the server, creating it
+/// first if requested, and exit on failure.
+fn check_static_path(static_path: &str, create: bool) {
+    let path = Path::new(static_path);
+    if !path.exists() {
+        if !create {
+            eprintln!("Static path {} does not exist", static_path);
+            process::exit(EXIT_FAILURE);
+        }
+        if let Err(e) = fs::create_dir_all(path) {
+            eprintln!("Unable to create static path {}: {}", static_path, e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+    if !path.is_dir() {
+        eprintln!("Static path {} is not a directory", static_path);
+        process::exit(EXIT_FAILURE);
+    }
+    if let Err(e) = fs::read_dir(path) {
+        eprintln!("Static path {} is not readable: {}", static_path, e);
+        process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Open a pool of connections to the database given on the command line.
+fn database_pool(m: &ArgMatches) -> db::DbPool {
+    db::database_pool(m.value_of("database").unwrap(), db::DEFAULT_DB_CONNECTIONS)
+}
+
+/// Make sure that the directory which is to hold a new database exists, and exit if not.  SQLite
+/// would otherwise fail to open the database with a cryptic error.
+fn check_database_directory(database: &str) {
+    if database == db::IN_MEMORY_DATABASE {
+        return;
+    }
+    match Path::new(database).parent() {
+        Some(directory) if !directory.as_os_str().is_empty() && !directory.is_dir() => {
+            eprintln!("Directory {} does not exist.", directory.display());
+            process::exit(EXIT_FAILURE);
+        }
+        _ => {}
+    }
+}
+
+/// Load the key for signing cookies from the given file, or else from the base64 given in the
+/// `PUNCH_SECRET` environment variable, exiting on failure.  Without either, the server uses the
+/// key stored in the database.
+fn load_secret(secret_file: Option<&str>) -> Option<models::Secret> {
+    let bytes = match secret_file {
+        Some(path) => match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Unable to read secret file {}: {}", path, e);
+                process::exit(EXIT_FAILURE);
+            }
+        },
+        None => match ::std::env::var(SECRET_ENV_VAR) {
+            Ok(value) => match base64::decode(value.trim()) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    eprintln!("{} is not valid base64: {}", SECRET_ENV_VAR, e);
+                    process::exit(EXIT_FAILURE);
+                }
+            },
+            Err(_) => return None,
+        },
+    };
+    match models::Secret::from_bytes(bytes) {
+        Ok(secret) => Some(secret),
+        Err(length) => {
+            eprintln!(
+                "The secret key must be exactly {} bytes, not {}",
+                models::SECRET_KEY_SIZE,
+                length
+            );
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Parse an event time given on the command line, exiting on failure.
+fn parse_event_time(time: &str) -> NaiveDateTime {
+    match NaiveDateTime::parse_from_str(time, EVENT_TIME_FORMAT) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Cannot parse time \"{}\": {}", time, e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Parse the choice of which occurrence to use for a repeated local time.
+fn parse_dst(value: Option<&str>) -> time::Disambiguate {
+    match value {
+        Some("later") => time::Disambiguate::Later,
+        _ => time::Disambiguate::Earlier,
+    }
+}
+
+/// Parse an optional count given on the command line, exiting on failure.
+fn parse_count<T: std::str::FromStr>(value: Option<&str>, name: &str, default: T) -> T {
+    match value {
+        Some(value) => match value.parse() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("Invalid number of {}: {}", name, value);
+                process::exit(EXIT_FAILURE);
+            }
+        },
+        None => default,
+    }
+}
+
+/// Parse a non-negative decimal number given on the command line, exiting on failure.
+fn parse_decimal(value: &str, name: &str) -> f64 {
+    match value.parse::<f64>() {
+        Ok(n) if n >= 0.0 && n.is_finite() => n,
+        _ => {
+            eprintln!("Invalid {}: {}", name, value);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Parse an hour of the day, from 0 to 23, given on the command line, exiting on failure.
+fn parse_hour(value: &str) -> i32 {
+    match value.parse::<i32>() {
+        Ok(hour) if (0..24).contains(&hour) => hour,
+        _ => {
+            eprintln!("Invalid hour of the day: {}", value);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Parse the path under which the server is reached, such as "/time", exiting on failure.  Any
+/// trailing slash is removed, so that the root path becomes empty.
+fn parse_base_path(value: &str) -> String {
+    if !value.starts_with('/')
+        || value.contains(|c: char| c == '?' || c == '#' || c.is_whitespace())
+    {
+        eprintln!("Invalid base path (it should look like /time): {}", value);
+        process::exit(EXIT_FAILURE);
+    }
+    value.trim_end_matches('/').to_string()
+}
+
+/// Parse a non-negative number of hours given on the command line as a number of minutes, exiting
+/// on failure.
+fn parse_hours(value: &str, name: &str) -> i32 {
+    (parse_decimal(value, name) * 60.0).round() as i32
+}
+
+/// Parse an event id given on the command line, exiting on failure.
+fn parse_event_id(id: &str) -> i64 {
+    match id.parse() {
+        Ok(id) => id,
+        Err(_) => {
+            eprintln!("Invalid event id: {}", id);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Punch in or out, or show the report, by making requests to a running web server.
+fn cmd_client(url: &str, user: &str, password: &str, command: &str, note: Option<&str>) {
+    let command = match command {
+        "in" => client::ClientCommand::Punch(models::PunchDirection::In, note.map(String::from)),
+        "out" => client::ClientCommand::Punch(models::PunchDirection::Out, note.map(String::from)),
+        _ => client::ClientCommand::Report,
+    };
+    if let Err(e) = client::do_client(url, user, password, command) {
+        eprintln!("{}", e);
+        process::exit(EXIT_FAILURE);
+    }
 }
 
 /// Run the web server.
-fn cmd_server(database: &str, bind: &str, static_path: &str) {
+fn cmd_server(
+    database: &str,
+    bind: &str,
+    paths: server::PathOptions,
+    cookies: server::CookieOptions,
+    audit_log: Option<&str>,
+    db_connections: Option<&str>,
+    sync_threads: Option<&str>,
+) {
+    let db_connections = parse_count(
+        db_connections,
+        "database connections",
+        db::DEFAULT_DB_CONNECTIONS,
+    );
+    let sync_threads = parse_count(sync_threads, "sync threads", db::DEFAULT_SYNC_THREADS);
+    let audit_log = audit_log.map(|path| match audit::AuditLog::open(path) {
+        Ok(audit_log) => audit_log,
+        Err(e) => {
+            eprintln!("Unable to open the audit log {}: {}", path, e);
+            process::exit(EXIT_FAILURE);
+        }
+    });
     ::std::env::set_var("RUST_LOG", "actix=info,actix_web=info,punch=trace");
-    server::do_server(database, bind, static_path);
+    if let Err(e) = server::do_server(
+        database,
+        bind,
+        paths,
+        cookies,
+        audit_log,
+        db_connections,
+        sync_threads,
+    ) {
+        eprintln!("{}", e);
+        process::exit(EXIT_FAILURE);
+    }
 }
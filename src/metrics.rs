@@ -0,0 +1,78 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use models::PunchDirection;
+
+/// Counters exposed to Prometheus via the metrics endpoint.  These are shared by all of the server
+/// workers, and only live as long as the server process.
+pub struct Metrics {
+    punch_in_events: AtomicUsize,
+    punch_out_events: AtomicUsize,
+    login_failures: AtomicUsize,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            punch_in_events: AtomicUsize::new(0),
+            punch_out_events: AtomicUsize::new(0),
+            login_failures: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count a successful punch in the given direction.
+    pub fn record_punch(&self, direction: PunchDirection) {
+        let counter = match direction {
+            PunchDirection::In => &self.punch_in_events,
+            PunchDirection::Out => &self.punch_out_events,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a failed login attempt.
+    pub fn record_login_failure(&self) {
+        self.login_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.  Whether a work session is in
+    /// progress comes from the database rather than from a counter, so it is supplied by the caller.
+    pub fn render(&self, session_in_progress: bool) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP punch_events_total Number of punch events recorded by this server."
+        );
+        let _ = writeln!(out, "# TYPE punch_events_total counter");
+        let _ = writeln!(
+            out,
+            "punch_events_total{{type=\"in\"}} {}",
+            self.punch_in_events.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "punch_events_total{{type=\"out\"}} {}",
+            self.punch_out_events.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP punch_login_failures_total Number of failed login attempts."
+        );
+        let _ = writeln!(out, "# TYPE punch_login_failures_total counter");
+        let _ = writeln!(
+            out,
+            "punch_login_failures_total {}",
+            self.login_failures.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP punch_session_in_progress Whether any work session is currently in progress."
+        );
+        let _ = writeln!(out, "# TYPE punch_session_in_progress gauge");
+        let _ = writeln!(
+            out,
+            "punch_session_in_progress {}",
+            if session_in_progress { 1 } else { 0 }
+        );
+        out
+    }
+}
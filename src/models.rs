@@ -1,9 +1,13 @@
-use chrono::NaiveDateTime;
+use std::borrow::Borrow;
+use std::fmt;
+
+use chrono::Weekday;
 
 use super::schema::config;
 use super::schema::events;
 use super::schema::projects;
 use super::schema::users;
+use super::time::{Elapsed, FormattedElapsed, UtcDateTime};
 
 //////////////////////////////////////////////////////////////////////
 // Configuration
@@ -11,11 +15,37 @@ use super::schema::users;
 
 const CONFIG_FIXED_ID: i64 = 1;
 
+// When the bcrypt crate is compiled in debug, it is excruciatingly slow.  So new debug databases
+// start with a cost of 6, which is not very secure, but a more reasonable cost of 12 is used for
+// production.  The cost may be changed later with the "configure" command.
+#[cfg(debug_assertions)]
+const DEFAULT_BCRYPT_COST: u32 = 6;
+#[cfg(not(debug_assertions))]
+const DEFAULT_BCRYPT_COST: u32 = 12;
+
+/// The overhead, in minutes, given to new projects until it is changed with the "set-config" command.
+const DEFAULT_OVERHEAD_MINUTES: i32 = 15;
+
+/// How long, in seconds, a flash message survives its redirect before it is discarded as stale.
+const DEFAULT_FLASH_MAX_AGE_SECS: i32 = 60;
+
+/// The longest note, in characters, which may be attached to an event.
+const DEFAULT_MAX_NOTE_LENGTH: i32 = 1000;
+
+/// The range of costs accepted by the bcrypt crate.
+pub const MIN_BCRYPT_COST: u32 = 4;
+pub const MAX_BCRYPT_COST: u32 = 31;
+
 #[derive(Queryable, Insertable)]
 #[table_name = "config"]
 pub struct ConfigRow {
     pub id: i64, // always 1
     pub secret: Vec<u8>,
+    pub bcrypt_cost: i32,
+    pub default_overhead: i32,
+    pub flash_max_age: i32,
+    pub password_algorithm: PasswordAlgorithm,
+    pub max_note_length: i32,
 }
 
 impl ConfigRow {
@@ -23,6 +53,11 @@ impl ConfigRow {
         ConfigRow {
             id: CONFIG_FIXED_ID,
             secret: Secret::generate().into(),
+            bcrypt_cost: DEFAULT_BCRYPT_COST as i32,
+            default_overhead: DEFAULT_OVERHEAD_MINUTES,
+            flash_max_age: DEFAULT_FLASH_MAX_AGE_SECS,
+            password_algorithm: PasswordAlgorithm::Bcrypt,
+            max_note_length: DEFAULT_MAX_NOTE_LENGTH,
         }
     }
 
@@ -35,6 +70,18 @@ impl ConfigRow {
 
 pub struct Config {
     pub secret: Secret,
+    /// The cost used when hashing new passwords.  Existing hashes record their own cost, so they
+    /// can still be verified after this changes.
+    pub bcrypt_cost: u32,
+    /// The overhead, in minutes, given to new projects.
+    pub default_overhead: i32,
+    /// How long, in seconds, a flash message remains fresh after it is set.
+    pub flash_max_age: u64,
+    /// The algorithm used when hashing new passwords.  Existing hashes are verified with whichever
+    /// algorithm made them.
+    pub password_algorithm: PasswordAlgorithm,
+    /// The longest note, in characters, which may be attached to an event.
+    pub max_note_length: usize,
 }
 
 impl Config {
@@ -42,15 +89,27 @@ impl Config {
         if config_row.secret.len() != SECRET_KEY_SIZE {
             return Err(());
         }
+        let bcrypt_cost = config_row.bcrypt_cost as u32;
+        if !(MIN_BCRYPT_COST..=MAX_BCRYPT_COST).contains(&bcrypt_cost) {
+            return Err(());
+        }
+        if config_row.flash_max_age < 0 || config_row.max_note_length < 1 {
+            return Err(());
+        }
         let mut secret_key: [u8; 32] = [0; 32];
         secret_key.copy_from_slice(&config_row.secret);
         Ok(Config {
             secret: Secret { data: secret_key },
+            bcrypt_cost,
+            default_overhead: config_row.default_overhead,
+            flash_max_age: config_row.flash_max_age as u64,
+            password_algorithm: config_row.password_algorithm,
+            max_note_length: config_row.max_note_length as usize,
         })
     }
 }
 
-const SECRET_KEY_SIZE: usize = 32;
+pub const SECRET_KEY_SIZE: usize = 32;
 
 pub struct Secret {
     pub data: [u8; SECRET_KEY_SIZE],
@@ -62,6 +121,26 @@ impl Secret {
             data: ::rand::random(),
         }
     }
+
+    /// Take a secret key supplied from outside the database, such as from a key file.  The given
+    /// bytes are zeroed either way, and their length is returned if it's not the size of a key.
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Result<Secret, usize> {
+        let mut secret = Secret {
+            data: [0; SECRET_KEY_SIZE],
+        };
+        let length = bytes.len();
+        if length == SECRET_KEY_SIZE {
+            secret.data.copy_from_slice(&bytes);
+        }
+        for byte in bytes.iter_mut() {
+            *byte = 0;
+        }
+        if length == SECRET_KEY_SIZE {
+            Ok(secret)
+        } else {
+            Err(length)
+        }
+    }
 }
 
 impl Drop for Secret {
@@ -87,7 +166,11 @@ pub struct User {
     pub id: i64,
     pub name: String,
     pub password: Option<String>,
-    pub admin: bool,
+    pub role: Role,
+    /// Which of the gross and net times the user wants to see on the dashboard.
+    pub time_display: TimeDisplay,
+    /// How the user wants durations to be written on the dashboard.
+    pub duration_format: DurationFormat,
 }
 
 #[derive(Insertable)]
@@ -95,15 +178,42 @@ pub struct User {
 pub struct NewUser<'a> {
     pub name: &'a str,
     pub password: Option<&'a str>,
-    pub admin: bool,
+    pub role: Role,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Clone)]
 pub struct Project {
     pub id: i64,
     pub user_id: i64,
     pub name: String,
     pub overhead: i32,
+    /// Sessions still in progress are capped at this length, in hours.  Zero disables the cap.
+    pub max_session_hours: i32,
+    /// Hours of vacation time earned per net hour worked.  Zero disables vacation accrual.
+    pub vacation_accrual_ratio: f64,
+    /// The net time the user aims to work each week, in minutes.  Zero disables the target.
+    pub weekly_target_minutes: i32,
+    /// Net time beyond this many minutes in a day is counted as overtime.  Zero disables overtime.
+    pub daily_overtime_threshold_minutes: i32,
+    /// Weekend days without any work time don't break a streak of consecutive working days.
+    pub skip_weekends: bool,
+    /// Whether the overhead is deducted from each work session, or once from each day.
+    pub overhead_mode: OverheadMode,
+    /// Completed sessions longer than this many hours are capped, with a warning, since they are
+    /// probably missing a punch-out.  Zero disables the cap.
+    pub auto_split_hours: i32,
+    /// The day on which weeks begin, for weekly totals.
+    pub week_start: WeekStart,
+    /// While the web server is running, an open session with no requests from the user for this
+    /// many minutes is punched out automatically.  Zero disables the timeout.
+    pub idle_timeout_minutes: i32,
+    /// Completed sessions shorter than this many seconds, such as an accidental punch-in followed
+    /// by a punch-out, are left out of the report's totals.  The events themselves are kept.  Zero
+    /// disables the filtering.
+    pub min_session_seconds: i32,
+    /// The hour, from 0 to 23, at which each day begins in the report.  Time worked before this
+    /// hour counts toward the previous day, which suits shifts that run past midnight.
+    pub day_start_hour: i32,
 }
 
 #[derive(Insertable)]
@@ -114,15 +224,188 @@ pub struct NewProject<'a> {
     pub overhead: i32,
 }
 
-#[derive(DbEnum, Debug, PartialEq, Clone)]
+/// Changes to the settings of a project, as made by the "configure" command.  Settings which are
+/// `None` are left unchanged.
+#[derive(AsChangeset, Default)]
+#[table_name = "projects"]
+pub struct ProjectChanges {
+    pub vacation_accrual_ratio: Option<f64>,
+    pub weekly_target_minutes: Option<i32>,
+    pub daily_overtime_threshold_minutes: Option<i32>,
+    pub skip_weekends: Option<bool>,
+    pub overhead_mode: Option<OverheadMode>,
+    pub auto_split_hours: Option<i32>,
+    pub week_start: Option<WeekStart>,
+    pub idle_timeout_minutes: Option<i32>,
+    pub min_session_seconds: Option<i32>,
+    pub day_start_hour: Option<i32>,
+}
+
+impl ProjectChanges {
+    /// Returns true if no settings are being changed.
+    pub fn is_empty(&self) -> bool {
+        self.vacation_accrual_ratio.is_none()
+            && self.weekly_target_minutes.is_none()
+            && self.daily_overtime_threshold_minutes.is_none()
+            && self.skip_weekends.is_none()
+            && self.overhead_mode.is_none()
+            && self.auto_split_hours.is_none()
+            && self.week_start.is_none()
+            && self.idle_timeout_minutes.is_none()
+            && self.min_session_seconds.is_none()
+            && self.day_start_hour.is_none()
+    }
+}
+
+/// How the ramp-up overhead is deducted from the gross time worked.
+#[derive(DbEnum, Debug, PartialEq, Clone, Copy)]
+pub enum OverheadMode {
+    /// Deduct the overhead from every work session.
+    PerSession,
+    /// Deduct the overhead once from each day's total, however many sessions it has.
+    PerDay,
+}
+
+/// What a user is allowed to do.
+#[derive(DbEnum, Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Role {
+    /// Administrators can manage users, as well as punch and edit events.
+    Admin,
+    /// Workers can punch and edit events.
+    Worker,
+    /// Viewers can see the report, but can't make any changes.
+    Viewer,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Admin, Role::Worker, Role::Viewer];
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The algorithm used to hash passwords.
+#[derive(DbEnum, Debug, PartialEq, Clone, Copy)]
+pub enum PasswordAlgorithm {
+    Bcrypt,
+    Argon2,
+}
+
+impl fmt::Display for PasswordAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Which of the gross and net times are shown in the report.
+#[derive(DbEnum, Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum TimeDisplay {
+    Both,
+    Net,
+    Gross,
+}
+
+impl TimeDisplay {
+    pub const ALL: [TimeDisplay; 3] = [TimeDisplay::Both, TimeDisplay::Net, TimeDisplay::Gross];
+
+    pub fn show_gross(self) -> bool {
+        self != TimeDisplay::Net
+    }
+
+    pub fn show_net(self) -> bool {
+        self != TimeDisplay::Gross
+    }
+}
+
+impl fmt::Display for TimeDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// How durations are written in the report: as hours and minutes, such as "7h15m", or as decimal
+/// hours, such as "7.25h", which suits timesheets.
+#[derive(DbEnum, Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum DurationFormat {
+    HoursMinutes,
+    DecimalHours,
+}
+
+impl DurationFormat {
+    pub const ALL: [DurationFormat; 2] =
+        [DurationFormat::HoursMinutes, DurationFormat::DecimalHours];
+
+    /// Write the given duration in this format.  The templates pass some durations by reference.
+    pub fn format<E: Borrow<Elapsed>>(self, elapsed: E) -> FormattedElapsed {
+        FormattedElapsed(*elapsed.borrow(), self)
+    }
+
+    /// An example of this format, for labeling the choices.
+    pub fn example(self) -> &'static str {
+        match self {
+            DurationFormat::HoursMinutes => "7h15m",
+            DurationFormat::DecimalHours => "7.25h",
+        }
+    }
+}
+
+impl fmt::Display for DurationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The first day of the week used for weekly totals.
+#[derive(DbEnum, Debug, PartialEq, Clone, Copy)]
+pub enum WeekStart {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl WeekStart {
+    pub fn weekday(self) -> Weekday {
+        match self {
+            WeekStart::Mon => Weekday::Mon,
+            WeekStart::Tue => Weekday::Tue,
+            WeekStart::Wed => Weekday::Wed,
+            WeekStart::Thu => Weekday::Thu,
+            WeekStart::Fri => Weekday::Fri,
+            WeekStart::Sat => Weekday::Sat,
+            WeekStart::Sun => Weekday::Sun,
+        }
+    }
+}
+
+#[derive(DbEnum, Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum EventType {
     In,
     Out,
     Note,
+    /// Vacation time used, in the amount given by the event's minutes.
+    Vacation,
+    /// A break taken during a work session, lasting the event's minutes from the event's time.  The
+    /// break's time is subtracted from the session's gross time.
+    Break,
+}
+
+impl EventType {
+    /// Returns true for the punch-in and punch-out events which delimit work intervals.  Other
+    /// events don't participate in the in/out ordering.
+    pub fn is_punch(&self) -> bool {
+        *self == EventType::In || *self == EventType::Out
+    }
 }
 
 /// PunchDirection is effectively a subset of EventType that only includes in and out types.
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
 pub enum PunchDirection {
     In,
     Out,
@@ -136,19 +419,20 @@ impl From<PunchDirection> for EventType {
     }
 }
 
-#[derive(Queryable, Debug, PartialEq, Clone)]
+#[derive(Queryable, Serialize, Debug, PartialEq, Clone)]
 pub struct Event {
     pub id: i64,
     pub project_id: i64,
     pub event_type: EventType,
-    // We store date/time as UTC without a time zone (a NaiveDateTime), because that's what Diesel
-    // supports out of the box.  This is less than ideal.  In the future, this should be refactored
-    // to provide custom row deserialization to convert the database value into a DateTime
-    // reflecting UTC, to reduce the likelihood of time zone mistakes.
-    // Also, we are currently assuming the server's local time zone is the user's preferred time
-    // zone for the purposes of allocating work intervals to days and weeks.  We should instead
-    // allow per-user or per-project time zones.
-    pub clock: NaiveDateTime,
+    // The database stores date/time as UTC without a time zone, and UtcDateTime handles the
+    // conversion to a DateTime reflecting UTC.
+    // We are currently assuming the server's local time zone is the user's preferred time zone for
+    // the purposes of allocating work intervals to days and weeks.  We should instead allow
+    // per-user or per-project time zones.
+    pub clock: UtcDateTime,
+    pub note: Option<String>,
+    /// A duration associated with the event, such as the amount of vacation time used.
+    pub minutes: Option<i32>,
 }
 
 #[derive(Insertable)]
@@ -156,5 +440,7 @@ pub struct Event {
 pub struct NewEvent {
     pub project_id: i64,
     pub event_type: EventType,
-    pub clock: NaiveDateTime,
+    pub clock: UtcDateTime,
+    pub note: Option<String>,
+    pub minutes: Option<i32>,
 }
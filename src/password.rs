@@ -0,0 +1,103 @@
+use argon2;
+use bcrypt;
+use rand;
+
+use models::{Config, PasswordAlgorithm, MIN_BCRYPT_COST};
+
+/// The length of the random salt given to each Argon2 hash.
+const ARGON2_SALT_SIZE: usize = 16;
+
+/// The identifying prefixes of stored hashes.  Bcrypt hashes come in several revisions, such as
+/// "$2a$" and "$2b$", which all start the same way.
+const ARGON2_PREFIX: &str = "$argon2";
+const BCRYPT_PREFIX: &str = "$2";
+
+#[derive(Fail, Debug)]
+pub enum PasswordError {
+    #[fail(display = "{}", _0)]
+    Bcrypt(bcrypt::BcryptError),
+    #[fail(display = "{}", _0)]
+    Argon2(argon2::Error),
+    #[fail(display = "The stored password hash was made with an unknown algorithm")]
+    UnknownHash,
+}
+impl From<bcrypt::BcryptError> for PasswordError {
+    fn from(e: bcrypt::BcryptError) -> PasswordError {
+        PasswordError::Bcrypt(e)
+    }
+}
+impl From<argon2::Error> for PasswordError {
+    fn from(e: argon2::Error) -> PasswordError {
+        PasswordError::Argon2(e)
+    }
+}
+
+/// A password hashing algorithm.  Each algorithm's hashes begin with a distinct prefix, so that
+/// `verify` can tell which algorithm made a stored hash, and passwords hashed before the algorithm
+/// was changed keep working.
+pub trait PasswordHasher {
+    /// Hash a password for storage.
+    fn hash(&self, password: &str) -> Result<String, PasswordError>;
+    /// Check a password against a hash made by this algorithm.
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordError>;
+}
+
+/// Hashing with bcrypt at the given cost.  The cost is recorded in each hash, so it only matters
+/// when hashing.
+pub struct Bcrypt {
+    pub cost: u32,
+}
+
+impl PasswordHasher for Bcrypt {
+    fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        Ok(bcrypt::hash(password, self.cost)?)
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordError> {
+        Ok(bcrypt::verify(password, hash)?)
+    }
+}
+
+/// Hashing with Argon2id, using the argon2 crate's default costs.  As with bcrypt, the parameters
+/// are recorded in each hash.
+pub struct Argon2;
+
+impl PasswordHasher for Argon2 {
+    fn hash(&self, password: &str) -> Result<String, PasswordError> {
+        let salt: [u8; ARGON2_SALT_SIZE] = rand::random();
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            ..argon2::Config::default()
+        };
+        Ok(argon2::hash_encoded(password.as_bytes(), &salt, &config)?)
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordError> {
+        Ok(argon2::verify_encoded(hash, password.as_bytes())?)
+    }
+}
+
+/// The hasher for new passwords, as configured for the database.
+pub fn hasher(config: &Config) -> Box<dyn PasswordHasher> {
+    match config.password_algorithm {
+        PasswordAlgorithm::Bcrypt => Box::new(Bcrypt {
+            cost: config.bcrypt_cost,
+        }),
+        PasswordAlgorithm::Argon2 => Box::new(Argon2),
+    }
+}
+
+/// Check a password against a stored hash, with whichever algorithm made the hash.
+pub fn verify(password: &str, hash: &str) -> Result<bool, PasswordError> {
+    if hash.starts_with(ARGON2_PREFIX) {
+        Argon2.verify(password, hash)
+    } else if hash.starts_with(BCRYPT_PREFIX) {
+        // The cost is read from the hash, so any cost will do here.
+        Bcrypt {
+            cost: MIN_BCRYPT_COST,
+        }
+        .verify(password, hash)
+    } else {
+        Err(PasswordError::UnknownHash)
+    }
+}
@@ -1,7 +1,9 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration as StdDuration, Instant};
 
-use chrono::{Datelike, Duration, IsoWeek, Local, NaiveDate, Weekday};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, Weekday};
 use diesel::prelude::*;
 
 use db::{self, DatabaseError};
@@ -12,77 +14,1205 @@ use time::*;
 
 /// A summary report contains information about work activity in recent days and weeks, and is used
 /// to populate the dashboard.
+#[derive(Serialize)]
 pub struct SummaryReport {
+    /// The name of the project being reported on.
+    pub project_name: String,
     pub next_direction: PunchDirection,
     pub days: Vec<(NaiveDate, WorkTime)>,
-    pub weeks: Vec<(Week, WorkTime)>,
+    /// The days of the current week, for the dashboard's week-at-a-glance grid.
+    pub week_grid: WeekGrid,
+    pub weeks: Vec<WeekSummary>,
+    pub months: Vec<(Month, WorkTime)>,
+    /// The work time since the start of the current month, however few weeks are reported.
+    pub mtd: WorkTime,
+    /// The work time since the start of the current year.
+    pub ytd: WorkTime,
     pub recent_events: Vec<Event>,
+    /// The work session still accruing time, if the most recent punch was a punch-in.
+    pub in_progress: Option<Interval>,
+    /// Problems noticed while generating the report which the user should know about.
+    pub warnings: Vec<String>,
+    /// Vacation time earned from the net time worked during the reported weeks.
+    pub vacation_earned: Elapsed,
+    /// Vacation time used during the reported weeks.
+    pub vacation_used: Elapsed,
+    /// The net time the user aims to work each week, if a target has been set.
+    pub weekly_target: Option<Elapsed>,
+    /// Whether the current week is on pace to meet the weekly target, if a target has been set.
+    pub target_status: Option<TargetStatus>,
+    /// The daily net time beyond which work is counted as overtime, if a threshold has been set.
+    pub overtime_threshold: Option<Elapsed>,
+    /// The number of consecutive days, up to today, with some net time logged.
+    pub current_streak_days: u32,
+    /// The longest run of consecutive days with some net time logged.
+    pub longest_streak_days: u32,
+}
+
+impl SummaryReport {
+    /// The vacation time earned less the vacation time used during the reported weeks.
+    pub fn vacation_balance(&self) -> Elapsed {
+        Elapsed(self.vacation_earned.0 - self.vacation_used.0)
+    }
+
+    /// Show the split between regular time and overtime, if overtime is being tracked.
+    fn fmt_overtime(
+        &self,
+        f: &mut fmt::Formatter,
+        work_time: &WorkTime,
+        duration_format: DurationFormat,
+    ) -> fmt::Result {
+        if self.overtime_threshold.is_some() {
+            write!(
+                f,
+                " ({} regular, {} overtime)",
+                duration_format.format(work_time.regular()),
+                duration_format.format(work_time.overtime)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Format the report as text, showing only the chosen times, in the chosen format.
+    pub fn text<'a>(
+        &'a self,
+        time_display: TimeDisplay,
+        duration_format: DurationFormat,
+    ) -> TextReport<'a> {
+        TextReport {
+            report: self,
+            time_display,
+            duration_format,
+        }
+    }
+
+    /// Returns true if there is any vacation activity worth reporting.
+    pub fn has_vacation(&self) -> bool {
+        !self.vacation_earned.0.is_zero() || !self.vacation_used.0.is_zero()
+    }
 }
 
 impl fmt::Display for SummaryReport {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.text(TimeDisplay::Both, DurationFormat::HoursMinutes)
+            .fmt(f)
+    }
+}
+
+/// The summary report as text, showing the gross time, the net time, or both.
+pub struct TextReport<'a> {
+    report: &'a SummaryReport,
+    time_display: TimeDisplay,
+    duration_format: DurationFormat,
+}
+
+impl<'a> TextReport<'a> {
+    /// Show the gross and/or net times, as chosen.
+    fn fmt_times(&self, f: &mut fmt::Formatter, work_time: &WorkTime) -> fmt::Result {
+        let gross = self.duration_format.format(work_time.gross);
+        let net = self.duration_format.format(work_time.net);
+        match self.time_display {
+            TimeDisplay::Both => write!(f, "{} {}", gross, net),
+            TimeDisplay::Net => write!(f, "{}", net),
+            TimeDisplay::Gross => write!(f, "{}", gross),
+        }
+    }
+}
+
+impl<'a> fmt::Display for TextReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let report = self.report;
         writeln!(f, "Summary report:")?;
-        writeln!(f, "\tNext expected direction: {:?}", self.next_direction)?;
+        writeln!(f, "\tNext expected direction: {:?}", report.next_direction)?;
+        if let Some(ref interval) = report.in_progress {
+            writeln!(
+                f,
+                "\tCurrently punched in since {}: {} and counting",
+                interval.start.format(SESSION_START_FORMAT),
+                self.duration_format.format(interval.work_time.gross)
+            )?;
+        }
+        for warning in &report.warnings {
+            writeln!(f, "\tWarning: {}", warning)?;
+        }
+        if report.has_vacation() {
+            writeln!(
+                f,
+                "\tVacation: {} earned, {} used, {} balance",
+                self.duration_format.format(report.vacation_earned),
+                self.duration_format.format(report.vacation_used),
+                self.duration_format.format(report.vacation_balance())
+            )?;
+        }
+        writeln!(
+            f,
+            "\tStreak: {} days (longest {} days)",
+            report.current_streak_days, report.longest_streak_days
+        )?;
         writeln!(f, "\tDays:")?;
-        for day in &self.days {
-            writeln!(f, "\t\t{}: {} {}", day.0, day.1.gross, day.1.net)?;
+        let mut days_total = WorkTime::new();
+        for day in &report.days {
+            write!(f, "\t\t{}: ", day.0)?;
+            self.fmt_times(f, &day.1)?;
+            report.fmt_overtime(f, &day.1, self.duration_format)?;
+            writeln!(f)?;
+            days_total += day.1;
         }
+        write!(f, "\t\tTotal: ")?;
+        self.fmt_times(f, &days_total)?;
+        report.fmt_overtime(f, &days_total, self.duration_format)?;
+        writeln!(f)?;
         writeln!(f, "\tWeeks:")?;
-        for week in &self.weeks {
-            writeln!(f, "\t\t{}: {} {}", week.0, week.1.gross, week.1.net)?;
+        let mut weeks_total = WorkTime::new();
+        for week in &report.weeks {
+            write!(f, "\t\t{}: ", week.week)?;
+            self.fmt_times(f, &week.work_time)?;
+            report.fmt_overtime(f, &week.work_time, self.duration_format)?;
+            match week.target_percent {
+                Some(percent) => writeln!(f, " ({}% of target)", percent)?,
+                None => writeln!(f)?,
+            }
+            weeks_total += week.work_time;
+        }
+        write!(f, "\t\tTotal: ")?;
+        self.fmt_times(f, &weeks_total)?;
+        report.fmt_overtime(f, &weeks_total, self.duration_format)?;
+        writeln!(f)?;
+        if let (Some(target), Some(status)) = (report.weekly_target, report.target_status) {
+            writeln!(
+                f,
+                "\tThis week is {} for the target of {}",
+                status,
+                self.duration_format.format(target)
+            )?;
         }
+        writeln!(f, "\tMonths:")?;
+        for month in &report.months {
+            write!(f, "\t\t{}: ", month.0)?;
+            self.fmt_times(f, &month.1)?;
+            writeln!(f)?;
+        }
+        write!(f, "\tMonth to date: ")?;
+        self.fmt_times(f, &report.mtd)?;
+        report.fmt_overtime(f, &report.mtd, self.duration_format)?;
+        writeln!(f)?;
+        write!(f, "\tYear to date: ")?;
+        self.fmt_times(f, &report.ytd)?;
+        report.fmt_overtime(f, &report.ytd, self.duration_format)?;
+        writeln!(f)?;
         writeln!(f, "\tRecent events:")?;
-        for event in &self.recent_events {
-            writeln!(f, "\t\t{:?}", event)?;
+        for event in &report.recent_events {
+            write!(
+                f,
+                "\t\t{} {:?}",
+                event.clock.format_friendly(),
+                event.event_type
+            )?;
+            if let Some(minutes) = event.minutes {
+                write!(f, " ({} min)", minutes)?;
+            }
+            if let Some(ref note) = event.note {
+                write!(f, ": {}", note)?;
+            }
+            writeln!(f)?;
         }
         Ok(())
     }
 }
 
-/// Generate a summary report.
+/// The work time for one week, along with progress toward the weekly target.
+#[derive(Serialize)]
+pub struct WeekSummary {
+    pub week: Week,
+    pub work_time: WorkTime,
+    /// The percentage of the weekly target met by the net time, if a target has been set.
+    pub target_percent: Option<i64>,
+}
+
+impl WeekSummary {
+    /// The width of a progress bar showing the percentage of the target met, which is capped at
+    /// 100% for weeks that exceed the target.
+    pub fn progress_width(&self) -> i64 {
+        self.target_percent.unwrap_or(0).min(100)
+    }
+}
+
+/// The seven days of the current week, from the project's first day of the week onward, laid out as
+/// the columns of a calendar.  Every day has a column, including those with no time yet.
+#[derive(Serialize)]
+pub struct WeekGrid {
+    pub days: Vec<WeekGridDay>,
+}
+
+/// One column of the week-at-a-glance grid.
+#[derive(Serialize)]
+pub struct WeekGridDay {
+    pub date: NaiveDate,
+    pub work_time: WorkTime,
+    pub today: bool,
+    /// Days after today have no time yet, and are shown empty rather than as zero.
+    pub future: bool,
+    /// The height of the day's bar, as a percentage of the week's longest net time.
+    pub bar_percent: i64,
+}
+
+impl WeekGrid {
+    /// Lay out the week containing the given day, taking each day's time from the given totals.
+    fn new(week: Week, today: NaiveDate, day_map: &BTreeMap<NaiveDate, WorkTime>) -> WeekGrid {
+        let work_time = |day| day_map.get(&day).cloned().unwrap_or_else(WorkTime::new);
+        let dates: Vec<NaiveDate> = (0..7).map(|n| week.first_day + Duration::days(n)).collect();
+        let longest = dates
+            .iter()
+            .map(|&date| work_time(date).net.0.num_seconds())
+            .max()
+            .unwrap_or(0);
+        WeekGrid {
+            days: dates
+                .into_iter()
+                .map(|date| {
+                    let work_time = work_time(date);
+                    WeekGridDay {
+                        date,
+                        work_time,
+                        today: date == today,
+                        future: date > today,
+                        bar_percent: if longest > 0 {
+                            work_time.net.0.num_seconds() * 100 / longest
+                        } else {
+                            0
+                        },
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Progress through the current week relative to the weekly target.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum TargetStatus {
+    OnTrack,
+    Behind,
+}
+
+impl fmt::Display for TargetStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TargetStatus::OnTrack => write!(f, "on track"),
+            TargetStatus::Behind => write!(f, "behind"),
+        }
+    }
+}
+
+/// The number of weekdays over which the weekly target is expected to be met.
+const WEEKDAYS: i32 = 5;
+
+/// The format used when displaying the start time of an in-progress session.
+const SESSION_START_FORMAT: &str = "%-I:%M%P";
+
+/// The format used when referring to the start of a session which may not have been today.
+const SESSION_START_FORMAT_LONG: &str = "%a %b %-d at %-I:%M%P";
+
+/// The format used when referring to an event anywhere in the event log.  This is the format which
+/// the "add-event" and "edit-event" commands accept.
+const EVENT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// The default number of past weeks to report on, in addition to the current week.
+pub const DEFAULT_REPORT_WEEKS: u32 = 5;
+
+/// The default number of recent events to include in a report.
+pub const DEFAULT_REPORT_EVENTS: usize = 10;
+
+/// The default number of past months to report on, in addition to the current month.
+const DEFAULT_REPORT_MONTHS: u32 = 2;
+
+/// The default week for a digest, as a number of weeks before the current week.
+pub const DEFAULT_DIGEST_WEEKS_AGO: u32 = 1;
+
+/// The settings which shape a summary report.  `from_project` gives the project's own settings and
+/// the default extent of a report, any of which may then be changed for a particular report, such
+/// as to preview the effect of a change to the project before making it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportSettings {
+    /// The overhead deducted from the work time, in minutes.
+    pub overhead: i32,
+    pub overhead_mode: OverheadMode,
+    pub week_start: WeekStart,
+    /// The number of past weeks reported, in addition to the current week.
+    pub weeks_in_past: u32,
+    /// The number of past months reported, in addition to the current month.
+    pub months_in_past: u32,
+    /// The number of days up to today in the daily totals, or none for the days of the current
+    /// week.
+    pub days: Option<u32>,
+    /// The number of recent events listed.
+    pub max_events: usize,
+}
+
+impl ReportSettings {
+    /// The project's own settings, with the default extent of a report.
+    pub fn from_project(project: &models::Project) -> ReportSettings {
+        ReportSettings {
+            overhead: project.overhead,
+            overhead_mode: project.overhead_mode,
+            week_start: project.week_start,
+            weeks_in_past: DEFAULT_REPORT_WEEKS,
+            months_in_past: DEFAULT_REPORT_MONTHS,
+            days: None,
+            max_events: DEFAULT_REPORT_EVENTS,
+        }
+    }
+
+    /// The project as it would be with these settings in place.
+    fn apply(&self, project: &models::Project) -> models::Project {
+        models::Project {
+            overhead: self.overhead,
+            overhead_mode: self.overhead_mode,
+            week_start: self.week_start,
+            ..project.clone()
+        }
+    }
+}
+
+/// Generate a summary report of a project with the given settings.  Nothing is stored, so the
+/// settings needn't be the project's own.
 pub fn summary_report(
     connection: &SqliteConnection,
-    project_id: i64,
+    project: &models::Project,
+    settings: &ReportSettings,
 ) -> Result<SummaryReport, DatabaseError> {
-    const MAX_REPORT_EVENTS: usize = 10;
-    const START_WEEKS_IN_PAST: i64 = 5;
+    Ok(CompletedSessions::load(connection, project, settings)?.report())
+}
 
-    use self::schema::events::dsl as events_dsl;
-    use self::schema::projects::dsl as projects_dsl;
+/// How long, in seconds, the web server may reuse the completed sessions behind a summary report.
+/// Changes made through the web server invalidate them at once, but changes made from the command
+/// line while the server is running can only be noticed by loading the sessions again.
+const REPORT_CACHE_SECONDS: u64 = 60;
 
-    // Load the project.
-    let project = projects_dsl::projects
-        .filter(projects_dsl::id.eq(project_id))
-        .first::<models::Project>(connection)
-        .optional()?
-        .ok_or(DatabaseError::BadProject)?;
+/// A cache of the completed sessions behind each project's summary report, shared by the web
+/// server's database threads.  Loading and pairing the events is the costly part of a report, and
+/// only needs to be redone when the events change, so the dashboard can be reloaded cheaply.  The
+/// cached sessions of a project must be invalidated whenever its events or settings change.
+pub struct ReportCache {
+    state: Mutex<ReportCacheState>,
+}
+
+struct ReportCacheState {
+    /// Advanced by each invalidation, so that sessions which were being loaded when an
+    /// invalidation happened aren't cached afterwards.
+    generation: u64,
+    projects: HashMap<i64, CompletedSessions>,
+}
+
+impl ReportCache {
+    pub fn new() -> ReportCache {
+        ReportCache {
+            state: Mutex::new(ReportCacheState {
+                generation: 0,
+                projects: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Generate a summary report as `summary_report` does, reusing the project's cached sessions if
+    /// they are still current and were loaded with the same settings.
+    pub fn summary_report(
+        &self,
+        connection: &SqliteConnection,
+        project: &models::Project,
+        settings: &ReportSettings,
+    ) -> Result<SummaryReport, DatabaseError> {
+        let generation = {
+            let state = self.state.lock().unwrap();
+            if let Some(sessions) = state.projects.get(&project.id) {
+                if sessions.is_current(settings) {
+                    return Ok(sessions.report());
+                }
+            }
+            state.generation
+        };
+
+        let sessions = CompletedSessions::load(connection, project, settings)?;
+        let report = sessions.report();
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation {
+            state.projects.insert(project.id, sessions);
+        }
+        Ok(report)
+    }
+
+    /// Forget the cached sessions of a project whose events or settings have changed.
+    pub fn invalidate(&self, project_id: i64) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.projects.remove(&project_id);
+    }
+}
+
+/// The parts of a summary report which depend only on the sessions completed so far, and which are
+/// costly to produce.  A session still in progress is added each time a report is produced, since
+/// its time grows by the minute.
+struct CompletedSessions {
+    /// The project, with the report's settings in place of its own.
+    project: models::Project,
+    settings: ReportSettings,
+    /// The day on which the sessions were loaded.  The reported weeks and months end on this day.
+    today: NaiveDate,
+    loaded: Instant,
+    /// All of the events of the reported weeks and months.
+    events: Vec<Event>,
+    /// The work time of the completed sessions on each day of the reported weeks and months, before
+    /// any daily overhead.
+    days: BTreeMap<NaiveDate, WorkTime>,
+    /// The same for the project's entire history, from which the streaks are counted.
+    history: BTreeMap<NaiveDate, WorkTime>,
+    /// The punch-in of the session in progress, if any.
+    last_in: Option<Event>,
+    warnings: Vec<String>,
+    next_direction: PunchDirection,
+}
+
+impl CompletedSessions {
+    fn load(
+        connection: &SqliteConnection,
+        project: &models::Project,
+        settings: &ReportSettings,
+    ) -> Result<CompletedSessions, DatabaseError> {
+        use self::schema::events::dsl as events_dsl;
+
+        let project = settings.apply(project);
+        let project_id = project.id;
+
+        // Monthly totals usually need events from further back than the daily and weekly totals,
+        // so the query starts at whichever is earliest.
+        let today = project_today(&project);
+        let (start_day, start_month) = report_start(settings, today);
+        let first_day_shown = first_day_shown(settings, today);
+        let start_utc = to_utc(
+            &day_begins(
+                &project,
+                start_day.min(start_month.first_day()).min(first_day_shown),
+            ),
+            Disambiguate::Earlier,
+        );
+
+        // All types of events are loaded, so that note and vacation events appear among the recent
+        // events.  Only the in and out events form intervals.
+        let events = events_dsl::events
+            .filter(events_dsl::project_id.eq(project_id))
+            .filter(events_dsl::clock.ge(start_utc))
+            .order(events_dsl::clock)
+            .load::<models::Event>(connection)?;
+
+        // Step through events and total the work time of each day.  This includes days before the
+        // reported weeks, so that the overtime for each day of the reported months can be
+        // determined.
+        let mut warnings = Vec::new();
+        let (days, last_in) = completed_days(&project, &events, &mut warnings);
+        let last_in = last_in.cloned();
+
+        // Streaks look at the project's entire history, for which only the punches and breaks are
+        // needed.
+        let punches = events_dsl::events
+            .filter(events_dsl::project_id.eq(project_id))
+            .filter(
+                events_dsl::event_type
+                    .eq(models::EventType::In)
+                    .or(events_dsl::event_type.eq(models::EventType::Out))
+                    .or(events_dsl::event_type.eq(models::EventType::Break)),
+            )
+            .order(events_dsl::clock)
+            .load::<models::Event>(connection)?;
+        let (history, _) = completed_days(&project, &punches, &mut Vec::new());
+
+        Ok(CompletedSessions {
+            project,
+            settings: settings.clone(),
+            today,
+            loaded: Instant::now(),
+            events,
+            days,
+            history,
+            last_in,
+            warnings,
+            next_direction: db::next_expected_punch_direction(connection, project_id)?,
+        })
+    }
+
+    /// Returns true if these sessions can still be used for a report with the given settings.
+    fn is_current(&self, settings: &ReportSettings) -> bool {
+        self.today == project_today(&self.project)
+            && self.settings == *settings
+            && self.loaded.elapsed() < StdDuration::from_secs(REPORT_CACHE_SECONDS)
+    }
+
+    /// Produce the report, counting the session in progress, if any, up to the present.
+    fn report(&self) -> SummaryReport {
+        let project = &self.project;
+        let today = self.today;
+        let week_start = project.week_start.weekday();
+        let this_week = Week::containing(&today, week_start);
+        let this_month = YearMonth::from_date(&today);
+        let (start_day, start_month) = report_start(&self.settings, today);
+        let start_month_day = start_month.first_day();
+        let first_day_shown = first_day_shown(&self.settings, today);
+
+        // Is there a work session in progress? If so, then account for its time to the present.
+        let mut warnings = self.warnings.clone();
+        let in_progress = self
+            .last_in
+            .as_ref()
+            .map(|event| in_progress_interval(project, event, &self.events, &mut warnings));
+        let mut day_map = finish_days(project, self.days.clone(), in_progress);
+        let overtime_threshold = overtime_threshold(project);
+
+        // Roll the days up into weeks and months
+        let mut week_map = BTreeMap::<Week, WorkTime>::new();
+        let mut month_map = BTreeMap::<YearMonth, WorkTime>::new();
+        let mut net_worked = Duration::zero();
+        for (day, work_time) in &day_map {
+            if *day >= start_day {
+                net_worked = net_worked + work_time.net.0;
+                let entry = week_map
+                    .entry(Week::containing(day, week_start))
+                    .or_insert(WorkTime::new());
+                *entry += work_time;
+            }
+            if *day >= start_month_day {
+                let month = YearMonth::from_date(day);
+                let entry = month_map.entry(month).or_insert(WorkTime::new());
+                *entry += work_time;
+            }
+        }
 
-    // Determine the Monday at or before 5 weeks ago
-    let today = Local::now().naive_local().date();
-    let mut start_day = today - Duration::weeks(START_WEEKS_IN_PAST);
-    while start_day.weekday() != Weekday::Mon {
-        start_day -= Duration::days(1);
+        // Accrue vacation time in proportion to the net time worked, and total the vacation time used
+        let vacation_earned = Elapsed(Duration::seconds(
+            (net_worked.num_seconds() as f64 * project.vacation_accrual_ratio) as i64,
+        ));
+        let vacation_used = Elapsed(
+            self.events
+                .iter()
+                .filter(|e| e.event_type == EventType::Vacation)
+                .filter(|e| project_date(project, &to_local(&e.clock)) >= start_day)
+                .fold(Duration::zero(), |total, e| {
+                    total + Duration::minutes(e.minutes.unwrap_or(0) as i64)
+                }),
+        );
+
+        // Fill in empty days with zero values
+        let mut day = start_day.min(first_day_shown);
+        while day <= today {
+            day_map.entry(day).or_insert(WorkTime::new());
+            day = day.succ();
+        }
+
+        // Fill in empty weeks with zero values
+        let mut week = Week::containing(&start_day, week_start);
+        while week <= this_week {
+            week_map.entry(week).or_insert(WorkTime::new());
+            week = week.succ();
+        }
+
+        // Fill in empty months with zero values
+        let mut month = start_month;
+        while month <= this_month {
+            month_map.entry(month).or_insert(WorkTime::new());
+            month = month.succ();
+        }
+
+        let week_grid = WeekGrid::new(this_week, today, &day_map);
+
+        // Flatten to vectors
+        let mut days = WorkTime::flatten_map(day_map);
+        let mut weeks = WorkTime::flatten_map(week_map);
+        let mut months = WorkTime::flatten_map(month_map);
+
+        // Keep only the days to be shown
+        let keep_days = ((today - first_day_shown).num_days() + 1) as usize;
+        if days.len() > keep_days {
+            let split_point = days.len() - keep_days;
+            days = days.split_off(split_point);
+        }
+
+        // Keep only the most recent events
+        let max_events = self.settings.max_events;
+        let mut recent_events = if self.events.len() > max_events {
+            let split_point = self.events.len() - max_events;
+            self.events[split_point..].to_vec()
+        } else {
+            self.events.clone()
+        };
+
+        // Measure each week against the weekly target.  The current week is on track if its net time
+        // has kept pace with the target through the weekdays that have already passed.
+        let weekly_target = if project.weekly_target_minutes > 0 {
+            Some(Duration::minutes(project.weekly_target_minutes as i64))
+        } else {
+            None
+        };
+        let target_status = weekly_target.map(|target| {
+            let weekdays_past = (0..(today - this_week.first_day).num_days())
+                .map(|n| this_week.first_day + Duration::days(n))
+                .filter(|day| day.weekday() != Weekday::Sat && day.weekday() != Weekday::Sun)
+                .count() as i32;
+            let expected = target * weekdays_past / WEEKDAYS;
+            let this_week = weeks
+                .last()
+                .map(|w| w.1.net.0)
+                .unwrap_or_else(Duration::zero);
+            if this_week >= expected {
+                TargetStatus::OnTrack
+            } else {
+                TargetStatus::Behind
+            }
+        });
+
+        let (current_streak_days, longest_streak_days) =
+            streaks(project, self.history.clone(), in_progress, today);
+
+        // The month-to-date and year-to-date totals reach beyond the reported weeks, so they come
+        // from the project's entire history.  That way a session which began before midnight at
+        // the start of the month or year is paired with its punch-in, and counts toward the day on
+        // which it began, just as it does in the daily totals.
+        let month_start_day = this_month.first_day();
+        let year_start_day = NaiveDate::from_ymd(today.year(), 1, 1);
+        let mut mtd = WorkTime::new();
+        let mut ytd = WorkTime::new();
+        for (day, work_time) in
+            finish_days(project, self.history.clone(), in_progress).range(year_start_day..=today)
+        {
+            ytd += work_time;
+            if *day >= month_start_day {
+                mtd += work_time;
+            }
+        }
+
+        // Reverse date order
+        days.reverse();
+        weeks.reverse();
+        months.reverse();
+        recent_events.reverse();
+
+        SummaryReport {
+            project_name: project.name.clone(),
+            next_direction: self.next_direction,
+            days,
+            week_grid,
+            weeks: weeks
+                .iter()
+                .map(|(w, t)| WeekSummary {
+                    week: *w,
+                    work_time: *t,
+                    target_percent: weekly_target
+                        .map(|target| t.net.0.num_seconds() * 100 / target.num_seconds()),
+                })
+                .collect(),
+            months: months.iter().map(|(m, t)| (Month(*m), *t)).collect(),
+            mtd,
+            ytd,
+            recent_events,
+            in_progress,
+            warnings,
+            vacation_earned,
+            vacation_used,
+            weekly_target: weekly_target.map(Elapsed),
+            target_status,
+            overtime_threshold: overtime_threshold.map(Elapsed),
+            current_streak_days,
+            longest_streak_days,
+        }
+    }
+}
+
+/// The first day of the daily totals, for a report with the given settings made on the given day.
+fn first_day_shown(settings: &ReportSettings, today: NaiveDate) -> NaiveDate {
+    match settings.days {
+        Some(days) => today - Duration::days(days.max(1) as i64 - 1),
+        None => Week::containing(&today, settings.week_start.weekday()).first_day,
+    }
+}
+
+/// The first day of the reported weeks, and the first of the reported months, for a report with
+/// the given settings made on the given day.
+fn report_start(settings: &ReportSettings, today: NaiveDate) -> (NaiveDate, YearMonth) {
+    // All of the daily and weekly totals start from the start of the week the requested number of
+    // weeks ago.
+    let week_start = settings.week_start.weekday();
+    let start_day = Week::containing(
+        &(today - Duration::weeks(settings.weeks_in_past as i64)),
+        week_start,
+    )
+    .first_day;
+    let mut start_month = YearMonth::from_date(&today);
+    for _ in 0..settings.months_in_past {
+        start_month = start_month.pred();
+    }
+    (start_day, start_month)
+}
+
+/// Determine the current and longest streaks of consecutive days with some net time logged.  These
+/// look at the project's entire history rather than just the reported weeks.  Today doesn't break
+/// the current streak until it is over, and neither do idle weekends if the project skips them.
+/// The work time of the completed sessions on each day is given, along with the session in
+/// progress, if any.
+fn streaks(
+    project: &models::Project,
+    mut day_map: BTreeMap<NaiveDate, WorkTime>,
+    in_progress: Option<Interval>,
+    today: NaiveDate,
+) -> (u32, u32) {
+    if let Some(interval) = in_progress {
+        add_to_days(project, &mut day_map, &interval);
+    }
+
+    let mut current = 0;
+    let mut longest = 0;
+    let mut day = match day_map.keys().next() {
+        Some(day) => *day,
+        None => return (0, 0),
+    };
+    while day <= today {
+        let active = day_map
+            .get(&day)
+            .map(|work_time| apply_day_overhead(project, *work_time).net.0)
+            .unwrap_or_else(Duration::zero)
+            > Duration::zero();
+        let weekend = day.weekday() == Weekday::Sat || day.weekday() == Weekday::Sun;
+        if active {
+            current += 1;
+            longest = longest.max(current);
+        } else if day != today && !(weekend && project.skip_weekends) {
+            current = 0;
+        }
+        day = day.succ();
     }
-    let start_utc = to_utc(&start_day.and_hms(0, 0, 0))?;
+    (current, longest)
+}
+
+/// A quick status for the current local day, which is much cheaper to produce than a summary
+/// report.
+#[derive(Serialize)]
+pub struct DayStatus {
+    pub punched_in: bool,
+    /// The time of the punch-in, if the user is currently punched in.
+    pub since: Option<UtcDateTime>,
+    /// The net time logged today, in seconds.
+    pub net_today: i64,
+    /// The gross time logged today, in seconds.
+    pub gross_today: i64,
+}
+
+/// Determine whether the user is punched in, and how much time has been logged today.  As with the
+/// summary report, sessions count toward the day on which they started.
+pub fn day_status(
+    connection: &SqliteConnection,
+    project_id: i64,
+) -> Result<DayStatus, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    let project = load_project(connection, project_id)?;
+    // The most recent punch tells whether the user is punched in, even if it was before today.
+    let last_punch = db::load_last_punch(connection, project_id)?;
+    let since = match last_punch {
+        Some(ref event) if event.event_type == EventType::In => Some(event.clock),
+        _ => None,
+    };
 
+    // Only recent punches and breaks are needed to total today's time.  Yesterday's are included
+    // so that a session which began before today contributes its share of today's time.
+    let today = project_today(&project);
+    let start_utc = to_utc(&day_begins(&project, today.pred()), Disambiguate::Earlier);
     let events = events_dsl::events
         .filter(events_dsl::project_id.eq(project_id))
         .filter(
             events_dsl::event_type
                 .eq(models::EventType::In)
-                .or(events_dsl::event_type.eq(models::EventType::Out)),
+                .or(events_dsl::event_type.eq(models::EventType::Out))
+                .or(events_dsl::event_type.eq(models::EventType::Break)),
         )
         .filter(events_dsl::clock.ge(start_utc))
         .order(events_dsl::clock)
         .load::<models::Event>(connection)?;
+    let (mut intervals, last_in) = pair_punches(&project, &events, &mut Vec::new());
+    drop_short_sessions(&project, &mut intervals);
+    if let Some(event) = last_in {
+        intervals.push(in_progress_interval(
+            &project,
+            event,
+            &events,
+            &mut Vec::new(),
+        ));
+    }
+    let today_time = allocate_to_days(&project, &intervals)
+        .get(&today)
+        .cloned()
+        .unwrap_or_else(WorkTime::new);
+    let today_time = apply_day_overhead(&project, today_time);
+
+    Ok(DayStatus {
+        punched_in: since.is_some(),
+        since,
+        net_today: today_time.net.0.num_seconds(),
+        gross_today: today_time.gross.0.num_seconds(),
+    })
+}
+
+/// One work session, or the part of it which falls on a particular day.
+#[derive(Serialize, Debug)]
+pub struct DaySession {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub work_time: WorkTime,
+    /// The notes of the punch-in and punch-out, if any.
+    pub note: Option<String>,
+    /// The session is still accruing time.
+    pub in_progress: bool,
+    /// The session is shorter than the project's minimum, so it isn't counted in the totals.
+    pub too_short: bool,
+}
+
+/// List the work sessions of the given day of the project.  A session which spans the start or end
+/// of the day is trimmed to the part which falls on the day, and only that part's time is counted.
+pub fn day_sessions(
+    connection: &SqliteConnection,
+    project_id: i64,
+    date: NaiveDate,
+) -> Result<Vec<DaySession>, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    let project = load_project(connection, project_id)?;
+
+    // Load the punches from the day before through the day after, so that sessions which span
+    // either end of the day are paired up.
+    let start_utc = to_utc(&day_begins(&project, date.pred()), Disambiguate::Earlier);
+    let end_utc = to_utc(
+        &day_begins(&project, date.succ().succ()),
+        Disambiguate::Earlier,
+    );
+    let events = events_dsl::events
+        .filter(events_dsl::project_id.eq(project_id))
+        .filter(events_dsl::clock.ge(start_utc))
+        .filter(events_dsl::clock.lt(end_utc))
+        .order(events_dsl::clock)
+        .load::<models::Event>(connection)?;
+
+    // A session is still in progress only if its punch-in is the most recent punch of all.
+    let (pairs, last_in) = pair_punch_events(&project, &events, &mut Vec::new());
+    let mut sessions: Vec<(&Event, Option<&Event>, Interval)> = pairs
+        .into_iter()
+        .map(|(punch_in, punch_out, interval)| (punch_in, Some(punch_out), interval))
+        .collect();
+    if let Some(event) = last_in {
+        let last_punch = db::load_last_punch(connection, project_id)?;
+        if last_punch.map(|e| e.id) == Some(event.id) {
+            let interval = in_progress_interval(&project, event, &events, &mut Vec::new());
+            sessions.push((event, None, interval));
+        }
+    }
+
+    let day_start = day_begins(&project, date);
+    let day_end = day_begins(&project, date.succ());
+    Ok(sessions
+        .into_iter()
+        .filter_map(|(punch_in, punch_out, interval)| {
+            let (_, work_time) = interval
+                .split_by_day(day_start_offset(&project))
+                .into_iter()
+                .find(|&(day, _)| day == date)?;
+            let notes: Vec<&str> = Some(punch_in)
+                .into_iter()
+                .chain(punch_out)
+                .filter_map(|event| event.note.as_deref())
+                .filter(|note| !note.is_empty())
+                .collect();
+            Some(DaySession {
+                start: interval.start.max(day_start),
+                end: interval.end.min(day_end),
+                work_time,
+                note: if notes.is_empty() {
+                    None
+                } else {
+                    Some(notes.join(" / "))
+                },
+                in_progress: punch_out.is_none(),
+                too_short: punch_out.is_some() && is_short_session(&project, &interval),
+            })
+        })
+        .collect())
+}
+
+/// Produce a plain-text digest of one week's work, suitable for sending by email.  The week is
+/// given as the number of weeks before the current week, so that 0 is this week and 1 is last week.
+/// The digest opens with a one-line summary of the week, followed by the time worked each day.
+pub fn weekly_digest(
+    connection: &SqliteConnection,
+    project_id: i64,
+    weeks_ago: u32,
+) -> Result<String, DatabaseError> {
+    let project = load_project(connection, project_id)?;
+    let today = project_today(&project);
+    let week_start = project.week_start.weekday();
+    let week = Week::containing(&(today - Duration::weeks(weeks_ago as i64)), week_start);
+    let next_week = week.succ();
+
+    let days = week_days(connection, &project, week)?;
+    let mut total = WorkTime::new();
+    for (_, work_time) in &days {
+        total += work_time;
+    }
+    let days_worked = days
+        .iter()
+        .filter(|(_, work_time)| work_time.net.0 > Duration::zero())
+        .count();
+
+    let mut digest = format!(
+        "Week {} ({} to {}): {} net ({} gross) across {} day{}",
+        week,
+        week.first_day,
+        next_week.first_day.pred(),
+        total.net,
+        total.gross,
+        days_worked,
+        if days_worked == 1 { "" } else { "s" },
+    );
+    if project.weekly_target_minutes > 0 {
+        let target = Duration::minutes(project.weekly_target_minutes as i64);
+        let difference = total.net.0 - target;
+        if difference < Duration::zero() {
+            digest.push_str(&format!(", {} behind", Elapsed(-difference)));
+        } else if difference > Duration::zero() {
+            digest.push_str(&format!(", {} ahead of", Elapsed(difference)));
+        } else {
+            digest.push_str(", meeting");
+        }
+        digest.push_str(&format!(" the target of {}", Elapsed(target)));
+    }
+    digest.push('\n');
+    if overtime_threshold(&project).is_some() {
+        digest.push_str(&format!("Overtime: {}\n", total.overtime));
+    }
+
+    digest.push('\n');
+    for (day, work_time) in &days {
+        digest.push_str(&format!(
+            "\t{}\t{} net\t{} gross\n",
+            day.format("%a %Y-%m-%d"),
+            work_time.net,
+            work_time.gross
+        ));
+    }
+    Ok(digest)
+}
+
+/// Total the work time of the current week, including any session in progress.  This is much
+/// cheaper than a summary report, for an overview of several projects at once.
+pub fn current_week_total(
+    connection: &SqliteConnection,
+    project: &models::Project,
+) -> Result<WorkTime, DatabaseError> {
+    let week = Week::containing(&project_today(project), project.week_start.weekday());
+    let mut total = WorkTime::new();
+    for (_, work_time) in week_days(connection, project, week)? {
+        total += &work_time;
+    }
+    Ok(total)
+}
+
+/// Total the work time of every day the project has ever had, including any session in progress.
+pub fn lifetime_total(
+    connection: &SqliteConnection,
+    project: &models::Project,
+) -> Result<WorkTime, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    let events = events_dsl::events
+        .filter(events_dsl::project_id.eq(project.id))
+        .order(events_dsl::clock)
+        .load::<models::Event>(connection)?;
+    let (day_map, _) = day_totals(project, &events, &mut Vec::new());
+    let mut total = WorkTime::new();
+    for work_time in day_map.values() {
+        total += work_time;
+    }
+    Ok(total)
+}
+
+/// Total the work time of each day of the given week which has any sessions.
+fn week_days(
+    connection: &SqliteConnection,
+    project: &models::Project,
+    week: Week,
+) -> Result<Vec<(NaiveDate, WorkTime)>, DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    // Events from the day before the week are included, so that a session which spans the first
+    // midnight is paired up.  Later events are all included, so that a session still in progress
+    // is recognized as such.
+    let start_utc = to_utc(
+        &day_begins(project, week.first_day.pred()),
+        Disambiguate::Earlier,
+    );
+    let events = events_dsl::events
+        .filter(events_dsl::project_id.eq(project.id))
+        .filter(events_dsl::clock.ge(start_utc))
+        .order(events_dsl::clock)
+        .load::<models::Event>(connection)?;
+    let (day_map, _) = day_totals(project, &events, &mut Vec::new());
 
-    // Step through events and formulate in-out intervals
+    // Bucket the days into the week, as the summary report does.
+    let week_start = project.week_start.weekday();
+    Ok(day_map
+        .into_iter()
+        .filter(|&(day, _)| Week::containing(&day, week_start) == week)
+        .collect())
+}
+
+/// A punch which breaks the in/out ordering of the event log.
+#[derive(Debug)]
+pub struct OrderingProblem {
+    /// The punch which is out of order.
+    pub event: Event,
+    /// The earlier punch in the same direction, or None for a punch-out which comes before any
+    /// punch-in.
+    pub previous: Option<Event>,
+}
+
+impl fmt::Display for OrderingProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |event: &Event| {
+            format!(
+                "{} ({} at {})",
+                event.id,
+                if event.event_type == EventType::In {
+                    "punch-in"
+                } else {
+                    "punch-out"
+                },
+                event.clock.format_local(EVENT_TIME_FORMAT)
+            )
+        };
+        match self.previous {
+            Some(ref previous) => write!(
+                f,
+                "Event {} follows event {} with no {} between them",
+                describe(&self.event),
+                describe(previous),
+                if self.event.event_type == EventType::In {
+                    "punch-out"
+                } else {
+                    "punch-in"
+                }
+            ),
+            None => write!(
+                f,
+                "Event {} comes before any punch-in",
+                describe(&self.event)
+            ),
+        }
+    }
+}
+
+/// Scan all of a project's events in clock order for punches which break the in/out ordering.  The
+/// summary report skips such punches with only a log message, so this reports them instead.
+/// Returns the number of events checked, along with the problems found.
+pub fn check_events(
+    connection: &SqliteConnection,
+    project_id: i64,
+) -> Result<(usize, Vec<OrderingProblem>), DatabaseError> {
+    use self::schema::events::dsl as events_dsl;
+
+    let events = events_dsl::events
+        .filter(events_dsl::project_id.eq(project_id))
+        .order(events_dsl::clock)
+        .load::<models::Event>(connection)?;
+
+    // Follow the same in/out sequence as pair_punch_events, except that a punch-out before the
+    // first punch-in is a problem here, since the scan starts at the beginning of the log.
+    let mut problems = Vec::new();
+    let mut last_punch: Option<&Event> = None;
+    for event in events.iter().filter(|e| e.event_type.is_punch()) {
+        let expected_type = match last_punch {
+            Some(punch) if punch.event_type == EventType::In => EventType::Out,
+            _ => EventType::In,
+        };
+        if event.event_type == expected_type {
+            last_punch = Some(event);
+        } else {
+            problems.push(OrderingProblem {
+                event: event.clone(),
+                previous: last_punch.cloned(),
+            });
+        }
+    }
+    Ok((events.len(), problems))
+}
+
+/// Load a project by id.
+fn load_project(
+    connection: &SqliteConnection,
+    project_id: i64,
+) -> Result<models::Project, DatabaseError> {
+    use self::schema::projects::dsl as projects_dsl;
+    projects_dsl::projects
+        .filter(projects_dsl::id.eq(project_id))
+        .first::<models::Project>(connection)
+        .optional()?
+        .ok_or(DatabaseError::BadProject)
+}
+
+/// The overhead to deduct from each work session.  When the overhead is deducted once per day
+/// instead, sessions are counted in full, and `apply_day_overhead` deducts it from the day's total.
+fn session_overhead(project: &models::Project) -> Duration {
+    match project.overhead_mode {
+        OverheadMode::PerSession => Duration::minutes(project.overhead as i64),
+        OverheadMode::PerDay => Duration::zero(),
+    }
+}
+
+/// Deduct the overhead from a day's total work time, if the project deducts overhead once per day.
+/// The net time never goes below zero, even for a day shorter than the overhead.
+fn apply_day_overhead(project: &models::Project, work_time: WorkTime) -> WorkTime {
+    match project.overhead_mode {
+        OverheadMode::PerSession => work_time,
+        OverheadMode::PerDay => WorkTime::from_duration(
+            work_time.gross.0,
+            Duration::minutes(project.overhead as i64),
+        ),
+    }
+}
+
+/// Step through events in time order, pairing each punch-in with the following punch-out to form
+/// work intervals.  Also returns the final punch-in, if it hasn't been paired with a punch-out yet.
+/// Sessions longer than the project's auto-split length are capped, with a warning identifying the
+/// events so that they can be corrected.
+fn pair_punches<'a>(
+    project: &models::Project,
+    events: &'a [Event],
+    warnings: &mut Vec<String>,
+) -> (Vec<Interval>, Option<&'a Event>) {
+    let (sessions, last_in) = pair_punch_events(project, events, warnings);
+    let intervals = sessions
+        .into_iter()
+        .map(|(_, _, interval)| interval)
+        .collect();
+    (intervals, last_in)
+}
+
+/// Pair punches as `pair_punches` does, but keep the punch-in and punch-out events of each
+/// interval.
+fn pair_punch_events<'a>(
+    project: &models::Project,
+    events: &'a [Event],
+    warnings: &mut Vec<String>,
+) -> (Vec<(&'a Event, &'a Event, Interval)>, Option<&'a Event>) {
+    let overhead = session_overhead(project);
+    let max_session = Duration::hours(project.auto_split_hours as i64);
     let mut expected_type = EventType::In;
     let mut last_in: Option<&Event> = None;
-    let mut intervals: Vec<Interval> = Vec::with_capacity(events.len() / 2);
+    let mut last_in_index = 0;
+    let mut intervals = Vec::with_capacity(events.len() / 2);
     let mut lead_in: bool = true;
-    let overhead = Duration::minutes(project.overhead as i64);
-    for event in &events {
+    for (index, event) in events.iter().enumerate() {
         // Trim any leading "out" events without a warning since we can't create a valid interval
         // without the corresponding "in" event.  This can happen since we picked an arbitrary
         // point in time to start.  This is somewhat redundant with the expected_type check below,
@@ -90,9 +1220,9 @@ pub fn summary_report(
         if lead_in && event.event_type == EventType::Out {
             continue;
         }
-        // We already made this restriction in the database query, but we'll eventually need to be
-        // able to do something with Note events...
-        if event.event_type != EventType::In && event.event_type != EventType::Out {
+        // Note, vacation, and break events don't delimit intervals, so they are skipped without
+        // disturbing the lead-in trimming or the expected in/out sequence.
+        if !event.event_type.is_punch() {
             continue;
         }
         if event.event_type != expected_type {
@@ -103,91 +1233,213 @@ pub fn summary_report(
         match event.event_type {
             EventType::In => {
                 last_in = Some(event);
+                last_in_index = index;
                 expected_type = EventType::Out;
             }
             EventType::Out => {
-                let interval = match last_in.take() {
-                    Some(e) => {
-                        Interval::new(&to_local(&e.clock), &to_local(&event.clock), overhead)
-                    }
+                let punch_in = match last_in.take() {
+                    Some(e) => e,
                     None => unreachable!(),
                 };
-                intervals.push(interval);
+                let start = to_local(&punch_in.clock);
+                let mut end = to_local(&event.clock);
+                if project.auto_split_hours > 0 && end - start > max_session {
+                    end = start + max_session;
+                    warnings.push(format!(
+                        "The session started {} (events {} and {}) lasted more than {} hours, and \
+                         only {} hours have been counted.  Did you forget to punch out?",
+                        start.format(SESSION_START_FORMAT_LONG),
+                        punch_in.id,
+                        event.id,
+                        project.auto_split_hours,
+                        project.auto_split_hours,
+                    ));
+                }
+                let breaks = break_time(&events[last_in_index..index], &start, &end);
+                intervals.push((
+                    punch_in,
+                    event,
+                    Interval::new(&start, &end, breaks, overhead),
+                ));
                 expected_type = EventType::In;
             }
             _ => {}
         }
     }
+    (intervals, last_in)
+}
 
-    // Is there a work session in progress? If so, then account for its time to the present.
-    if let Some(event) = last_in {
-        let interval = Interval::new(
-            &to_local(&event.clock),
-            &Local::now().naive_local(),
-            overhead,
-        );
-        intervals.push(interval);
-    }
+/// Returns true if a completed session is too short to count toward the totals.
+fn is_short_session(project: &models::Project, interval: &Interval) -> bool {
+    interval.end - interval.start < Duration::seconds(project.min_session_seconds as i64)
+}
 
-    // Allocate work time to days and weeks
-    let mut day_map = BTreeMap::<NaiveDate, WorkTime>::new();
-    let mut week_map = BTreeMap::<IsoWeek, WorkTime>::new();
-    for interval in &intervals {
-        // Allocate to days
-        let day = interval.start.date();
-        let mut entry = day_map.entry(day).or_insert(WorkTime::new());
-        *entry += interval.work_time;
+/// Remove the completed sessions which are too short to count toward the totals, returning the
+/// number removed.  This only affects the report; the events are left in place.
+fn drop_short_sessions(project: &models::Project, intervals: &mut Vec<Interval>) -> usize {
+    let before = intervals.len();
+    intervals.retain(|interval| !is_short_session(project, interval));
+    before - intervals.len()
+}
 
-        // Allocate to weeks
-        let week = day.iso_week();
-        let mut entry = week_map.entry(week).or_insert(WorkTime::new());
-        *entry += interval.work_time;
+/// Pair the punches into work sessions and total the work time of each day, applying the project's
+/// rules for short sessions, overhead, and overtime.  Also returns the session still in progress,
+/// if any, which is counted up to the present.
+fn day_totals(
+    project: &models::Project,
+    events: &[Event],
+    warnings: &mut Vec<String>,
+) -> (BTreeMap<NaiveDate, WorkTime>, Option<Interval>) {
+    let (day_map, last_in) = completed_days(project, events, warnings);
+    let in_progress = last_in.map(|event| in_progress_interval(project, event, events, warnings));
+    (finish_days(project, day_map, in_progress), in_progress)
+}
+
+/// Pair the punches into completed work sessions, leaving out those which are too short, and total
+/// the work time of each day before any daily overhead.  Also returns the punch-in of the session
+/// still in progress, if any.
+fn completed_days<'a>(
+    project: &models::Project,
+    events: &'a [Event],
+    warnings: &mut Vec<String>,
+) -> (BTreeMap<NaiveDate, WorkTime>, Option<&'a Event>) {
+    let (mut intervals, last_in) = pair_punches(project, events, warnings);
+    let dropped = drop_short_sessions(project, &mut intervals);
+    if dropped > 0 {
+        warnings.push(format!(
+            "{} session{} shorter than {} seconds {} been left out of the totals.",
+            dropped,
+            if dropped == 1 { "" } else { "s" },
+            project.min_session_seconds,
+            if dropped == 1 { "has" } else { "have" },
+        ));
     }
+    (allocate_to_days(project, &intervals), last_in)
+}
 
-    // Fill in empty days with zero values
-    let mut day = start_day;
-    while day <= today {
-        day_map.entry(day).or_insert(WorkTime::new());
-        day = day.succ();
+/// Add the session in progress, if any, to the work time of the completed sessions on each day,
+/// and apply the project's rules for overhead and overtime.
+fn finish_days(
+    project: &models::Project,
+    mut day_map: BTreeMap<NaiveDate, WorkTime>,
+    in_progress: Option<Interval>,
+) -> BTreeMap<NaiveDate, WorkTime> {
+    if let Some(interval) = in_progress {
+        add_to_days(project, &mut day_map, &interval);
+    }
+    for work_time in day_map.values_mut() {
+        *work_time = apply_day_overhead(project, *work_time);
     }
 
-    // Fill in empty weeks with zero values
-    let mut week = start_day.iso_week();
-    while week <= today.iso_week() {
-        week_map.entry(week).or_insert(WorkTime::new());
-        week = (NaiveDate::from_isoywd(week.year(), week.week(), Weekday::Mon)
-            + Duration::weeks(1))
-            .iso_week();
+    // Net time beyond the daily overtime threshold is overtime.
+    if let Some(threshold) = overtime_threshold(project) {
+        for work_time in day_map.values_mut() {
+            if work_time.net.0 > threshold {
+                work_time.overtime = Elapsed(work_time.net.0 - threshold);
+            }
+        }
     }
+    day_map
+}
 
-    // Flatten to vectors
-    let mut days = WorkTime::flatten_map(day_map);
-    let mut weeks = WorkTime::flatten_map(week_map);
+/// The daily net time beyond which work counts as overtime, if the project tracks overtime.
+fn overtime_threshold(project: &models::Project) -> Option<Duration> {
+    if project.daily_overtime_threshold_minutes > 0 {
+        Some(Duration::minutes(
+            project.daily_overtime_threshold_minutes as i64,
+        ))
+    } else {
+        None
+    }
+}
 
-    // Keep only the days from this week
-    let keep_days = (today.weekday().num_days_from_monday() + 1) as usize;
-    if days.len() > keep_days {
-        let split_point = days.len() - keep_days;
-        days = days.split_off(split_point);
+/// Total the work time of each day.  Sessions which span the start of a day contribute to each day
+/// they touch.
+fn allocate_to_days(
+    project: &models::Project,
+    intervals: &[Interval],
+) -> BTreeMap<NaiveDate, WorkTime> {
+    let mut day_map = BTreeMap::<NaiveDate, WorkTime>::new();
+    for interval in intervals {
+        add_to_days(project, &mut day_map, interval);
     }
+    day_map
+}
 
-    // Keep only the last 10 events
-    let mut recent_events = if events.len() > MAX_REPORT_EVENTS {
-        let split_point = events.len() - MAX_REPORT_EVENTS;
-        events[split_point..].to_vec()
-    } else {
-        events.clone()
-    };
+/// Add the work time of an interval to the days on which it falls.
+fn add_to_days(
+    project: &models::Project,
+    day_map: &mut BTreeMap<NaiveDate, WorkTime>,
+    interval: &Interval,
+) {
+    for (day, work_time) in interval.split_by_day(day_start_offset(project)) {
+        *day_map.entry(day).or_insert(WorkTime::new()) += work_time;
+    }
+}
 
-    // Reverse date order
-    days.reverse();
-    weeks.reverse();
-    recent_events.reverse();
+/// How long after local midnight each of the project's days begins.
+fn day_start_offset(project: &models::Project) -> Duration {
+    Duration::hours(project.day_start_hour as i64)
+}
 
-    Ok(SummaryReport {
-        next_direction: db::next_expected_punch_direction(connection, project_id)?,
-        days,
-        weeks: weeks.iter().map(|(w, t)| (Week(*w), t.clone())).collect(),
-        recent_events,
-    })
+/// The local time at which the given day of the project begins.
+pub fn day_begins(project: &models::Project, day: NaiveDate) -> NaiveDateTime {
+    day.and_hms(0, 0, 0) + day_start_offset(project)
+}
+
+/// The day of the project on which the given local time falls.  Before the project's day start
+/// hour, this is the previous calendar day.
+pub fn project_date(project: &models::Project, local_datetime: &NaiveDateTime) -> NaiveDate {
+    (*local_datetime - day_start_offset(project)).date()
+}
+
+/// The day of the project on which the present falls.
+fn project_today(project: &models::Project) -> NaiveDate {
+    project_date(project, &Local::now().naive_local())
+}
+
+/// Account for the time of a work session which is still in progress.  A forgotten punch-out could
+/// otherwise accrue an absurd amount of time, so the session is capped at the project's maximum
+/// session length, with a warning.
+fn in_progress_interval(
+    project: &models::Project,
+    punch_in: &Event,
+    events: &[Event],
+    warnings: &mut Vec<String>,
+) -> Interval {
+    let overhead = session_overhead(project);
+    let start = to_local(&punch_in.clock);
+    let mut end = to_local(&UtcDateTime::now());
+    let max_session = Duration::hours(project.max_session_hours as i64);
+    if project.max_session_hours > 0 && end - start > max_session {
+        end = start + max_session;
+        warnings.push(format!(
+            "The session started {} has been open for more than {} hours, and only {} hours \
+             have been counted.  Did you forget to punch out?",
+            start.format(SESSION_START_FORMAT_LONG),
+            project.max_session_hours,
+            project.max_session_hours,
+        ));
+    }
+    Interval::new(&start, &end, break_time(events, &start, &end), overhead)
+}
+
+/// The time taken in breaks during the session between the given times, according to the break
+/// events among the given events.  Only the part of each break which falls within the session is
+/// counted.
+fn break_time(events: &[Event], start: &NaiveDateTime, end: &NaiveDateTime) -> Duration {
+    events
+        .iter()
+        .filter(|e| e.event_type == EventType::Break)
+        .fold(Duration::zero(), |total, e| {
+            let break_start = to_local(&e.clock);
+            let break_end = break_start + Duration::minutes(e.minutes.unwrap_or(0) as i64);
+            let break_start = break_start.max(*start);
+            let break_end = break_end.min(*end);
+            if break_end > break_start {
+                total + (break_end - break_start)
+            } else {
+                total
+            }
+        })
 }
@@ -0,0 +1,80 @@
+use actix_web;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::{Finished, Middleware, Response, Started};
+use actix_web::{HttpRequest, HttpResponse};
+use uuid::Uuid;
+
+use std::time::Instant;
+
+static REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Middleware to assign each request a unique id, so that log messages about the same request can
+/// be correlated.  The id is returned to the client in the `X-Request-Id` header, and a structured
+/// log line is written for each request when it finishes.  This should be the first middleware, so
+/// that the id is available to everything else.
+pub struct RequestIdService {}
+
+impl RequestIdService {
+    pub fn new() -> RequestIdService {
+        RequestIdService {}
+    }
+}
+
+impl<S> Middleware<S> for RequestIdService {
+    fn start(&self, request: &HttpRequest<S>) -> actix_web::error::Result<Started> {
+        request.extensions_mut().insert(RequestId {
+            id: Uuid::new_v4().to_string(),
+            start: Instant::now(),
+        });
+        Ok(Started::Done)
+    }
+
+    fn response(
+        &self,
+        request: &HttpRequest<S>,
+        mut response: HttpResponse,
+    ) -> actix_web::error::Result<Response> {
+        if let Ok(value) = HeaderValue::from_str(&request.request_id()) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(Response::Done(response))
+    }
+
+    fn finish(&self, request: &HttpRequest<S>, response: &HttpResponse) -> Finished {
+        let elapsed = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|r| r.start.elapsed())
+            .unwrap_or_default();
+        info!(
+            "request_id={} method={} path={:?} status={} elapsed_ms={}",
+            request.request_id(),
+            request.method(),
+            request.path(),
+            response.status().as_u16(),
+            elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+        );
+        Finished::Done
+    }
+}
+
+struct RequestId {
+    id: String,
+    start: Instant,
+}
+
+pub trait RequestIdentifier {
+    /// The unique id assigned to this request, or "-" if none was assigned.
+    fn request_id(&self) -> String;
+}
+
+impl<S> RequestIdentifier for HttpRequest<S> {
+    fn request_id(&self) -> String {
+        match self.extensions().get::<RequestId>() {
+            Some(r) => r.id.clone(),
+            None => "-".to_string(),
+        }
+    }
+}
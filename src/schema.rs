@@ -1,38 +1,67 @@
-use models::EventTypeMapping;
+use models::{
+    DurationFormatMapping, EventTypeMapping, OverheadModeMapping, PasswordAlgorithmMapping,
+    RoleMapping, TimeDisplayMapping, WeekStartMapping,
+};
 
 table! {
+    use diesel::sql_types::{BigInt,Binary,Integer};
+    use super::PasswordAlgorithmMapping;
     config (id) {
         id -> BigInt,
         secret -> Binary,
+        bcrypt_cost -> Integer,
+        default_overhead -> Integer,
+        flash_max_age -> Integer,
+        password_algorithm -> PasswordAlgorithmMapping,
+        max_note_length -> Integer,
     }
 }
 
 table! {
-    use diesel::sql_types::{BigInt,Timestamp};
+    use diesel::sql_types::{BigInt,Integer,Nullable,Text,Timestamp};
     use super::EventTypeMapping;
     events (id) {
         id -> BigInt,
         project_id -> BigInt,
         event_type -> EventTypeMapping,
         clock -> Timestamp,
+        note -> Nullable<Text>,
+        minutes -> Nullable<Integer>,
     }
 }
 
 table! {
+    use diesel::sql_types::{BigInt,Bool,Double,Integer,Text};
+    use super::{OverheadModeMapping, WeekStartMapping};
     projects (id) {
         id -> BigInt,
         user_id -> BigInt,
         name -> Text,
         overhead -> Integer,
+        max_session_hours -> Integer,
+        vacation_accrual_ratio -> Double,
+        weekly_target_minutes -> Integer,
+        daily_overtime_threshold_minutes -> Integer,
+        skip_weekends -> Bool,
+        overhead_mode -> OverheadModeMapping,
+        auto_split_hours -> Integer,
+        week_start -> WeekStartMapping,
+        idle_timeout_minutes -> Integer,
+        min_session_seconds -> Integer,
+        day_start_hour -> Integer,
     }
 }
 
 table! {
+    use diesel::sql_types::{BigInt,Nullable,Text};
+    use super::{DurationFormatMapping, RoleMapping, TimeDisplayMapping};
     users (id) {
         id -> BigInt,
         name -> Text,
         password -> Nullable<Text>,
-        admin -> Bool,
+        role -> RoleMapping,
+        time_display -> TimeDisplayMapping,
+        duration_format -> DurationFormatMapping,
     }
 }
 
@@ -1,74 +1,317 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
 use actix::prelude::*;
-use actix_web::middleware::identity::{CookieIdentityPolicy, IdentityService, RequestIdentity};
-use actix_web::middleware::{Middleware, Started};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::identity::{
+    CookieIdentity, CookieIdentityPolicy, Identity, IdentityPolicy, IdentityService,
+    RequestIdentity,
+};
+use actix_web::middleware::{Finished, Middleware, Response, Started};
+use actix_web::server::StopServer;
 use actix_web::{
-    self, middleware, App, AsyncResponder, Form, FutureResponse, HttpRequest, HttpResponse, State,
+    self, App, AsyncResponder, Form, FutureResponse, HttpMessage, HttpRequest, HttpResponse, Json,
+    Path, Query, ResponseError, State,
 };
 use askama::{self, Template};
+use futures::future::{self, Either};
 use futures::Future;
 
-use db::{self, AuthenticateUser, DatabaseError, DbExecutor, GetSummaryReport, PunchCommand};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+use audit::AuditLog;
+use csrf::{self, RequestCsrf};
+use db::{
+    self, AnySessionInProgress, AuthenticateUser, AutoPunchOut, BackupDatabase, ChangePassword,
+    CreateUser, DatabaseError, DbExecutor, DeleteEvent, EditEvent, EventPage,
+    GetAllProjectsSummary, GetDaySessions, GetDayStatus, GetDurationFormat, GetEvents,
+    GetEventsSince, GetLifetimeStats, GetNextDirection, GetNotes, GetProject, GetRole,
+    GetSummaryReport, GetTimeDisplay, LifetimeStats, ListUsers, Ping, PreviewReport, PunchCommand,
+    RenameProject, ReplaceDayEvents, SearchNotes, SetDurationFormat, SetRole, SetTimeDisplay,
+    TogglePunch, UndoLastPunch,
+};
 use flash::{self, RequestFlash};
-use models::PunchDirection;
-use report::SummaryReport;
+use metrics::Metrics;
+use models::{
+    DurationFormat, Event, EventType, OverheadMode, Project, PunchDirection, Role, Secret,
+    TimeDisplay, User,
+};
+use report::{self, DaySession, SummaryReport, TargetStatus};
+use request_id::{RequestIdService, RequestIdentifier};
+use time::{self, UtcDateTime, WorkTime};
+use timing::{RequestTiming, TimingService};
 
 const ROOT_PATH: &str = "/";
 const STATIC_PATH: &str = "/static";
-const LOGIN_PATH: &str = "/login";
+pub const LOGIN_PATH: &str = "/login";
 const LOGOUT_PATH: &str = "/logout";
+const PASSWORD_PATH: &str = "/password";
 const PUNCH_PATH: &str = "/punch";
+const UNDO_PUNCH_PATH: &str = "/punch/undo";
+const TOGGLE_PUNCH_PATH: &str = "/punch/toggle";
+const HEALTHZ_PATH: &str = "/healthz";
+const METRICS_PATH: &str = "/metrics";
+const EDIT_EVENT_PATH: &str = "/event/edit";
+const DELETE_EVENT_PATH: &str = "/event/delete";
+const EVENTS_PATH: &str = "/events";
+const SEARCH_PATH: &str = "/search";
+const NOTES_PATH: &str = "/notes";
+const PROJECTS_PATH: &str = "/projects";
+const DAY_PATH: &str = "/day/{date}";
+const TIME_DISPLAY_PATH: &str = "/prefs/time-display";
+const DURATION_FORMAT_PATH: &str = "/prefs/duration-format";
+const API_PATH: &str = "/api/";
+pub const API_PUNCH_PATH: &str = "/api/punch";
+const API_TOGGLE_PUNCH_PATH: &str = "/api/punch/toggle";
+pub const API_REPORT_PATH: &str = "/api/report";
+const API_REPORT_PREVIEW_PATH: &str = "/api/report/preview";
+const API_EVENTS_PATH: &str = "/api/events";
+const API_SEARCH_PATH: &str = "/api/search";
+const API_NOTES_PATH: &str = "/api/notes";
+const API_PROJECTS_SUMMARY_PATH: &str = "/api/projects/summary";
+const API_STATS_PATH: &str = "/api/stats";
+const API_STATUS_PATH: &str = "/api/status";
+const API_NEXT_DIRECTION_PATH: &str = "/api/next-direction";
+const API_DAY_PATH: &str = "/api/day/{date}";
+const ADMIN_PATH: &str = "/admin/";
+const ADMIN_USERS_PATH: &str = "/admin/users";
+const ADMIN_CREATE_USER_PATH: &str = "/admin/users/create";
+const ADMIN_SET_ROLE_PATH: &str = "/admin/users/role";
+const ADMIN_RENAME_PROJECT_PATH: &str = "/admin/project/rename";
+const ADMIN_BACKUP_PATH: &str = "/admin/backup";
+
+/// The number of events shown on each page of the event history.
+const EVENTS_PAGE_SIZE: usize = 25;
+
+/// The largest page of events which API clients may request.
+const MAX_EVENTS_PAGE_SIZE: usize = 500;
+
+/// The most past weeks which the dashboard can be asked to show with "?weeks=".
+const MAX_DASHBOARD_WEEKS: u32 = 104;
+
+/// The most days which the dashboard can be asked to show with "?days=".
+const MAX_DASHBOARD_DAYS: u32 = 93;
+
+/// The most recent events which the dashboard can be asked to show with "?events=".
+const MAX_DASHBOARD_EVENTS: u32 = 500;
+
+/// How often open sessions are checked against the idle timeout.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The format used by HTML "datetime-local" inputs.
+const FORM_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+/// The format of the date in the day pages' paths.
+const DAY_PATH_FORMAT: &str = "%Y-%m-%d";
+
+/// How the web server's cookies are protected.
+pub struct CookieOptions {
+    /// Only send cookies over HTTPS.
+    pub secure: bool,
+    /// The key which signs the identity cookie, in place of the one stored in the database, so
+    /// that sessions can outlive the database or be shared between servers.
+    pub secret: Option<Secret>,
+}
+
+/// Where the web server's routes and static resources are found.
+pub struct PathOptions<'a> {
+    /// The path under which every route is served, such as "/time" behind a reverse proxy, with no
+    /// trailing slash.  This is empty when serving from the root.
+    pub base_path: String,
+    /// The directory of static resources.
+    pub static_path: &'a str,
+    /// List directories under the static path.
+    pub listing: bool,
+}
 
-/// Launch the Actix-web web server.
-pub fn do_server(database: &str, bind: &str, static_path: &str) {
+/// Launch the Actix-web web server, and run it until it is asked to stop.  Directories under the
+/// static path are only listed if `listing` is set, and the cookies are protected as the `cookies`
+/// options say.  Database queries run on `sync_threads` threads, which share a pool of
+/// `db_connections` connections.
+pub fn do_server(
+    database: &str,
+    bind: &str,
+    paths: PathOptions,
+    cookies: CookieOptions,
+    audit_log: Option<AuditLog>,
+    db_connections: u32,
+    sync_threads: usize,
+) -> Result<(), DatabaseError> {
     let sys = actix::System::new("punch");
 
-    let (db_addr, config) = db::database_init(database).unwrap();
+    let (db_addr, mut config) =
+        db::database_init(database, db_connections, sync_threads, audit_log)?;
+    let CookieOptions {
+        secure: secure_cookies,
+        secret,
+    } = cookies;
+    if let Some(secret) = secret {
+        // The stored secret is zeroed as it's dropped.
+        config.secret = secret;
+    }
+    let PathOptions {
+        base_path,
+        static_path,
+        listing,
+    } = paths;
     let static_path: PathBuf = PathBuf::from(static_path);
+    let metrics = Arc::new(Metrics::new());
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let server_in_flight = in_flight.clone();
+    let activity: Activity = Arc::new(Mutex::new(HashMap::new()));
+
+    // Punch out sessions which have been left idle
+    IdleMonitor {
+        db: db_addr.clone(),
+        activity: activity.clone(),
+    }.start();
 
     // Start http server
-    actix_web::server::new(move || {
-        App::with_state(AppState{db: db_addr.clone()})
-            .handler(STATIC_PATH,
-                     actix_web::fs::StaticFiles::new(&static_path).unwrap()
-                        .show_files_listing()
-                     )
-            // logger
-            .middleware(middleware::Logger::default())
+    let server = actix_web::server::new(move || {
+        let static_files = actix_web::fs::StaticFiles::new(&static_path).unwrap();
+        let static_files = if listing {
+            static_files.show_files_listing()
+        } else {
+            static_files
+        };
+        let app = App::with_state(AppState{
+                db: db_addr.clone(),
+                metrics: metrics.clone(),
+                base_path: base_path.clone(),
+                max_note_length: config.max_note_length,
+            })
+            .prefix(base_path.as_str())
+            .handler(STATIC_PATH, static_files)
+            // request ids and structured request logging
+            .middleware(RequestIdService::new())
+            // handler timing for performance debugging
+            .middleware(TimingService::new())
+            // in-flight request tracking
+            .middleware(InFlightService::new(server_in_flight.clone()))
             // cookie-auth example
-            .middleware(IdentityService::new(
+            .middleware(IdentityService::new(ApiIdentityPolicy::new(
                 CookieIdentityPolicy::new(&config.secret.data)
                     .name("auth")
-                    .secure(false),
-            ))
+                    .secure(secure_cookies),
+            )))
             // authentication
             .middleware(AuthService::new())
+            // user activity tracking
+            .middleware(ActivityService::new(activity.clone()))
+            // role authorization
+            .middleware(RoleService::new())
             // flash messages
-            .middleware(flash::FlashService::new())
+            .middleware(flash::FlashService::new(secure_cookies, config.flash_max_age))
+            // cross-site request forgery protection
+            .middleware(csrf::CsrfService::new(secure_cookies))
             // resources
             .resource(LOGIN_PATH, |r| {
-                r.get().f(|req| login_get(req));
+                r.get().f(login_get);
                 r.post().with(login_post);
             })
-            .resource(LOGOUT_PATH, |r| r.f(logout))
+            .resource(LOGOUT_PATH, |r| {
+                r.get().f(logout_get);
+                r.post().with(logout_post);
+            })
+            .resource(PASSWORD_PATH, |r| {
+                r.get().f(password_get);
+                r.post().with(password_post);
+            })
             .resource(PUNCH_PATH, |r| {
                 r.post().with(punch);
             })
+            .resource(UNDO_PUNCH_PATH, |r| {
+                r.post().with(undo_punch);
+            })
+            .resource(TOGGLE_PUNCH_PATH, |r| {
+                r.post().with(toggle_punch);
+            })
+            .resource(EDIT_EVENT_PATH, |r| {
+                r.post().with(edit_event);
+            })
+            .resource(DELETE_EVENT_PATH, |r| {
+                r.post().with(delete_event);
+            })
+            .resource(EVENTS_PATH, |r| r.get().with(events_get))
+            .resource(SEARCH_PATH, |r| r.get().with(search_get))
+            .resource(NOTES_PATH, |r| r.get().with(notes_get))
+            .resource(PROJECTS_PATH, |r| r.get().with(projects_get))
+            .resource(DAY_PATH, |r| r.get().with(day_get))
+            .resource(TIME_DISPLAY_PATH, |r| r.post().with(set_time_display))
+            .resource(DURATION_FORMAT_PATH, |r| r.post().with(set_duration_format))
+            .resource(API_PUNCH_PATH, |r| r.post().with(api_punch))
+            .resource(API_TOGGLE_PUNCH_PATH, |r| r.post().with(api_toggle_punch))
+            .resource(API_REPORT_PATH, |r| r.get().with(api_report))
+            .resource(API_REPORT_PREVIEW_PATH, |r| r.get().with(api_report_preview))
+            .resource(API_EVENTS_PATH, |r| r.get().with(api_events))
+            .resource(API_SEARCH_PATH, |r| r.get().with(api_search))
+            .resource(API_NOTES_PATH, |r| r.get().with(api_notes))
+            .resource(API_PROJECTS_SUMMARY_PATH, |r| r.get().with(api_projects_summary))
+            .resource(API_STATS_PATH, |r| r.get().with(api_stats))
+            .resource(API_STATUS_PATH, |r| r.get().with(api_status))
+            .resource(API_NEXT_DIRECTION_PATH, |r| r.get().with(api_next_direction))
+            .resource(API_DAY_PATH, |r| {
+                r.get().with(api_day);
+                r.put().with(api_day_put);
+            })
+            .resource(ADMIN_USERS_PATH, |r| r.get().with(admin_users_get))
+            .resource(ADMIN_CREATE_USER_PATH, |r| r.post().with(admin_create_user))
+            .resource(ADMIN_SET_ROLE_PATH, |r| r.post().with(admin_set_role))
+            .resource(ADMIN_RENAME_PROJECT_PATH, |r| r.post().with(admin_rename_project))
+            .resource(ADMIN_BACKUP_PATH, |r| r.get().with(admin_backup_get))
+            .resource(HEALTHZ_PATH, |r| r.get().with(healthz))
+            .resource(METRICS_PATH, |r| r.get().with(metrics_get))
             .resource(ROOT_PATH, |r| r.get().with(index))
+            .default_resource(|r| r.f(not_found));
+        // Actix-web garbles its own reply to a request which no application accepts, so requests
+        // outside the base path are refused here instead.
+        let outside = App::new().default_resource(|r| r.f(|_| HttpResponse::NotFound()));
+        vec![app.boxed(), outside.boxed()]
     }).bind(bind)
         .unwrap()
+        .disable_signals()
         .start();
 
+    // Stop gracefully on termination signals
+    ShutdownHandler {
+        server: server.recipient(),
+        in_flight,
+    }.start();
+
     println!("Started http server: {}", bind);
     let _ = sys.run();
+    Ok(())
+}
+
+/// The fields of a form which carries nothing but its CSRF token.
+#[derive(Deserialize, Debug)]
+struct CsrfForm {
+    csrf_token: String,
+}
+
+/// Refuse a form submission whose CSRF token is missing or doesn't match the browser's cookie.
+fn csrf_rejection() -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("text/plain")
+        .body("Invalid or missing CSRF token.  Try reloading the page.")
+}
+
+/// Refuse a form submission, as with `csrf_rejection`, from an asynchronous handler.
+fn csrf_failure() -> FutureResponse<HttpResponse> {
+    Box::new(future::ok(csrf_rejection()))
 }
 
 /// Render an Askama template as an HttpResponse.
 /// TODO: Investigate the use of the "with-actix-web" Askama feature which may eliminate the need
 /// for this function.
 fn render_html(template: impl askama::Template) -> HttpResponse {
-    match template.render().map_err(|e| TemplateError(e)) {
+    match template.render().map_err(TemplateError) {
         Ok(s) => HttpResponse::Ok().content_type("text/html").body(s),
         Err(e) => {
             error!("{}", e);
@@ -80,6 +323,46 @@ fn render_html(template: impl askama::Template) -> HttpResponse {
 /// Application state with DbExecutor address
 struct AppState {
     db: Addr<DbExecutor>,
+    metrics: Arc<Metrics>,
+    /// The path under which the application is served, such as "/time" behind a reverse proxy, or
+    /// an empty string when it is served from the root.
+    base_path: String,
+    /// The longest note which may be attached to an event, as configured when the server started.
+    max_note_length: usize,
+}
+
+/// Translate between the paths of the application's routes, such as `LOGIN_PATH`, and the paths
+/// seen by the browser, which are under the base path.
+trait RequestPaths {
+    /// The base path, for prefixing the links in templates.
+    fn base_path(&self) -> &str;
+    /// The request's path with the base path removed, for comparison with the route paths.
+    fn route_path(&self) -> &str;
+    /// Redirect to one of the application's routes.
+    fn redirect(&self, path: &str) -> HttpResponse;
+}
+
+impl RequestPaths for HttpRequest<AppState> {
+    fn base_path(&self) -> &str {
+        &self.state().base_path
+    }
+
+    fn route_path(&self) -> &str {
+        let base_path = self.base_path();
+        let path = self.path();
+        if let Some(rest) = path.strip_prefix(base_path) {
+            if rest.is_empty() || rest.starts_with('/') {
+                return rest;
+            }
+        }
+        path
+    }
+
+    fn redirect(&self, path: &str) -> HttpResponse {
+        HttpResponse::Found()
+            .header("location", format!("{}{}", self.base_path(), path))
+            .finish()
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////
@@ -98,20 +381,378 @@ impl Middleware<AppState> for AuthService {
         match req.identity() {
             Some(_) => Ok(Started::Done), // User is authenticated
             None => {
-                let path = req.path();
-                if path == LOGIN_PATH || path.starts_with(STATIC_PATH) {
-                    // No authentication is needed to get to the login page itself or the static
-                    // assets.
+                let path = req.route_path();
+                if req.resource().rdef().is_none() {
+                    // Paths which match no resource get the 404 page rather than a misleading
+                    // redirect to the login page.
                     Ok(Started::Done)
-                } else {
-                    // Redirect to the login page.
+                } else if path == LOGIN_PATH
+                    || path == HEALTHZ_PATH
+                    || path == METRICS_PATH
+                    || path.starts_with(STATIC_PATH)
+                {
+                    // No authentication is needed to get to the login page itself, the health
+                    // check and metrics endpoints, or the static assets.
+                    Ok(Started::Done)
+                } else if path.starts_with(API_PATH) {
+                    // API clients can't follow a redirect to the login page, so just refuse, and
+                    // invite them to present Basic credentials instead.
                     Ok(Started::Response(
-                        HttpResponse::Found()
-                            .header("location", LOGIN_PATH)
-                            .finish(),
+                        HttpResponse::Unauthorized()
+                            .header(header::WWW_AUTHENTICATE, BASIC_AUTH_CHALLENGE)
+                            .json(ApiError::new("NOT_LOGGED_IN", "Not logged in")),
                     ))
+                } else {
+                    // Redirect to the login page.
+                    Ok(Started::Response(req.redirect(LOGIN_PATH)))
+                }
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////
+
+/// The challenge sent with a 401 response to an API request.
+const BASIC_AUTH_CHALLENGE: &str = "Basic realm=\"punch\"";
+
+/// Identity policy which lets API clients send HTTP Basic credentials with each request, as an
+/// alternative to the identity cookie.  Requests under `/api/` with an `Authorization` header are
+/// checked against the users table, and everything else falls back to the cookie.  A Basic
+/// identity lasts only for its request, so no cookie is ever issued for it.
+///
+/// The API's changes aren't guarded by CSRF tokens, so the cookie only counts for them when the
+/// request is JSON, which a form posted from another site can't be.
+struct ApiIdentityPolicy {
+    cookie: CookieIdentityPolicy,
+}
+
+impl ApiIdentityPolicy {
+    fn new(cookie: CookieIdentityPolicy) -> ApiIdentityPolicy {
+        ApiIdentityPolicy { cookie }
+    }
+}
+
+impl IdentityPolicy<AppState> for ApiIdentityPolicy {
+    type Identity = ApiIdentity;
+    type Future = Box<dyn Future<Item = ApiIdentity, Error = actix_web::Error>>;
+
+    fn from_request(&self, req: &HttpRequest<AppState>) -> Self::Future {
+        let authorization = match req.headers().get(header::AUTHORIZATION) {
+            Some(value) if req.route_path().starts_with(API_PATH) => value,
+            _ if !cookie_trusted(req.route_path(), req.method(), req.content_type()) => {
+                return Box::new(future::ok(ApiIdentity::Basic(None)))
+            }
+            _ => return Box::new(self.cookie.from_request(req).map(ApiIdentity::Cookie)),
+        };
+        let (username, password) = match basic_credentials(authorization) {
+            Some(credentials) => credentials,
+            None => return Box::new(future::ok(ApiIdentity::Basic(None))),
+        };
+        let auth = AuthenticateUser { username, password };
+        Box::new(req.state().db.send(auth).from_err().map(move |res| {
+            // Unknown users are refused the same as wrong passwords.
+            match res {
+                Ok(Some(username)) => ApiIdentity::Basic(Some(username)),
+                _ => ApiIdentity::Basic(None),
+            }
+        }))
+    }
+}
+
+/// Whether the identity cookie may vouch for the given request.  Browsers attach the cookie to
+/// requests from any site, so an API request which changes anything must also be JSON: other sites
+/// can only send that with the server's consent, which it never gives.
+fn cookie_trusted(path: &str, method: &Method, content_type: &str) -> bool {
+    !path.starts_with(API_PATH)
+        || *method == Method::GET
+        || *method == Method::HEAD
+        || content_type == "application/json"
+}
+
+/// Parse the username and password from the value of an `Authorization: Basic` header, or return
+/// `None` if it is some other scheme or is malformed.
+fn basic_credentials(authorization: &HeaderValue) -> Option<(String, String)> {
+    let authorization = authorization.to_str().ok()?;
+    let mut parts = authorization.splitn(2, ' ');
+    if !parts.next()?.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::decode(parts.next()?.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    let username = parts.next()?.to_string();
+    let password = parts.next()?.to_string();
+    Some((username, password))
+}
+
+enum ApiIdentity {
+    Cookie(CookieIdentity),
+    /// The user whose Basic credentials were verified, or `None` if they were refused.
+    Basic(Option<String>),
+}
+
+impl Identity for ApiIdentity {
+    fn identity(&self) -> Option<&str> {
+        match self {
+            ApiIdentity::Cookie(cookie) => cookie.identity(),
+            ApiIdentity::Basic(username) => username.as_ref().map(|s| s.as_str()),
+        }
+    }
+
+    fn remember(&mut self, key: String) {
+        match self {
+            ApiIdentity::Cookie(cookie) => cookie.remember(key),
+            ApiIdentity::Basic(username) => *username = Some(key),
+        }
+    }
+
+    fn forget(&mut self) {
+        match self {
+            ApiIdentity::Cookie(cookie) => cookie.forget(),
+            ApiIdentity::Basic(username) => *username = None,
+        }
+    }
+
+    fn write(&mut self, response: HttpResponse) -> actix_web::error::Result<Response> {
+        match self {
+            ApiIdentity::Cookie(cookie) => cookie.write(response),
+            ApiIdentity::Basic(_) => Ok(Response::Done(response)),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////
+
+/// Middleware to look up the role of the requesting user, and refuse requests which the role
+/// doesn't allow: the administration pages require an administrator, and viewers may not punch or
+/// change events.  The role is kept in the request extensions for handlers to consult.  This must
+/// follow `AuthService`, which turns away requests without an identity.
+struct RoleService {}
+
+impl RoleService {
+    fn new() -> RoleService {
+        RoleService {}
+    }
+}
+
+/// Routes which change the user's events, and so are refused to viewers.
+const VIEWER_FORBIDDEN_ROUTES: [(Method, &str); 8] = [
+    (Method::POST, PUNCH_PATH),
+    (Method::POST, UNDO_PUNCH_PATH),
+    (Method::POST, TOGGLE_PUNCH_PATH),
+    (Method::POST, EDIT_EVENT_PATH),
+    (Method::POST, DELETE_EVENT_PATH),
+    (Method::POST, API_PUNCH_PATH),
+    (Method::POST, API_TOGGLE_PUNCH_PATH),
+    (Method::PUT, API_DAY_PATH),
+];
+
+/// Whether a request path matches a route path, in which each `{name}` segment matches any one
+/// segment.
+fn route_matches(route: &str, path: &str) -> bool {
+    let mut route_segments = route.split('/');
+    let mut path_segments = path.split('/');
+    loop {
+        match (route_segments.next(), path_segments.next()) {
+            (None, None) => return true,
+            (Some(r), Some(p)) if r == p || (r.starts_with('{') && r.ends_with('}')) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Whether a viewer is refused the given request because it would change their events.
+fn viewer_forbidden(method: &Method, path: &str) -> bool {
+    VIEWER_FORBIDDEN_ROUTES
+        .iter()
+        .any(|(route_method, route)| route_method == method && route_matches(route, path))
+}
+
+impl Middleware<AppState> for RoleService {
+    fn start(&self, req: &HttpRequest<AppState>) -> actix_web::error::Result<Started> {
+        let username = match req.identity() {
+            Some(username) => username,
+            None => return Ok(Started::Done),
+        };
+        let req = req.clone();
+        let forbidden = req
+            .state()
+            .db
+            .send(GetRole { username })
+            .from_err()
+            .map(move |res| {
+                // Without a role, the user is only trusted as far as a viewer.
+                let role = res.unwrap_or(Role::Viewer);
+                req.extensions_mut().insert(role);
+                let path = req.route_path();
+                if path.starts_with(ADMIN_PATH) && role != Role::Admin {
+                    Some(
+                        HttpResponse::Forbidden()
+                            .content_type("text/plain")
+                            .body("Administrator access is required."),
+                    )
+                } else if role == Role::Viewer && viewer_forbidden(req.method(), path) {
+                    Some(if path.starts_with(API_PATH) {
+                        HttpResponse::Forbidden()
+                            .json(ApiError::new("FORBIDDEN", "Viewers cannot make changes"))
+                    } else {
+                        HttpResponse::Forbidden()
+                            .content_type("text/plain")
+                            .body("Viewers cannot make changes.")
+                    })
+                } else {
+                    None
                 }
+            });
+        Ok(Started::Future(Box::new(forbidden)))
+    }
+}
+
+/// Access to the requesting user's role, as found by `RoleService`.
+trait RequestRole {
+    fn role(&self) -> Role;
+}
+
+impl RequestRole for HttpRequest<AppState> {
+    fn role(&self) -> Role {
+        self.extensions()
+            .get::<Role>()
+            .cloned()
+            .unwrap_or(Role::Viewer)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////
+
+/// Middleware to count the requests currently being processed, so that shutdown can report on
+/// them.
+struct InFlightService {
+    count: Arc<AtomicUsize>,
+}
+
+impl InFlightService {
+    fn new(count: Arc<AtomicUsize>) -> InFlightService {
+        InFlightService { count }
+    }
+}
+
+impl<S> Middleware<S> for InFlightService {
+    fn start(&self, _: &HttpRequest<S>) -> actix_web::error::Result<Started> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        Ok(Started::Done)
+    }
+
+    fn finish(&self, _: &HttpRequest<S>, _: &HttpResponse) -> Finished {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+        Finished::Done
+    }
+}
+
+/// The time of each user's most recent request, by username.
+type Activity = Arc<Mutex<HashMap<String, UtcDateTime>>>;
+
+/// Middleware to note the time of each authenticated request, so that `IdleMonitor` can tell when
+/// a user has walked away without punching out.
+struct ActivityService {
+    activity: Activity,
+}
+
+impl ActivityService {
+    fn new(activity: Activity) -> ActivityService {
+        ActivityService { activity }
+    }
+}
+
+impl Middleware<AppState> for ActivityService {
+    fn start(&self, req: &HttpRequest<AppState>) -> actix_web::error::Result<Started> {
+        if let Some(username) = req.identity() {
+            self.activity
+                .lock()
+                .unwrap()
+                .insert(username, UtcDateTime::now());
+        }
+        Ok(Started::Done)
+    }
+}
+
+/// Actor which periodically punches out sessions that have been idle for longer than their
+/// project's idle timeout.
+struct IdleMonitor {
+    db: Addr<DbExecutor>,
+    activity: Activity,
+}
+
+impl Actor for IdleMonitor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(IDLE_CHECK_INTERVAL, |monitor, ctx| {
+            let last_activity = monitor.activity.lock().unwrap().clone();
+            ctx.spawn(
+                monitor
+                    .db
+                    .send(AutoPunchOut { last_activity })
+                    .into_actor(monitor)
+                    .then(|res, _, _| {
+                        match res {
+                            Ok(Ok(usernames)) => {
+                                for username in usernames {
+                                    info!("Punched out {} after the idle timeout", username);
+                                }
+                            }
+                            Ok(Err(e)) => error!("Unable to check for idle sessions: {}", e),
+                            Err(e) => error!("Unable to check for idle sessions: {}", e),
+                        }
+                        actix::fut::ok(())
+                    }),
+            );
+        });
+    }
+}
+
+/// Actor which responds to SIGTERM, SIGINT, and SIGQUIT by stopping the http server gracefully,
+/// and then stopping the system.  A graceful stop closes the listening sockets right away, but
+/// lets in-flight requests (and the `DbExecutor` messages they are waiting on) run to completion,
+/// so a punch isn't interrupted halfway through.
+struct ShutdownHandler {
+    server: Recipient<StopServer>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Actor for ShutdownHandler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let signals = System::current().registry().get::<ProcessSignals>();
+        signals.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<Signal> for ShutdownHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, ctx: &mut Self::Context) {
+        match msg.0 {
+            SignalType::Term | SignalType::Int | SignalType::Quit => {
+                println!(
+                    "Shutting down: {} request(s) in flight",
+                    self.in_flight.load(Ordering::SeqCst)
+                );
+                ctx.spawn(
+                    self.server
+                        .send(StopServer { graceful: true })
+                        .into_actor(self)
+                        .then(|res, _, _| {
+                            if let Ok(Err(())) | Err(_) = res {
+                                error!("The http server did not stop cleanly.");
+                            }
+                            System::current().stop();
+                            actix::fut::ok(())
+                        }),
+                );
             }
+            _ => {}
         }
     }
 }
@@ -124,44 +765,205 @@ impl Middleware<AppState> for AuthService {
 #[fail(display = "Template error: {}", _0)]
 pub struct TemplateError(askama::Error);
 
+// Every template is given the base path, which prefixes each of its links.
+
 #[derive(Template)]
 #[template(path = "login.html")]
 struct LoginTemplate<'a> {
+    base_path: &'a str,
     error_message: Option<&'a str>,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "logout.html")]
+struct LogoutTemplate<'a> {
+    base_path: &'a str,
+    username: &'a str,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "password.html")]
+struct PasswordTemplate<'a> {
+    base_path: &'a str,
+    username: &'a str,
+    error_message: Option<String>,
+    success_message: Option<&'a str>,
+    csrf_token: String,
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate<'a> {
+    base_path: &'a str,
     username: &'a str,
+    /// The name of the active project, shown in the header.
+    project_name: String,
     error_message: Option<String>,
     report: Option<SummaryReport>,
+    /// Offer to undo the punch which was just made.
+    undo: bool,
+    /// Offer to punch and edit events, which viewers can't do.
+    editable: bool,
+    /// Which of the gross and net times to show, as the user prefers.
+    time_display: TimeDisplay,
+    time_displays: &'a [TimeDisplay],
+    /// How to write durations, as the user prefers.
+    duration_format: DurationFormat,
+    duration_formats: &'a [DurationFormat],
+    /// The longest note which the edit forms accept.
+    max_note_length: usize,
+    csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "events.html")]
+struct EventsTemplate<'a> {
+    base_path: &'a str,
+    username: &'a str,
+    error_message: Option<String>,
+    page: Option<EventPage>,
+}
+
+#[derive(Template)]
+#[template(path = "notes.html")]
+struct NotesTemplate<'a> {
+    base_path: &'a str,
+    username: &'a str,
+    error_message: Option<String>,
+    page: Option<EventPage>,
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchTemplate<'a> {
+    base_path: &'a str,
+    username: &'a str,
+    error_message: Option<String>,
+    query: &'a str,
+    results: Option<Vec<Event>>,
+}
+
+#[derive(Template)]
+#[template(path = "day.html")]
+struct DayTemplate<'a> {
+    base_path: &'a str,
+    username: &'a str,
+    error_message: Option<String>,
+    date: NaiveDate,
+    sessions: Vec<DaySession>,
+    total: WorkTime,
+    duration_format: DurationFormat,
+}
+
+#[derive(Template)]
+#[template(path = "projects.html")]
+struct ProjectsTemplate<'a> {
+    base_path: &'a str,
+    username: &'a str,
+    error_message: Option<String>,
+    projects: Vec<(Project, WorkTime)>,
+    /// The totals since the first punch, for the banner.  The banner is left out if they couldn't
+    /// be loaded, or if there has never been a punch.
+    stats: Option<LifetimeStats>,
+    duration_format: DurationFormat,
+}
+
+#[derive(Template)]
+#[template(path = "not_found.html")]
+struct NotFoundTemplate<'a> {
+    base_path: &'a str,
+    path: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "admin_users.html")]
+struct AdminUsersTemplate<'a> {
+    base_path: &'a str,
+    username: &'a str,
+    error_message: Option<String>,
+    users: Vec<User>,
+    roles: &'a [Role],
+    /// The administrator's own project, which may be renamed.
+    project: Option<Project>,
+    csrf_token: String,
 }
 
 ////////////////////////////////////////////////////////////////////////
 // Endpoint handlers
 ////////////////////////////////////////////////////////////////////////
 
+/// Request the summary report of the logged-in user, with the number of past weeks, of days, and of
+/// recent events given in the query string, if any.  Values which are out of range are clamped,
+/// and those which can't be parsed are ignored.
+fn report_request(request: &HttpRequest<AppState>) -> GetSummaryReport {
+    let query = request.query();
+    let clamped = |name: &str, min: u32, max: u32| {
+        query
+            .get(name)
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(|value| value.max(u64::from(min)).min(u64::from(max)) as u32)
+    };
+    GetSummaryReport {
+        username: request.identity().unwrap_or("".to_string()),
+        weeks_in_past: clamped("weeks", 0, MAX_DASHBOARD_WEEKS)
+            .unwrap_or(report::DEFAULT_REPORT_WEEKS),
+        days: clamped("days", 1, MAX_DASHBOARD_DAYS),
+        max_events: clamped("events", 0, MAX_DASHBOARD_EVENTS)
+            .map(|events| events as usize)
+            .unwrap_or(report::DEFAULT_REPORT_EVENTS),
+    }
+}
+
 fn index(
     (request, state): (HttpRequest<AppState>, State<AppState>),
 ) -> FutureResponse<HttpResponse> {
+    let db_start = Instant::now();
     state
         .db
-        .send(GetSummaryReport {})
+        .send(report_request(&request))
+        .join3(
+            state.db.send(GetTimeDisplay {
+                username: request.identity().unwrap_or("".to_string()),
+            }),
+            state.db.send(GetDurationFormat {
+                username: request.identity().unwrap_or("".to_string()),
+            }),
+        )
         .from_err()
-        .and_then(move |report| {
+        .and_then(move |(report, time_display, duration_format)| {
+            request.record_db_time(db_start.elapsed());
             let error_message = request.get_flash_message();
             let report = match report {
                 Ok(report) => Some(report),
                 Err(e) => {
-                    error!("Unable to produce report: {}", e);
+                    error!(
+                        "request_id={} Unable to produce report: {}",
+                        request.request_id(),
+                        e
+                    );
                     None
                 }
             };
+            let project_name = report
+                .as_ref()
+                .map(|report| report.project_name.clone())
+                .unwrap_or_default();
             Ok(render_html(IndexTemplate {
+                base_path: request.base_path(),
                 username: &request.identity().unwrap_or("".to_string()),
+                project_name,
                 error_message,
                 report,
+                undo: request.query().contains_key("punched"),
+                editable: request.role() != Role::Viewer,
+                time_display: time_display.unwrap_or(TimeDisplay::Both),
+                time_displays: &TimeDisplay::ALL,
+                duration_format: duration_format.unwrap_or(DurationFormat::HoursMinutes),
+                duration_formats: &DurationFormat::ALL,
+                max_note_length: request.state().max_note_length,
+                csrf_token: request.csrf_token(),
             }))
         })
         .responder()
@@ -171,44 +973,160 @@ fn index(
 struct LoginForm {
     username: String,
     password: String,
+    csrf_token: String,
 }
 
-fn login_get(_: &HttpRequest<AppState>) -> HttpResponse {
+fn login_get(req: &HttpRequest<AppState>) -> HttpResponse {
     render_html(LoginTemplate {
+        base_path: req.base_path(),
         error_message: None,
+        csrf_token: req.csrf_token(),
     })
 }
 
 fn login_post(
     (req, state, params): (HttpRequest<AppState>, State<AppState>, Form<LoginForm>),
 ) -> FutureResponse<HttpResponse> {
-    let LoginForm { username, password } = params.into_inner();
+    let LoginForm {
+        username,
+        password,
+        csrf_token,
+    } = params.into_inner();
+    if !req.verify_csrf_token(&csrf_token) {
+        return csrf_failure();
+    }
+    let metrics = state.metrics.clone();
     state
         .db
-        .send(AuthenticateUser {
-            username: username.clone(),
-            password,
-        })
+        .send(AuthenticateUser { username, password })
         .from_err()
         .and_then(move |res| match res {
-            Ok(true) => {
+            Ok(Some(username)) => {
                 // Login successful
                 req.remember(username);
-                Ok(HttpResponse::Found().header("location", "/").finish())
+                req.renew_csrf_token();
+                Ok(req.redirect("/"))
             }
-            Ok(false) | Err(_) => {
+            Ok(None) | Err(_) => {
                 // Bad username or password
+                if let Err(e) = res {
+                    error!(
+                        "request_id={} Unable to authenticate: {}",
+                        req.request_id(),
+                        e
+                    );
+                }
+                metrics.record_login_failure();
                 Ok(render_html(LoginTemplate {
+                    base_path: req.base_path(),
                     error_message: Some("Invalid username and/or password."),
+                    csrf_token: req.csrf_token(),
                 }))
             }
         })
         .responder()
 }
 
-fn logout(req: &HttpRequest<AppState>) -> HttpResponse {
+/// Ask the user to confirm signing off.  Signing off takes a form post, so that another site
+/// can't sign the user off by linking to this page.
+fn logout_get(req: &HttpRequest<AppState>) -> HttpResponse {
+    render_html(LogoutTemplate {
+        base_path: req.base_path(),
+        username: &req.identity().unwrap_or("".to_string()),
+        csrf_token: req.csrf_token(),
+    })
+}
+
+fn logout_post((req, params): (HttpRequest<AppState>, Form<CsrfForm>)) -> HttpResponse {
+    if !req.verify_csrf_token(&params.csrf_token) {
+        return csrf_rejection();
+    }
     req.forget();
-    HttpResponse::Found().header("location", "/").finish()
+    req.renew_csrf_token();
+    req.redirect(LOGIN_PATH)
+}
+
+#[derive(Deserialize)]
+struct PasswordForm {
+    current_password: String,
+    new_password: String,
+    confirm_password: String,
+    csrf_token: String,
+}
+
+fn password_get(req: &HttpRequest<AppState>) -> HttpResponse {
+    render_html(PasswordTemplate {
+        base_path: req.base_path(),
+        username: &req.identity().unwrap_or("".to_string()),
+        error_message: None,
+        success_message: None,
+        csrf_token: req.csrf_token(),
+    })
+}
+
+fn password_post(
+    (req, state, params): (HttpRequest<AppState>, State<AppState>, Form<PasswordForm>),
+) -> FutureResponse<HttpResponse> {
+    let PasswordForm {
+        current_password,
+        new_password,
+        confirm_password,
+        csrf_token,
+    } = params.into_inner();
+    if !req.verify_csrf_token(&csrf_token) {
+        return csrf_failure();
+    }
+    let username = req.identity().unwrap_or("".to_string());
+    let base_path = req.base_path().to_string();
+    let render_error = move |username: &str, message: String| {
+        render_html(PasswordTemplate {
+            base_path: &base_path,
+            username,
+            error_message: Some(message),
+            success_message: None,
+            csrf_token: csrf_token.clone(),
+        })
+    };
+    if new_password != confirm_password {
+        return Box::new(future::ok(render_error(
+            &username,
+            "The new passwords do not match.".to_string(),
+        )));
+    }
+
+    // Confirm the current password before applying the change.
+    let db = state.db.clone();
+    state
+        .db
+        .send(AuthenticateUser {
+            username: username.clone(),
+            password: current_password,
+        })
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(Some(_)) => Either::A(
+                db.send(ChangePassword {
+                    username: username.clone(),
+                    new_password,
+                })
+                .from_err()
+                .and_then(move |res| match res {
+                    Ok(()) => Ok(render_html(PasswordTemplate {
+                        base_path: req.base_path(),
+                        username: &username,
+                        error_message: None,
+                        success_message: Some("Your password has been changed."),
+                        csrf_token: req.csrf_token(),
+                    })),
+                    Err(e) => Ok(render_error(&username, format!("{}", e))),
+                }),
+            ),
+            Ok(None) | Err(_) => Either::B(future::ok(render_error(
+                &username,
+                "The current password is incorrect.".to_string(),
+            ))),
+        })
+        .responder()
 }
 
 #[derive(Deserialize, Debug)]
@@ -218,18 +1136,37 @@ struct PunchForm {
     direction: PunchDirection,
 
     note: Option<String>,
+    /// The local time of the punch, if it isn't now.
+    at: Option<String>,
+    csrf_token: String,
 }
 
 fn punch(
     (mut req, state, params): (HttpRequest<AppState>, State<AppState>, Form<PunchForm>),
 ) -> FutureResponse<HttpResponse> {
     let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    let direction = form.direction;
+    let at = match form.at.as_ref().map(|at| at.trim()) {
+        Some(at) if !at.is_empty() => match NaiveDateTime::parse_from_str(at, FORM_TIME_FORMAT) {
+            Ok(at) => Some(at),
+            Err(_) => {
+                req.set_flash_message(format!("{}", DatabaseError::BadTime));
+                return Box::new(future::ok(req.redirect("/")));
+            }
+        },
+        _ => None,
+    };
+    let metrics = state.metrics.clone();
     state
         .db
         .send(PunchCommand {
             username: req.identity().unwrap_or("".to_string()),
-            direction: form.direction,
+            direction,
             note: form.note,
+            at,
         })
         .from_err()
         .and_then(move |res| {
@@ -240,11 +1177,1267 @@ fn punch(
                     );
                 }
                 Err(e) => {
+                    error!("request_id={} Unable to punch: {}", req.request_id(), e);
                     req.set_flash_message(format!("{}", e));
                 }
-                Ok(_) => {}
+                Ok(_) => {
+                    metrics.record_punch(direction);
+                    return Ok(req.redirect("/?punched"));
+                }
             };
-            Ok(HttpResponse::Found().header("location", "/").finish())
+            Ok(req.redirect("/"))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct TogglePunchForm {
+    note: Option<String>,
+    csrf_token: String,
+}
+
+/// Punch in or out, whichever is due.
+fn toggle_punch(
+    (mut req, state, params): (
+        HttpRequest<AppState>,
+        State<AppState>,
+        Form<TogglePunchForm>,
+    ),
+) -> FutureResponse<HttpResponse> {
+    let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    let metrics = state.metrics.clone();
+    state
+        .db
+        .send(TogglePunch {
+            username: req.identity().unwrap_or("".to_string()),
+            note: form.note,
+        })
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(direction) => {
+                metrics.record_punch(direction);
+                Ok(req.redirect("/?punched"))
+            }
+            Err(e) => {
+                error!("request_id={} Unable to punch: {}", req.request_id(), e);
+                req.set_flash_message(format!("{}", e));
+                Ok(req.redirect("/"))
+            }
         })
         .responder()
 }
+
+/// Revert a punch which was made by mistake.  Only a punch made within the last few minutes can be
+/// undone.
+fn undo_punch(
+    (mut req, state, params): (HttpRequest<AppState>, State<AppState>, Form<CsrfForm>),
+) -> FutureResponse<HttpResponse> {
+    if !req.verify_csrf_token(&params.csrf_token) {
+        return csrf_failure();
+    }
+    state
+        .db
+        .send(UndoLastPunch {
+            username: req.identity().unwrap_or("".to_string()),
+        })
+        .from_err()
+        .and_then(move |res| {
+            if let Err(e) = res {
+                req.set_flash_message(format!("{}", e));
+            }
+            Ok(req.redirect("/"))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct TimeDisplayForm {
+    time_display: TimeDisplay,
+    csrf_token: String,
+}
+
+/// Remember whether the user wants to see gross time, net time, or both on the dashboard.
+fn set_time_display(
+    (mut req, state, params): (
+        HttpRequest<AppState>,
+        State<AppState>,
+        Form<TimeDisplayForm>,
+    ),
+) -> FutureResponse<HttpResponse> {
+    let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    state
+        .db
+        .send(SetTimeDisplay {
+            username: req.identity().unwrap_or("".to_string()),
+            time_display: form.time_display,
+        })
+        .from_err()
+        .and_then(move |res| {
+            if let Err(e) = res {
+                req.set_flash_message(format!("{}", e));
+            }
+            Ok(req.redirect("/"))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct DurationFormatForm {
+    duration_format: DurationFormat,
+    csrf_token: String,
+}
+
+/// Remember whether the user wants durations written as hours and minutes or as decimal hours.
+fn set_duration_format(
+    (mut req, state, params): (
+        HttpRequest<AppState>,
+        State<AppState>,
+        Form<DurationFormatForm>,
+    ),
+) -> FutureResponse<HttpResponse> {
+    let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    state
+        .db
+        .send(SetDurationFormat {
+            username: req.identity().unwrap_or("".to_string()),
+            duration_format: form.duration_format,
+        })
+        .from_err()
+        .and_then(move |res| {
+            if let Err(e) = res {
+                req.set_flash_message(format!("{}", e));
+            }
+            Ok(req.redirect("/"))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct EditEventForm {
+    id: i64,
+    clock: String,
+    note: String,
+    csrf_token: String,
+}
+
+fn edit_event(
+    (mut req, state, params): (HttpRequest<AppState>, State<AppState>, Form<EditEventForm>),
+) -> FutureResponse<HttpResponse> {
+    let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    let clock = NaiveDateTime::parse_from_str(&form.clock, FORM_TIME_FORMAT)
+        .map_err(|_| DatabaseError::BadTime)
+        .map(|t| time::to_utc(&t, time::Disambiguate::Earlier));
+    let new_clock = match clock {
+        Ok(clock) => clock,
+        Err(e) => {
+            req.set_flash_message(format!("{}", e));
+            return Box::new(::futures::future::ok(req.redirect("/")));
+        }
+    };
+    state
+        .db
+        .send(EditEvent {
+            username: req.identity().unwrap_or("".to_string()),
+            id: form.id,
+            new_clock: Some(new_clock),
+            new_note: Some(form.note),
+        })
+        .from_err()
+        .and_then(move |res| {
+            if let Err(e) = res {
+                req.set_flash_message(format!("{}", e));
+            }
+            Ok(req.redirect("/"))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct DeleteEventForm {
+    id: i64,
+    csrf_token: String,
+}
+
+fn delete_event(
+    (mut req, state, params): (
+        HttpRequest<AppState>,
+        State<AppState>,
+        Form<DeleteEventForm>,
+    ),
+) -> FutureResponse<HttpResponse> {
+    let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    state
+        .db
+        .send(DeleteEvent {
+            username: req.identity().unwrap_or("".to_string()),
+            id: form.id,
+            force: false,
+        })
+        .from_err()
+        .and_then(move |res| {
+            if let Err(e) = res {
+                req.set_flash_message(format!("{}", e));
+            }
+            Ok(req.redirect("/"))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct EventsQuery {
+    before: Option<i64>,
+    limit: Option<usize>,
+    since: Option<DateTime<Utc>>,
+}
+
+/// Show a page of the event history, starting after the event given by the "before" parameter.
+fn events_get(
+    (request, state, query): (HttpRequest<AppState>, State<AppState>, Query<EventsQuery>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(GetEvents {
+            username: request.identity().unwrap_or("".to_string()),
+            before_id: query.before,
+            limit: EVENTS_PAGE_SIZE,
+        })
+        .from_err()
+        .and_then(move |res| {
+            let (page, error_message) = match res {
+                Ok(page) => (Some(page), None),
+                Err(e) => (None, Some(format!("{}", e))),
+            };
+            Ok(render_html(EventsTemplate {
+                base_path: request.base_path(),
+                username: &request.identity().unwrap_or("".to_string()),
+                error_message,
+                page,
+            }))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct NotesQuery {
+    before: Option<i64>,
+    limit: Option<usize>,
+}
+
+/// Show a page of the notes, as a running work journal, starting after the note given by the
+/// "before" parameter.
+fn notes_get(
+    (request, state, query): (HttpRequest<AppState>, State<AppState>, Query<NotesQuery>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(GetNotes {
+            username: request.identity().unwrap_or("".to_string()),
+            before_id: query.before,
+            limit: EVENTS_PAGE_SIZE,
+        })
+        .from_err()
+        .and_then(move |res| {
+            let (page, error_message) = match res {
+                Ok(page) => (Some(page), None),
+                Err(e) => (None, Some(format!("{}", e))),
+            };
+            Ok(render_html(NotesTemplate {
+                base_path: request.base_path(),
+                username: &request.identity().unwrap_or("".to_string()),
+                error_message,
+                page,
+            }))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchQuery {
+    q: Option<String>,
+}
+
+/// Search the notes of events for the text given by the "q" parameter.
+fn search_get(
+    (request, state, query): (HttpRequest<AppState>, State<AppState>, Query<SearchQuery>),
+) -> FutureResponse<HttpResponse> {
+    let query = query.into_inner().q.unwrap_or_default();
+    let username = request.identity().unwrap_or("".to_string());
+    if query.trim().is_empty() {
+        return Box::new(future::ok(render_html(SearchTemplate {
+            base_path: request.base_path(),
+            username: &username,
+            error_message: None,
+            query: "",
+            results: None,
+        })));
+    }
+    state
+        .db
+        .send(SearchNotes {
+            username: username.clone(),
+            query: query.clone(),
+        })
+        .from_err()
+        .and_then(move |res| {
+            let (results, error_message) = match res {
+                Ok(results) => (Some(results), None),
+                Err(e) => (None, Some(format!("{}", e))),
+            };
+            Ok(render_html(SearchTemplate {
+                base_path: request.base_path(),
+                username: &username,
+                error_message,
+                query: &query,
+                results,
+            }))
+        })
+        .responder()
+}
+
+/// Show the work sessions of the day given in the path.
+fn day_get(
+    (request, state, date): (HttpRequest<AppState>, State<AppState>, Path<String>),
+) -> FutureResponse<HttpResponse> {
+    let date = match NaiveDate::parse_from_str(&date, DAY_PATH_FORMAT) {
+        Ok(date) => date,
+        Err(_) => return Box::new(future::ok(not_found(&request))),
+    };
+    let username = request.identity().unwrap_or("".to_string());
+    state
+        .db
+        .send(GetDaySessions {
+            username: username.clone(),
+            date,
+        })
+        .join(state.db.send(GetDurationFormat {
+            username: username.clone(),
+        }))
+        .from_err()
+        .and_then(move |(res, duration_format)| {
+            let (sessions, error_message) = match res {
+                Ok(sessions) => (sessions, None),
+                Err(e) => (vec![], Some(format!("{}", e))),
+            };
+            let mut total = WorkTime::new();
+            for session in sessions.iter().filter(|session| !session.too_short) {
+                total += session.work_time;
+            }
+            Ok(render_html(DayTemplate {
+                base_path: request.base_path(),
+                username: &username,
+                error_message,
+                date,
+                sessions,
+                total,
+                duration_format: duration_format.unwrap_or(DurationFormat::HoursMinutes),
+            }))
+        })
+        .responder()
+}
+
+/// Show the time worked this week on each of the user's projects, beneath a banner with the
+/// lifetime totals.
+fn projects_get(
+    (request, state): (HttpRequest<AppState>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    let username = request.identity().unwrap_or("".to_string());
+    state
+        .db
+        .send(GetAllProjectsSummary {
+            username: username.clone(),
+        })
+        .join3(
+            state.db.send(GetLifetimeStats {
+                username: username.clone(),
+            }),
+            state.db.send(GetDurationFormat {
+                username: username.clone(),
+            }),
+        )
+        .from_err()
+        .and_then(move |(res, stats, duration_format)| {
+            let (projects, error_message) = match res {
+                Ok(projects) => (projects, None),
+                Err(e) => (vec![], Some(format!("{}", e))),
+            };
+            Ok(render_html(ProjectsTemplate {
+                base_path: request.base_path(),
+                username: &username,
+                error_message,
+                projects,
+                stats: stats.ok().filter(|stats| stats.first_punch.is_some()),
+                duration_format: duration_format.unwrap_or(DurationFormat::HoursMinutes),
+            }))
+        })
+        .responder()
+}
+
+/// List the users, with forms for creating users and changing their roles, and for renaming the
+/// administrator's project.
+fn admin_users_get(
+    (request, state): (HttpRequest<AppState>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    let username = request.identity().unwrap_or("".to_string());
+    state
+        .db
+        .send(ListUsers {})
+        .join(state.db.send(GetProject {
+            username: username.clone(),
+        }))
+        .from_err()
+        .and_then(move |(res, project)| {
+            let (users, error_message) = match res {
+                Ok(users) => (users, request.get_flash_message()),
+                Err(e) => (vec![], Some(format!("{}", e))),
+            };
+            Ok(render_html(AdminUsersTemplate {
+                base_path: request.base_path(),
+                username: &username,
+                error_message,
+                users,
+                roles: &Role::ALL,
+                project: project.ok(),
+                csrf_token: request.csrf_token(),
+            }))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct CreateUserForm {
+    name: String,
+    password: String,
+    role: Role,
+    csrf_token: String,
+}
+
+fn admin_create_user(
+    (mut req, state, params): (HttpRequest<AppState>, State<AppState>, Form<CreateUserForm>),
+) -> FutureResponse<HttpResponse> {
+    let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    state
+        .db
+        .send(CreateUser {
+            name: form.name,
+            password: form.password,
+            role: form.role,
+        })
+        .from_err()
+        .and_then(move |res| {
+            if let Err(e) = res {
+                req.set_flash_message(format!("{}", e));
+            }
+            Ok(req.redirect(ADMIN_USERS_PATH))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct SetRoleForm {
+    id: i64,
+    role: Role,
+    csrf_token: String,
+}
+
+fn admin_set_role(
+    (mut req, state, params): (HttpRequest<AppState>, State<AppState>, Form<SetRoleForm>),
+) -> FutureResponse<HttpResponse> {
+    let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    state
+        .db
+        .send(SetRole {
+            username: req.identity().unwrap_or("".to_string()),
+            user_id: form.id,
+            role: form.role,
+        })
+        .from_err()
+        .and_then(move |res| {
+            if let Err(e) = res {
+                req.set_flash_message(format!("{}", e));
+            }
+            Ok(req.redirect(ADMIN_USERS_PATH))
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+struct RenameProjectForm {
+    id: i64,
+    name: String,
+    csrf_token: String,
+}
+
+fn admin_rename_project(
+    (mut req, state, params): (
+        HttpRequest<AppState>,
+        State<AppState>,
+        Form<RenameProjectForm>,
+    ),
+) -> FutureResponse<HttpResponse> {
+    let form = params.into_inner();
+    if !req.verify_csrf_token(&form.csrf_token) {
+        return csrf_failure();
+    }
+    state
+        .db
+        .send(RenameProject {
+            project_id: form.id,
+            new_name: form.name,
+        })
+        .from_err()
+        .and_then(move |res| {
+            if let Err(e) = res {
+                req.set_flash_message(format!("{}", e));
+            }
+            Ok(req.redirect(ADMIN_USERS_PATH))
+        })
+        .responder()
+}
+
+/// Download a consistent snapshot of the whole database, for backups.
+fn admin_backup_get(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(BackupDatabase {})
+        .from_err()
+        .and_then(|res| match res {
+            Ok(data) => Ok(HttpResponse::Ok()
+                .content_type("application/vnd.sqlite3")
+                .header(
+                    "content-disposition",
+                    "attachment; filename=punch-backup.db",
+                )
+                .body(data)),
+            Err(e) => Ok(HttpResponse::InternalServerError()
+                .content_type("text/plain")
+                .body(format!("{}", e))),
+        })
+        .responder()
+}
+
+/// Respond to a path which matches no resource.  API clients get a JSON error, and browsers get
+/// a page with a link back to the dashboard.
+fn not_found(req: &HttpRequest<AppState>) -> HttpResponse {
+    if req.route_path().is_empty() {
+        // The base path itself, without a trailing slash, is the dashboard.
+        return req.redirect(ROOT_PATH);
+    }
+    if req.route_path().starts_with(API_PATH) {
+        return HttpResponse::NotFound().json(ApiError::new("NOT_FOUND", "Not found"));
+    }
+    match (NotFoundTemplate {
+        base_path: req.base_path(),
+        path: req.path(),
+    })
+    .render()
+    {
+        Ok(s) => HttpResponse::NotFound().content_type("text/html").body(s),
+        Err(e) => {
+            error!("{}", TemplateError(e));
+            HttpResponse::NotFound().into()
+        }
+    }
+}
+
+/// The body of an API response describing a failure.
+#[derive(Serialize, Deserialize)]
+pub struct ApiError {
+    pub error: String,
+    /// A stable name for the kind of failure, such as "BAD_STATE".
+    pub code: String,
+}
+
+impl ApiError {
+    fn new<E: fmt::Display>(code: &str, error: E) -> ApiError {
+        ApiError {
+            error: error.to_string(),
+            code: code.to_string(),
+        }
+    }
+}
+
+/// API handlers pass database errors on to actix-web, which responds with the status suited to the
+/// kind of error, and the error described in an `ApiError` body.
+impl ResponseError for DatabaseError {
+    fn error_response(&self) -> HttpResponse {
+        let status = match self {
+            DatabaseError::BadState
+            | DatabaseError::OrderingConflict(..)
+            | DatabaseError::DanglingInterval(..)
+            | DatabaseError::ReplacementOrdering(..)
+            | DatabaseError::UserExists(..)
+            | DatabaseError::ProjectExists(..)
+            | DatabaseError::NothingToUndo
+            | DatabaseError::UndoExpired(..)
+            | DatabaseError::AlreadyInitialized => StatusCode::CONFLICT,
+            DatabaseError::BadProject | DatabaseError::BadEvent | DatabaseError::BadUser => {
+                StatusCode::NOT_FOUND
+            }
+            DatabaseError::BadTime
+            | DatabaseError::TimeRegression(..)
+            | DatabaseError::FuturePunch
+            | DatabaseError::NotOnDay(..)
+            | DatabaseError::BadVacation
+            | DatabaseError::BadBreak
+            | DatabaseError::NoteInReplacement
+            | DatabaseError::WeakPassword(..)
+            | DatabaseError::BadProjectName(..)
+            | DatabaseError::BadBcryptCost(..)
+            | DatabaseError::BadTestData(..)
+            | DatabaseError::ImportRow(..) => StatusCode::UNPROCESSABLE_ENTITY,
+            DatabaseError::NoteTooLong(..)
+            | DatabaseError::BadMaxNoteLength
+            | DatabaseError::BadFlashMaxAge
+            | DatabaseError::BadDefaultOverhead => StatusCode::BAD_REQUEST,
+            DatabaseError::OwnAdminAccess => StatusCode::FORBIDDEN,
+            DatabaseError::Diesel(..)
+            | DatabaseError::Pool(..)
+            | DatabaseError::Password(..)
+            | DatabaseError::BadPoolSize(..)
+            | DatabaseError::Backup(..)
+            | DatabaseError::DuplicateUsernames(..)
+            | DatabaseError::NotInitialized => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        HttpResponse::build(status).json(ApiError::new(self.code(), self))
+    }
+}
+
+/// The body of a punch request to the API.
+#[derive(Serialize, Deserialize)]
+pub struct ApiPunch {
+    pub direction: PunchDirection,
+    pub note: Option<String>,
+}
+
+/// An event given by API clients to replace the punches of a day.  The time is local.
+#[derive(Serialize, Deserialize)]
+pub struct ApiDayEvent {
+    pub event_type: EventType,
+    pub time: NaiveDateTime,
+    pub note: Option<String>,
+}
+
+/// The body of a request to replace the punches of a day.
+#[derive(Serialize, Deserialize)]
+pub struct ApiDayEvents {
+    pub events: Vec<ApiDayEvent>,
+}
+
+/// The direction of a punch just recorded, as reported to API clients.
+#[derive(Serialize, Deserialize)]
+pub struct ApiPunched {
+    pub direction: PunchDirection,
+}
+
+/// The direction of the next punch, as reported to API clients.
+#[derive(Serialize, Deserialize)]
+pub struct ApiNextDirection {
+    pub direction: PunchDirection,
+}
+
+/// Punch in or out on behalf of an API client.  Punching in the same direction twice in a row is
+/// reported as a conflict.
+fn api_punch(
+    (req, state, params): (HttpRequest<AppState>, State<AppState>, Json<ApiPunch>),
+) -> FutureResponse<HttpResponse> {
+    let ApiPunch { direction, note } = params.into_inner();
+    let metrics = state.metrics.clone();
+    state
+        .db
+        .send(PunchCommand {
+            username: req.identity().unwrap_or("".to_string()),
+            direction,
+            note,
+            at: None,
+        })
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(()) => {
+                metrics.record_punch(direction);
+                Ok(HttpResponse::NoContent().finish())
+            }
+            Err(e @ DatabaseError::BadState) => Ok(HttpResponse::Conflict()
+                .json(ApiError::new(e.code(), "You were already punched in/out."))),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// Punch in or out on behalf of an API client, whichever is due, and report the direction of the
+/// punch recorded.  Unlike `api_punch`, this can't conflict with the most recent punch.
+fn api_toggle_punch(request: HttpRequest<AppState>) -> FutureResponse<HttpResponse> {
+    let metrics = request.state().metrics.clone();
+    request
+        .state()
+        .db
+        .send(TogglePunch {
+            username: request.identity().unwrap_or("".to_string()),
+            note: None,
+        })
+        .from_err()
+        .and_then(move |res| match res {
+            Ok(direction) => {
+                metrics.record_punch(direction);
+                Ok(HttpResponse::Ok().json(ApiPunched { direction }))
+            }
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// Provide the summary report to API clients, with the same query string as the dashboard.
+fn api_report(
+    (request, state): (HttpRequest<AppState>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    let db_start = Instant::now();
+    state
+        .db
+        .send(report_request(&request))
+        .from_err()
+        .and_then(move |res| {
+            request.record_db_time(db_start.elapsed());
+            match res {
+                Ok(report) => Ok(HttpResponse::Ok().json(report)),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .responder()
+}
+
+/// The largest overhead, in minutes, which a preview report accepts.
+const MAX_PREVIEW_OVERHEAD: i32 = 24 * 60;
+
+/// Read the overhead settings to preview from the "overhead" and "overhead_mode" query parameters.
+fn preview_settings(
+    request: &HttpRequest<AppState>,
+) -> Result<(Option<i32>, Option<OverheadMode>), &'static str> {
+    let query = request.query();
+    let overhead = match query.get("overhead") {
+        Some(overhead) => match overhead.parse::<i32>() {
+            Ok(overhead) if (0..=MAX_PREVIEW_OVERHEAD).contains(&overhead) => Some(overhead),
+            _ => return Err("The overhead must be a number of minutes, no more than a day"),
+        },
+        None => None,
+    };
+    let overhead_mode = match query.get("overhead_mode").map(String::as_str) {
+        Some("per-session") => Some(OverheadMode::PerSession),
+        Some("per-day") => Some(OverheadMode::PerDay),
+        Some(_) => return Err("The overhead mode must be per-session or per-day"),
+        None => None,
+    };
+    Ok((overhead, overhead_mode))
+}
+
+/// Provide API clients with the summary report as it would be with other project settings, so
+/// that the effect of a change can be seen before making it.  The settings are given by the
+/// "overhead" and "overhead_mode" query parameters, and the report parameters are the same as for
+/// the report itself.  Nothing is stored.
+fn api_report_preview(
+    (request, state): (HttpRequest<AppState>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    let (overhead, overhead_mode) = match preview_settings(&request) {
+        Ok(settings) => settings,
+        Err(message) => {
+            return Box::new(future::ok(
+                HttpResponse::BadRequest().json(ApiError::new("BAD_REQUEST", message)),
+            ))
+        }
+    };
+    let report = report_request(&request);
+    let db_start = Instant::now();
+    state
+        .db
+        .send(PreviewReport {
+            username: report.username,
+            overhead,
+            overhead_mode,
+            weeks_in_past: report.weeks_in_past,
+            days: report.days,
+            max_events: report.max_events,
+        })
+        .from_err()
+        .and_then(move |res| {
+            request.record_db_time(db_start.elapsed());
+            match res {
+                Ok(report) => Ok(HttpResponse::Ok().json(report)),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .responder()
+}
+
+/// Provide a page of the event history to API clients.  The "next_before_id" field of the response
+/// is the cursor for requesting the following page.  Alternatively, the "since" parameter requests
+/// all of the events from the given time onward.
+fn api_events(
+    (request, state, query): (HttpRequest<AppState>, State<AppState>, Query<EventsQuery>),
+) -> FutureResponse<HttpResponse> {
+    if let Some(since) = query.since {
+        if query.before.is_some() || query.limit.is_some() {
+            return Box::new(future::ok(HttpResponse::BadRequest().json(ApiError::new(
+                "BAD_REQUEST",
+                "The since parameter can't be combined with before or limit",
+            ))));
+        }
+        return state
+            .db
+            .send(GetEventsSince {
+                username: request.identity().unwrap_or("".to_string()),
+                since: UtcDateTime(since),
+            })
+            .from_err()
+            .and_then(|res| match res {
+                Ok(events) => Ok(HttpResponse::Ok().json(events)),
+                Err(e) => Err(e.into()),
+            })
+            .responder();
+    }
+    state
+        .db
+        .send(GetEvents {
+            username: request.identity().unwrap_or("".to_string()),
+            before_id: query.before,
+            limit: query
+                .limit
+                .unwrap_or(EVENTS_PAGE_SIZE)
+                .min(MAX_EVENTS_PAGE_SIZE),
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(page) => Ok(HttpResponse::Ok().json(page)),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// Provide a page of the notes to API clients, with the same cursor as `api_events`.
+fn api_notes(
+    (request, state, query): (HttpRequest<AppState>, State<AppState>, Query<NotesQuery>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(GetNotes {
+            username: request.identity().unwrap_or("".to_string()),
+            before_id: query.before,
+            limit: query
+                .limit
+                .unwrap_or(EVENTS_PAGE_SIZE)
+                .min(MAX_EVENTS_PAGE_SIZE),
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(page) => Ok(HttpResponse::Ok().json(page)),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// Search the notes of events on behalf of API clients.
+fn api_search(
+    (request, state, query): (HttpRequest<AppState>, State<AppState>, Query<SearchQuery>),
+) -> FutureResponse<HttpResponse> {
+    let query = match query.into_inner().q {
+        Some(ref q) if !q.trim().is_empty() => q.clone(),
+        _ => {
+            return Box::new(future::ok(
+                HttpResponse::BadRequest()
+                    .json(ApiError::new("BAD_REQUEST", "Missing search query")),
+            ))
+        }
+    };
+    state
+        .db
+        .send(SearchNotes {
+            username: request.identity().unwrap_or("".to_string()),
+            query,
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(results) => Ok(HttpResponse::Ok().json(results)),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// Tell API clients whether the user is punched in, and how much time has been logged today.
+fn api_status(request: HttpRequest<AppState>) -> FutureResponse<HttpResponse> {
+    request
+        .state()
+        .db
+        .send(GetDayStatus {
+            username: request.identity().unwrap_or("".to_string()),
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(status) => Ok(HttpResponse::Ok().json(status)),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// The time worked this week on one of the user's projects, as given to API clients.
+#[derive(Serialize)]
+struct ApiProjectSummary {
+    id: i64,
+    name: String,
+    work_time: WorkTime,
+}
+
+/// Give API clients the time worked this week on each of the user's projects.
+fn api_projects_summary(request: HttpRequest<AppState>) -> FutureResponse<HttpResponse> {
+    request
+        .state()
+        .db
+        .send(GetAllProjectsSummary {
+            username: request.identity().unwrap_or("".to_string()),
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(projects) => Ok(HttpResponse::Ok().json(
+                projects
+                    .into_iter()
+                    .map(|(project, work_time)| ApiProjectSummary {
+                        id: project.id,
+                        name: project.name,
+                        work_time,
+                    })
+                    .collect::<Vec<_>>(),
+            )),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// Give API clients the user's totals since their first punch: the number of events and
+/// projects, the time of the first punch-in, and the work time ever logged.
+fn api_stats(request: HttpRequest<AppState>) -> FutureResponse<HttpResponse> {
+    request
+        .state()
+        .db
+        .send(GetLifetimeStats {
+            username: request.identity().unwrap_or("".to_string()),
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(stats) => Ok(HttpResponse::Ok().json(stats)),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// List the work sessions of the day given in the path on behalf of API clients.
+fn api_day(
+    (request, state, date): (HttpRequest<AppState>, State<AppState>, Path<String>),
+) -> FutureResponse<HttpResponse> {
+    let date = match NaiveDate::parse_from_str(&date, DAY_PATH_FORMAT) {
+        Ok(date) => date,
+        Err(_) => {
+            return Box::new(future::ok(HttpResponse::BadRequest().json(ApiError::new(
+                "BAD_REQUEST",
+                "Dates must be given as YYYY-MM-DD",
+            ))))
+        }
+    };
+    state
+        .db
+        .send(GetDaySessions {
+            username: request.identity().unwrap_or("".to_string()),
+            date,
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(sessions) => Ok(HttpResponse::Ok().json(sessions)),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// Replace the punches of the day given in the path on behalf of API clients.  The replacement is
+/// all or nothing, so a set of punches which would break the in/out ordering is reported as a
+/// conflict without changing anything.
+fn api_day_put(
+    (request, state, date, params): (
+        HttpRequest<AppState>,
+        State<AppState>,
+        Path<String>,
+        Json<ApiDayEvents>,
+    ),
+) -> FutureResponse<HttpResponse> {
+    let date = match NaiveDate::parse_from_str(&date, DAY_PATH_FORMAT) {
+        Ok(date) => date,
+        Err(_) => {
+            return Box::new(future::ok(HttpResponse::BadRequest().json(ApiError::new(
+                "BAD_REQUEST",
+                "Dates must be given as YYYY-MM-DD",
+            ))))
+        }
+    };
+    state
+        .db
+        .send(ReplaceDayEvents {
+            username: request.identity().unwrap_or("".to_string()),
+            date,
+            events: params
+                .into_inner()
+                .events
+                .into_iter()
+                .map(|e| (e.event_type, e.time, e.note))
+                .collect(),
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(()) => Ok(HttpResponse::NoContent().finish()),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// Tell API clients which direction the next punch should be, without the expense of producing the
+/// summary report.
+fn api_next_direction(
+    (request, state): (HttpRequest<AppState>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(GetNextDirection {
+            username: request.identity().unwrap_or("".to_string()),
+        })
+        .from_err()
+        .and_then(|res| match res {
+            Ok(direction) => Ok(HttpResponse::Ok().json(ApiNextDirection { direction })),
+            Err(e) => Err(e.into()),
+        })
+        .responder()
+}
+
+/// A liveness probe for load balancers and reverse proxies, which confirms that the database is
+/// reachable.
+fn healthz(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(Ping {})
+        .then(|res| match res {
+            Ok(Ok(())) => Ok(HttpResponse::Ok().content_type("text/plain").body("ok")),
+            Ok(Err(e)) => {
+                error!("Health check failed: {}", e);
+                Ok(HttpResponse::ServiceUnavailable()
+                    .content_type("text/plain")
+                    .body("unavailable"))
+            }
+            Err(e) => {
+                error!("Health check failed: {}", e);
+                Ok(HttpResponse::ServiceUnavailable()
+                    .content_type("text/plain")
+                    .body("unavailable"))
+            }
+        })
+        .responder()
+}
+
+/// Expose metrics in the Prometheus text exposition format.
+fn metrics_get(state: State<AppState>) -> FutureResponse<HttpResponse> {
+    let metrics = state.metrics.clone();
+    state
+        .db
+        .send(AnySessionInProgress {})
+        .from_err()
+        .and_then(move |res| {
+            let session_in_progress = match res {
+                Ok(in_progress) => in_progress,
+                Err(e) => {
+                    error!("Unable to determine session state: {}", e);
+                    false
+                }
+            };
+            Ok(HttpResponse::Ok()
+                .content_type("text/plain; version=0.0.4")
+                .body(metrics.render(session_in_progress)))
+        })
+        .responder()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::EventIds;
+    use diesel::r2d2::ConnectionManager;
+    use diesel::SqliteConnection;
+    use password::PasswordError;
+    use std::io;
+
+    /// A connection pool error, from waiting on a pool whose only connection is taken.
+    fn pool_error() -> ::r2d2::Error {
+        let pool = ::r2d2::Pool::builder()
+            .max_size(1)
+            .connection_timeout(Duration::from_millis(1))
+            .build(ConnectionManager::<SqliteConnection>::new(":memory:"))
+            .unwrap();
+        let _connection = pool.get().unwrap();
+        match pool.get() {
+            Err(e) => e,
+            Ok(_) => panic!("the pool had a second connection"),
+        }
+    }
+
+    #[test]
+    fn database_errors_respond_with_suitable_statuses() {
+        let time = NaiveDate::from_ymd(2026, 10, 16).and_hms(9, 0, 0);
+        let cases = vec![
+            (
+                DatabaseError::Diesel(::diesel::result::Error::NotFound),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::Pool(pool_error()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::Password(PasswordError::UnknownHash),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (DatabaseError::BadState, StatusCode::CONFLICT),
+            (
+                DatabaseError::WeakPassword(8),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (DatabaseError::BadTime, StatusCode::UNPROCESSABLE_ENTITY),
+            (DatabaseError::BadProject, StatusCode::NOT_FOUND),
+            (DatabaseError::BadEvent, StatusCode::NOT_FOUND),
+            (
+                DatabaseError::OrderingConflict(EventIds(vec![1])),
+                StatusCode::CONFLICT,
+            ),
+            (
+                DatabaseError::DanglingInterval(EventIds(vec![1, 2])),
+                StatusCode::CONFLICT,
+            ),
+            (
+                DatabaseError::BadBcryptCost(4, 31),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (DatabaseError::BadVacation, StatusCode::UNPROCESSABLE_ENTITY),
+            (DatabaseError::BadBreak, StatusCode::UNPROCESSABLE_ENTITY),
+            (
+                DatabaseError::NoteInReplacement,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (DatabaseError::NoteTooLong(1000), StatusCode::BAD_REQUEST),
+            (DatabaseError::BadMaxNoteLength, StatusCode::BAD_REQUEST),
+            (DatabaseError::BadFlashMaxAge, StatusCode::BAD_REQUEST),
+            (DatabaseError::BadDefaultOverhead, StatusCode::BAD_REQUEST),
+            (
+                DatabaseError::NotOnDay(time, time.date().succ()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                DatabaseError::ReplacementOrdering(time),
+                StatusCode::CONFLICT,
+            ),
+            (
+                DatabaseError::ImportRow(2, Box::new(DatabaseError::BadTime)),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                DatabaseError::TimeRegression(UtcDateTime::now()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (DatabaseError::FuturePunch, StatusCode::UNPROCESSABLE_ENTITY),
+            (
+                DatabaseError::UserExists("alice".to_string()),
+                StatusCode::CONFLICT,
+            ),
+            (DatabaseError::BadUser, StatusCode::NOT_FOUND),
+            (
+                DatabaseError::BadProjectName(100),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                DatabaseError::ProjectExists("Project".to_string()),
+                StatusCode::CONFLICT,
+            ),
+            (DatabaseError::OwnAdminAccess, StatusCode::FORBIDDEN),
+            (DatabaseError::NothingToUndo, StatusCode::CONFLICT),
+            (DatabaseError::UndoExpired(5), StatusCode::CONFLICT),
+            (
+                DatabaseError::BadTestData("days".to_string()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                DatabaseError::BadPoolSize(1, 3),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::Backup(io::Error::other("disk full")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::DuplicateUsernames("Alice, alice".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::NotInitialized,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (DatabaseError::AlreadyInitialized, StatusCode::CONFLICT),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.error_response().status(), status, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn viewers_are_refused_changes_to_their_events() {
+        assert!(viewer_forbidden(&Method::POST, PUNCH_PATH));
+        assert!(viewer_forbidden(&Method::POST, API_TOGGLE_PUNCH_PATH));
+        assert!(viewer_forbidden(&Method::PUT, "/api/day/2026-10-16"));
+        // Viewers may still look at their events.
+        assert!(!viewer_forbidden(&Method::GET, "/api/day/2026-10-16"));
+        assert!(!viewer_forbidden(&Method::GET, EVENTS_PATH));
+        assert!(!viewer_forbidden(&Method::PUT, "/api/day"));
+        assert!(!viewer_forbidden(&Method::PUT, "/api/day/2026-10-16/extra"));
+    }
+
+    #[test]
+    fn the_cookie_vouches_only_for_json_api_changes() {
+        assert!(cookie_trusted(PUNCH_PATH, &Method::POST, ""));
+        assert!(cookie_trusted(API_STATUS_PATH, &Method::GET, ""));
+        assert!(cookie_trusted(
+            API_TOGGLE_PUNCH_PATH,
+            &Method::POST,
+            "application/json"
+        ));
+        // Forms from other sites can post these content types.
+        assert!(!cookie_trusted(API_TOGGLE_PUNCH_PATH, &Method::POST, ""));
+        assert!(!cookie_trusted(
+            API_TOGGLE_PUNCH_PATH,
+            &Method::POST,
+            "text/plain"
+        ));
+        assert!(!cookie_trusted(
+            "/api/day/2026-10-16",
+            &Method::PUT,
+            "application/x-www-form-urlencoded"
+        ));
+    }
+}
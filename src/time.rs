@@ -1,9 +1,20 @@
 use std::collections::BTreeMap;
 use std::fmt;
+use std::io::Write;
 
-use chrono::{Duration, IsoWeek, Local, NaiveDateTime, TimeZone};
+use chrono::{
+    DateTime, Datelike, Duration, IsoWeek, Local, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc,
+    Weekday,
+};
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Timestamp;
+use diesel::sqlite::Sqlite;
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use db::DatabaseError;
+use models::DurationFormat;
 
 /// A newtype for displaying durations in our desired format, so this data can be easily rendered
 /// in Askama templates.
@@ -12,10 +23,98 @@ pub struct Elapsed(pub Duration);
 impl fmt::Display for Elapsed {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         const MINUTES_IN_HOUR: i64 = 60;
+        // A negative duration (from clock skew or a bad edit) gets a single leading sign, rather than
+        // a sign on each component.
         let t = self.0.num_minutes();
+        let sign = if t < 0 { "-" } else { "" };
+        let t = t.abs();
         let h = t / MINUTES_IN_HOUR;
         let m = t % MINUTES_IN_HOUR;
-        write!(f, "{:.2}h{:.2}m", h, m)
+        // Only the minutes are padded, so that they line up.  Hours can run to any number of digits.
+        write!(f, "{}{}h{:02}m", sign, h, m)
+    }
+}
+impl Elapsed {
+    /// Format as an ISO 8601 duration, such as "PT2H15M".  Zero components are left out, except
+    /// that a zero duration is "PT0S".  Negative durations have a leading minus sign.
+    pub fn to_iso8601(self) -> String {
+        let total = self.0.num_seconds();
+        let sign = if total < 0 { "-" } else { "" };
+        let total = total.abs();
+        let (h, m, s) = (total / 3600, total / 60 % 60, total % 60);
+        let mut iso = format!("{}PT", sign);
+        if h > 0 {
+            iso.push_str(&format!("{}H", h));
+        }
+        if m > 0 {
+            iso.push_str(&format!("{}M", m));
+        }
+        if s > 0 || total == 0 {
+            iso.push_str(&format!("{}S", s));
+        }
+        iso
+    }
+
+    /// Parse an ISO 8601 duration made up of whole days, hours, minutes, and seconds, such as
+    /// "PT2H15M" or "P1DT30S".  Years, months, and weeks are not supported, since their length
+    /// varies.
+    pub fn parse_iso8601(iso: &str) -> Option<Elapsed> {
+        let (negative, iso) = match iso.strip_prefix('-') {
+            Some(iso) => (true, iso),
+            None => (false, iso),
+        };
+        if !iso.starts_with('P') || iso.len() < 3 || iso.ends_with('T') {
+            return None;
+        }
+        let mut seconds: i64 = 0;
+        let mut number = String::new();
+        let mut in_time = false;
+        let mut last_unit = 0;
+        for c in iso[1..].chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+            // Units must appear in order, each at most once, with a number before each.
+            let (unit, scale) = match (c, in_time) {
+                ('T', false) if number.is_empty() => {
+                    in_time = true;
+                    continue;
+                }
+                ('D', false) => (1, 86_400),
+                ('H', true) => (2, 3600),
+                ('M', true) => (3, 60),
+                ('S', true) => (4, 1),
+                _ => return None,
+            };
+            if unit <= last_unit || number.is_empty() {
+                return None;
+            }
+            let value: i64 = number.parse().ok()?;
+            seconds = seconds.checked_add(value.checked_mul(scale)?)?;
+            number.clear();
+            last_unit = unit;
+        }
+        // Durations are limited to fewer seconds than an i64 holds, and panic beyond that.
+        if !number.is_empty() || last_unit == 0 || seconds > Duration::max_value().num_seconds() {
+            return None;
+        }
+        let duration = Duration::seconds(seconds);
+        Some(Elapsed(if negative { -duration } else { duration }))
+    }
+}
+/// API clients get durations in the ISO 8601 format, which is much easier to parse than our
+/// display format.
+impl Serialize for Elapsed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_iso8601())
+    }
+}
+impl<'de> Deserialize<'de> for Elapsed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Elapsed, D::Error> {
+        let iso = String::deserialize(deserializer)?;
+        Elapsed::parse_iso8601(&iso)
+            .ok_or_else(|| de::Error::custom(format!("invalid ISO 8601 duration: {}", iso)))
     }
 }
 impl ::std::ops::Add for Elapsed {
@@ -35,43 +134,263 @@ impl<'a> ::std::ops::AddAssign<&'a Elapsed> for Elapsed {
     }
 }
 
-/// A newtype for displaying weeks in our desired format, so this data can be easily rendered in
-/// Askama templates.
-pub struct Week(pub IsoWeek);
+/// A duration written in a chosen format.  `Elapsed` always displays as hours and minutes, since
+/// it can't see the user's preference, so the templates and the text report go through this.
+pub struct FormattedElapsed(pub Elapsed, pub DurationFormat);
+impl fmt::Display for FormattedElapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.1 {
+            DurationFormat::HoursMinutes => self.0.fmt(f),
+            DurationFormat::DecimalHours => {
+                write!(f, "{:.2}h", (self.0).0.num_minutes() as f64 / 60.0)
+            }
+        }
+    }
+}
+
+/// A week beginning on a configurable day, used as a key for allocating work time to weeks.  It is
+/// displayed in ISO week format, so that this data can be easily rendered in Askama templates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Week {
+    pub first_day: NaiveDate,
+}
+impl Week {
+    /// The week containing the given date, for weeks beginning on the given day.
+    pub fn containing(date: &NaiveDate, start: Weekday) -> Week {
+        let days_into_week =
+            (date.weekday().num_days_from_monday() + 7 - start.num_days_from_monday()) % 7;
+        Week {
+            first_day: *date - Duration::days(days_into_week as i64),
+        }
+    }
+
+    /// The following week.
+    pub fn succ(&self) -> Week {
+        Week {
+            first_day: self.first_day + Duration::weeks(1),
+        }
+    }
+
+    /// The ISO week which this week most overlaps.  For weeks beginning on Monday, this is the
+    /// same week.
+    pub fn iso_week(&self) -> IsoWeek {
+        (self.first_day + Duration::days(3)).iso_week()
+    }
+}
 impl fmt::Display for Week {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.0)
+        write!(f, "{:?}", self.iso_week())
     }
 }
+impl Serialize for Week {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A calendar month, used as a key for allocating work time to months.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct YearMonth {
+    pub year: i32,
+    pub month: u32,
+}
+impl YearMonth {
+    /// The month containing the given date.
+    pub fn from_date(date: &NaiveDate) -> YearMonth {
+        YearMonth {
+            year: date.year(),
+            month: date.month(),
+        }
+    }
+
+    /// The first day of this month.
+    pub fn first_day(&self) -> NaiveDate {
+        NaiveDate::from_ymd(self.year, self.month, 1)
+    }
+
+    /// The following month.
+    pub fn succ(&self) -> YearMonth {
+        if self.month == 12 {
+            YearMonth {
+                year: self.year + 1,
+                month: 1,
+            }
+        } else {
+            YearMonth {
+                year: self.year,
+                month: self.month + 1,
+            }
+        }
+    }
 
-/// Convert a NaiveDateTime in UTC to a NaiveDateTime in the local time zone.
-/// This is less than ideal.  See the comments in the Event struct.
-pub fn to_utc(local_datetime: &NaiveDateTime) -> Result<NaiveDateTime, DatabaseError> {
-    use chrono::offset::LocalResult;
-    match Local.from_local_datetime(local_datetime) {
-        LocalResult::None => Err(DatabaseError::BadTime),
-        LocalResult::Single(t) => Ok(t),
-        LocalResult::Ambiguous(_, _) => Err(DatabaseError::BadTime),
-    }.map(|t| t.naive_utc())
+    /// The preceding month.
+    pub fn pred(&self) -> YearMonth {
+        if self.month == 1 {
+            YearMonth {
+                year: self.year - 1,
+                month: 12,
+            }
+        } else {
+            YearMonth {
+                year: self.year,
+                month: self.month - 1,
+            }
+        }
+    }
 }
 
-/// Convert a NaiveDateTime in the local time zone to a NaiveDateTime in UTC.
-/// This is less than ideal.  See the comments in the Event struct.
-pub fn to_local(utc_datetime: &NaiveDateTime) -> NaiveDateTime {
-    Local.from_utc_datetime(utc_datetime).naive_local()
+/// A newtype for displaying months in our desired format, so this data can be easily rendered in
+/// Askama templates.
+pub struct Month(pub YearMonth);
+impl fmt::Display for Month {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{:02}", self.0.year, self.0.month)
+    }
+}
+impl Serialize for Month {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
-/// Represent an amount of work time in both gross and net forms.
-#[derive(Clone, Copy, Debug)]
+// Diesel's derives nest their trait impls inside a named constant, which newer compilers warn
+// about.  The lint can only be silenced on an enclosing item, hence this small module.
+#[allow(non_local_definitions)]
+mod utc {
+    use chrono::{DateTime, Utc};
+    use diesel::sql_types::Timestamp;
+
+    /// A UTC timestamp as stored in the database.  SQLite has no notion of time zones, so the
+    /// database column holds a bare date/time which is UTC by convention.  This wrapper performs
+    /// the conversion to and from a `DateTime<Utc>` at the Diesel boundary, so the rest of the code
+    /// never has to wonder which zone a value is in.
+    #[derive(
+        AsExpression, FromSqlRow, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+    )]
+    #[sql_type = "Timestamp"]
+    pub struct UtcDateTime(pub DateTime<Utc>);
+}
+pub use self::utc::UtcDateTime;
+impl UtcDateTime {
+    /// The current time.
+    pub fn now() -> UtcDateTime {
+        UtcDateTime(Utc::now())
+    }
+
+    /// Format this time in the local time zone.
+    pub fn format_local(&self, format: &str) -> String {
+        to_local(self).format(format).to_string()
+    }
+
+    /// Format this time in the local time zone, in the style of `format_friendly`.
+    pub fn format_friendly(&self) -> String {
+        format_friendly(&to_local(self))
+    }
+}
+impl FromSql<Timestamp, Sqlite> for UtcDateTime {
+    fn from_sql(value: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        let naive = <NaiveDateTime as FromSql<Timestamp, Sqlite>>::from_sql(value)?;
+        Ok(UtcDateTime(DateTime::from_utc(naive, Utc)))
+    }
+}
+impl ToSql<Timestamp, Sqlite> for UtcDateTime {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        ToSql::<Timestamp, Sqlite>::to_sql(&self.0.naive_utc(), out)
+    }
+}
+impl fmt::Display for UtcDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How to convert a local time which doesn't correspond to exactly one UTC time.  When clocks fall
+/// back, an hour of local times occurs twice, and either occurrence may be chosen.  When clocks
+/// spring forward, an hour of local times never occurs at all, and such times are pushed forward out
+/// of the gap by the daylight saving offset, so that 2:30am becomes 3:30am.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Disambiguate {
+    /// Choose the earlier of two occurrences of a local time.
+    Earlier,
+    /// Choose the later of two occurrences of a local time.
+    Later,
+}
+
+/// Convert a NaiveDateTime in the local time zone to a UTC timestamp, resolving daylight saving
+/// transitions as described by the policy.  The report pipeline always uses `Disambiguate::Earlier`
+/// for the midnight at the start of a day, so the day includes all of its events.
+/// We currently assume the server's local time zone is the user's preferred time zone.  See the
+/// comments in the Event struct.
+pub fn to_utc(local_datetime: &NaiveDateTime, policy: Disambiguate) -> UtcDateTime {
+    let offset_at =
+        |utc: &NaiveDateTime| Local.offset_from_utc_datetime(utc).fix().local_minus_utc() as i64;
+    let utc = resolve_local(local_datetime, policy, offset_at);
+    UtcDateTime(DateTime::from_utc(utc, Utc))
+}
+
+/// Convert a local time to UTC as `to_utc` does, in the time zone whose offset from UTC in seconds
+/// at any UTC time is given by `offset_at`.
+fn resolve_local<F>(
+    local_datetime: &NaiveDateTime,
+    policy: Disambiguate,
+    offset_at: F,
+) -> NaiveDateTime
+where
+    F: Fn(&NaiveDateTime) -> i64,
+{
+    // Rather than trust the platform to report transitions, try the offsets in effect a day before
+    // and a day after, and keep those which actually apply at the resulting time.
+    let before = offset_at(&(*local_datetime - Duration::days(1)));
+    let after = offset_at(&(*local_datetime + Duration::days(1)));
+    let candidate = |offset: i64| {
+        let utc = *local_datetime - Duration::seconds(offset);
+        if offset_at(&utc) == offset {
+            Some(utc)
+        } else {
+            None
+        }
+    };
+    match (candidate(before), candidate(after)) {
+        (Some(a), Some(b)) => match policy {
+            Disambiguate::Earlier => a.min(b),
+            Disambiguate::Later => a.max(b),
+        },
+        (Some(t), None) | (None, Some(t)) => t,
+        // The time falls in a gap, so interpret it using the offset from before the gap, which
+        // lands it the same distance past the end of the gap.
+        (None, None) => *local_datetime - Duration::seconds(before),
+    }
+}
+
+/// Convert a UTC timestamp to a NaiveDateTime in the local time zone.
+/// We currently assume the server's local time zone is the user's preferred time zone.  See the
+/// comments in the Event struct.
+pub fn to_local(utc_datetime: &UtcDateTime) -> NaiveDateTime {
+    utc_datetime.0.with_timezone(&Local).naive_local()
+}
+
+/// The format of the event times shown in the recent events list, such as "Mon Jan 15 9:00AM".
+const FRIENDLY_FORMAT: &str = "%a %b %-d %-I:%M%p";
+
+/// Format a local time for the recent events list, in both the web page and the text report.
+pub fn format_friendly(local_datetime: &NaiveDateTime) -> String {
+    local_datetime.format(FRIENDLY_FORMAT).to_string()
+}
+
+/// Represent an amount of work time in both gross and net forms.  The net time is further split
+/// into regular time and overtime, with overtime being zero unless a daily threshold is in effect.
+#[derive(Serialize, Clone, Copy, Debug)]
 pub struct WorkTime {
     pub gross: Elapsed,
     pub net: Elapsed,
+    pub overtime: Elapsed,
 }
 impl WorkTime {
     pub fn new() -> WorkTime {
         WorkTime {
             gross: Elapsed(Duration::zero()),
             net: Elapsed(Duration::zero()),
+            overtime: Elapsed(Duration::zero()),
         }
     }
     pub fn from_duration(gross: Duration, overhead: Duration) -> WorkTime {
@@ -83,8 +402,13 @@ impl WorkTime {
         WorkTime {
             gross: Elapsed(gross),
             net: Elapsed(net),
+            overtime: Elapsed(Duration::zero()),
         }
     }
+    /// The portion of the net time which is not overtime.
+    pub fn regular(&self) -> Elapsed {
+        Elapsed(self.net.0 - self.overtime.0)
+    }
     pub fn flatten_map<T>(map: BTreeMap<T, WorkTime>) -> Vec<(T, WorkTime)> {
         let mut elements: Vec<(T, WorkTime)> = Vec::with_capacity(map.len());
         for (t, worktime) in map {
@@ -97,26 +421,145 @@ impl ::std::ops::AddAssign for WorkTime {
     fn add_assign(&mut self, other: WorkTime) {
         self.gross = self.gross + other.gross;
         self.net = self.net + other.net;
+        self.overtime = self.overtime + other.overtime;
     }
 }
 impl<'a> ::std::ops::AddAssign<&'a WorkTime> for WorkTime {
     fn add_assign(&mut self, other: &'a WorkTime) {
         self.gross = self.gross + other.gross;
         self.net = self.net + other.net;
+        self.overtime = self.overtime + other.overtime;
     }
 }
 
 /// Represent a specific work session.
-#[derive(Debug)]
+#[derive(Serialize, Clone, Copy, Debug)]
 pub struct Interval {
     pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    /// The time taken in breaks, which doesn't count toward the gross time.
+    pub break_time: Elapsed,
     pub work_time: WorkTime,
 }
 impl Interval {
-    pub fn new(start: &NaiveDateTime, end: &NaiveDateTime, overhead: Duration) -> Interval {
+    pub fn new(
+        start: &NaiveDateTime,
+        end: &NaiveDateTime,
+        break_time: Duration,
+        overhead: Duration,
+    ) -> Interval {
+        let break_time = break_time.min(*end - *start).max(Duration::zero());
         Interval {
-            start: start.clone(),
-            work_time: WorkTime::from_duration(*end - *start, overhead),
+            start: *start,
+            end: *end,
+            break_time: Elapsed(break_time),
+            work_time: WorkTime::from_duration(*end - *start - break_time, overhead),
+        }
+    }
+
+    /// Split this session at the start of each day, giving the work time that falls on each day.
+    /// Days begin the given time after local midnight, and time before then belongs to the previous
+    /// day.  The session's breaks and overhead are each only deducted once, from the earliest part
+    /// of the session.
+    pub fn split_by_day(&self, day_start: Duration) -> Vec<(NaiveDate, WorkTime)> {
+        let mut break_time = self.break_time.0;
+        let mut overhead = self.work_time.gross.0 - self.work_time.net.0;
+        let mut days = Vec::new();
+        let mut start = self.start;
+        while start < self.end {
+            let day = (start - day_start).date();
+            let end = self.end.min(day.succ().and_hms(0, 0, 0) + day_start);
+            let day_break = break_time.min(end - start);
+            break_time = break_time - day_break;
+            let work_time = WorkTime::from_duration(end - start - day_break, overhead);
+            overhead = overhead - (work_time.gross.0 - work_time.net.0);
+            days.push((day, work_time));
+            start = end;
         }
+        days
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seconds in an hour, for writing offsets from UTC.
+    const HOUR: i64 = 60 * 60;
+
+    /// The offset of a zone which changes from `before` to `after` at the given UTC time.
+    fn offsets(
+        transition: NaiveDateTime,
+        before: i64,
+        after: i64,
+    ) -> impl Fn(&NaiveDateTime) -> i64 {
+        move |utc| if *utc < transition { before } else { after }
+    }
+
+    /// A time on the given day of 2026.
+    fn datetime(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2026, month, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn resolve_local_chooses_an_occurrence_of_an_ambiguous_time() {
+        // US Eastern clocks fall back from 2am to 1am on 1 November 2026, at 6am UTC.
+        let offset_at = offsets(datetime(11, 1, 6, 0), -4 * HOUR, -5 * HOUR);
+        let local = datetime(11, 1, 1, 30);
+        assert_eq!(
+            resolve_local(&local, Disambiguate::Earlier, &offset_at),
+            datetime(11, 1, 5, 30)
+        );
+        assert_eq!(
+            resolve_local(&local, Disambiguate::Later, &offset_at),
+            datetime(11, 1, 6, 30)
+        );
+    }
+
+    #[test]
+    fn resolve_local_pushes_a_nonexistent_time_out_of_the_gap() {
+        // US Eastern clocks spring forward from 2am to 3am on 8 March 2026, at 7am UTC.
+        let offset_at = offsets(datetime(3, 8, 7, 0), -5 * HOUR, -4 * HOUR);
+        for &policy in &[Disambiguate::Earlier, Disambiguate::Later] {
+            // 2:30am never happens, so it is taken to be 3:30am.
+            assert_eq!(
+                resolve_local(&datetime(3, 8, 2, 30), policy, &offset_at),
+                datetime(3, 8, 7, 30)
+            );
+        }
+        assert_eq!(
+            resolve_local(&datetime(3, 8, 3, 30), Disambiguate::Earlier, &offset_at),
+            datetime(3, 8, 7, 30)
+        );
+    }
+
+    /// Serialize the given duration to JSON, and deserialize it again.
+    fn round_trip(duration: Duration) -> (String, Duration) {
+        let json = ::serde_json::to_string(&Elapsed(duration)).unwrap();
+        let elapsed: Elapsed = ::serde_json::from_str(&json).unwrap();
+        (json, elapsed.0)
+    }
+
+    #[test]
+    fn elapsed_round_trips_through_json() {
+        assert_eq!(
+            round_trip(Duration::zero()),
+            ("\"PT0S\"".to_string(), Duration::zero())
+        );
+        assert_eq!(
+            round_trip(Duration::seconds(45)),
+            ("\"PT45S\"".to_string(), Duration::seconds(45))
+        );
+        let long = Duration::hours(26) + Duration::minutes(3) + Duration::seconds(9);
+        assert_eq!(round_trip(long), ("\"PT26H3M9S\"".to_string(), long));
+        // Durations too long to represent are refused rather than panicking.
+        assert!(::serde_json::from_str::<Elapsed>("\"PT9999999999999999S\"").is_err());
+        assert!(::serde_json::from_str::<Elapsed>("\"-PT9999999999999999S\"").is_err());
+    }
+
+    #[test]
+    fn elapsed_displays_hours_and_padded_minutes() {
+        assert_eq!(Elapsed(Duration::minutes(65)).to_string(), "1h05m");
+        assert_eq!(Elapsed(Duration::minutes(5)).to_string(), "0h05m");
     }
 }
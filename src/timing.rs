@@ -0,0 +1,86 @@
+use actix_web;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::{Middleware, Response, Started};
+use actix_web::{HttpRequest, HttpResponse};
+
+use std::time::{Duration, Instant};
+
+use request_id::RequestIdentifier;
+
+static SERVER_TIMING_HEADER: &str = "server-timing";
+
+/// Middleware to measure how long each request takes to handle, for performance debugging.  The
+/// total time, and the time spent waiting on the database for handlers which record it, are
+/// returned to the client in the `Server-Timing` header, which browsers show in their developer
+/// tools, and are also logged at the debug level.
+pub struct TimingService {}
+
+impl TimingService {
+    pub fn new() -> TimingService {
+        TimingService {}
+    }
+}
+
+impl<S> Middleware<S> for TimingService {
+    fn start(&self, request: &HttpRequest<S>) -> actix_web::error::Result<Started> {
+        request.extensions_mut().insert(Timing {
+            start: Instant::now(),
+            db: None,
+        });
+        Ok(Started::Done)
+    }
+
+    fn response(
+        &self,
+        request: &HttpRequest<S>,
+        mut response: HttpResponse,
+    ) -> actix_web::error::Result<Response> {
+        let (total, db) = match request.extensions().get::<Timing>() {
+            Some(timing) => (timing.start.elapsed(), timing.db),
+            None => return Ok(Response::Done(response)),
+        };
+        let mut metrics = Vec::new();
+        if let Some(db) = db {
+            metrics.push(format!("db;dur={:.1}", millis(db)));
+        }
+        metrics.push(format!("total;dur={:.1}", millis(total)));
+        debug!(
+            "request_id={} path={:?} db_ms={} total_ms={:.1}",
+            request.request_id(),
+            request.path(),
+            db.map(|db| format!("{:.1}", millis(db)))
+                .unwrap_or_else(|| "-".to_string()),
+            millis(total),
+        );
+        if let Ok(value) = HeaderValue::from_str(&metrics.join(", ")) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(SERVER_TIMING_HEADER), value);
+        }
+        Ok(Response::Done(response))
+    }
+}
+
+struct Timing {
+    start: Instant,
+    /// The time spent waiting on the database, if the handler recorded any.
+    db: Option<Duration>,
+}
+
+/// A duration in fractional milliseconds, as the `Server-Timing` header expresses them.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + f64::from(duration.subsec_nanos()) / 1_000_000.0
+}
+
+pub trait RequestTiming {
+    /// Add the time spent waiting on the database to this request's timing.
+    fn record_db_time(&self, elapsed: Duration);
+}
+
+impl<S> RequestTiming for HttpRequest<S> {
+    fn record_db_time(&self, elapsed: Duration) {
+        if let Some(timing) = self.extensions_mut().get_mut::<Timing>() {
+            timing.db = Some(timing.db.unwrap_or_default() + elapsed);
+        }
+    }
+}